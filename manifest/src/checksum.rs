@@ -0,0 +1,80 @@
+/// Hash algorithms that can be used in `WasmMetadata::hash`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Prefix used for the algorithm in hash strings
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Length of the digest in bytes
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha512 => 64,
+            HashAlgorithm::Blake3 => 32,
+        }
+    }
+}
+
+/// A hash parsed from `WasmMetadata::hash`
+///
+/// Hashes are written as `algorithm:hex`, for example `sha512:...` or `blake3:...`. A hash without
+/// a prefix is a SHA-256 digest
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Checksum {
+    pub algorithm: HashAlgorithm,
+    /// Lowercase hex encoded digest
+    pub hex: String,
+}
+
+impl std::str::FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex) = match s.split_once(':') {
+            None => (HashAlgorithm::Sha256, s),
+            Some((name, hex)) => {
+                let algorithm = [
+                    HashAlgorithm::Sha256,
+                    HashAlgorithm::Sha512,
+                    HashAlgorithm::Blake3,
+                ]
+                .into_iter()
+                .find(|x| x.name().eq_ignore_ascii_case(name));
+                match algorithm {
+                    Some(x) => (x, hex),
+                    None => return Err(format!("Unsupported hash algorithm {name:?}")),
+                }
+            }
+        };
+
+        let expected = algorithm.digest_len() * 2;
+        if hex.len() != expected || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(format!(
+                "Invalid {} hash {hex:?}, expected {expected} hex characters",
+                algorithm.name()
+            ));
+        }
+
+        Ok(Checksum {
+            algorithm,
+            hex: hex.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm.name(), self.hex)
+    }
+}
@@ -0,0 +1,72 @@
+/// Where the value of a reference in an `HttpRequest` header comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderSource {
+    /// `${env:NAME}`, the value of the environment variable `NAME`, the host decides which
+    /// variables can be used
+    Env,
+    /// `${credential:NAME}`, the value returned by the host's credential provider for `NAME`
+    Credential,
+}
+
+impl HeaderSource {
+    fn prefix(self) -> &'static str {
+        match self {
+            HeaderSource::Env => "${env:",
+            HeaderSource::Credential => "${credential:",
+        }
+    }
+}
+
+/// Replace the references in a header value using `resolve`, which is called with the source and
+/// name of each reference
+///
+/// `${env:NAME}` and `${credential:NAME}` are replaced, any other text is copied unchanged. `$${`
+/// is replaced with `${`, so a value can contain a literal reference
+pub fn expand_header(
+    value: &str,
+    mut resolve: impl FnMut(HeaderSource, &str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if let Some(x) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = x;
+            continue;
+        }
+
+        let source = [HeaderSource::Env, HeaderSource::Credential]
+            .into_iter()
+            .find(|x| rest.starts_with(x.prefix()));
+        let source = match source {
+            Some(x) => x,
+            None => {
+                out.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let reference = &rest[source.prefix().len()..];
+        let end = match reference.find('}') {
+            Some(x) => x,
+            None => return Err(format!("Unterminated reference {rest:?}")),
+        };
+
+        let name = &reference[..end];
+        if name.is_empty() {
+            return Err(format!(
+                "Empty name in {:?}",
+                &rest[..source.prefix().len() + 1]
+            ));
+        }
+
+        out.push_str(&resolve(source, name)?);
+        rest = &reference[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
@@ -1,32 +1,71 @@
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+mod checksum;
+mod header;
+mod validate;
+
+pub use checksum::{Checksum, HashAlgorithm};
+pub use header::{expand_header, HeaderSource};
+pub use validate::{Diagnostic, DiagnosticKind};
+
 #[deprecated]
 pub type ManifestMemory = MemoryOptions;
 
-#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 pub struct MemoryOptions {
     #[serde(alias = "max")]
     pub max_pages: Option<u32>,
+    /// Maximum total size of a plugin's variables in bytes, defaults to 100MB
+    #[serde(default)]
+    pub max_var_bytes: Option<u64>,
+    /// Maximum size of an HTTP response body in bytes, defaults to 50MB
+    #[serde(default)]
+    pub max_http_response_bytes: Option<u64>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 pub struct HttpRequest {
     pub url: String,
+    /// Header values can contain `${env:NAME}`, which is replaced with the environment variable
+    /// `NAME` if the host allows it, and `${credential:NAME}`, which is replaced with the value
+    /// returned by the host's credential provider, so tokens don't have to be written into the
+    /// manifest
     #[serde(default)]
     #[serde(alias = "header")]
     pub headers: std::collections::BTreeMap<String, String>,
     pub method: Option<String>,
+    /// Maximum time to download the module in milliseconds, including connecting and reading the
+    /// response. Defaults to 30 seconds
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Number of times the download is retried after a connection error, a timeout or a 408,
+    /// 429 or 5xx response. Defaults to 0, at most `HttpRequest::MAX_RETRIES`
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// Time to wait before the first retry in milliseconds, the time is doubled after each retry
+    /// up to `HttpRequest::MAX_RETRY_BACKOFF_MS`. Defaults to 500
+    #[serde(default)]
+    pub retry_backoff_ms: Option<u64>,
 }
 
 impl HttpRequest {
+    /// Maximum value of `retries`
+    pub const MAX_RETRIES: u32 = 10;
+
+    /// Maximum time to wait between retries in milliseconds
+    pub const MAX_RETRY_BACKOFF_MS: u64 = 30_000;
+
     pub fn new(url: impl Into<String>) -> HttpRequest {
         HttpRequest {
             url: url.into(),
             headers: Default::default(),
             method: None,
+            timeout_ms: None,
+            retries: None,
+            retry_backoff_ms: None,
         }
     }
 
@@ -39,13 +78,35 @@ impl HttpRequest {
         self.headers.insert(key.into(), value.into());
         self
     }
+
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> HttpRequest {
+        self.timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+
+    /// Retry failed downloads `retries` times, waiting `backoff` before the first retry and
+    /// doubling the wait after each retry
+    pub fn with_retries(mut self, retries: u32, backoff: std::time::Duration) -> HttpRequest {
+        self.retries = Some(retries);
+        self.retry_backoff_ms = Some(backoff.as_millis() as u64);
+        self
+    }
 }
 
-#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 pub struct WasmMetadata {
     pub name: Option<String>,
+    /// Hash of the WASM code, either a hex encoded SHA-256 digest or a digest prefixed with the
+    /// algorithm, for example `sha512:...` or `blake3:...`. See `Checksum`
     pub hash: Option<String>,
+    /// Base64 encoded ed25519 signature of the WASM code
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// ID of the key used to create `signature`, this is matched against the keys trusted by
+    /// the host
+    #[serde(default)]
+    pub key_id: Option<String>,
 }
 
 impl From<HttpRequest> for Wasm {
@@ -78,7 +139,7 @@ impl From<Vec<u8>> for Wasm {
 #[deprecated]
 pub type ManifestWasm = Wasm;
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum Wasm {
@@ -139,6 +200,31 @@ impl Wasm {
             Wasm::Url { req: _, meta } => meta,
         }
     }
+
+    /// Name used to link the module when the manifest contains more than one module, this is
+    /// `name` if it's set, otherwise it's based on the file name
+    pub fn module_name(&self) -> String {
+        if let Some(name) = &self.meta().name {
+            return name.clone();
+        }
+
+        match self {
+            Wasm::File { path, .. } => path
+                .with_extension("")
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            Wasm::Data { .. } => "main".to_string(),
+            Wasm::Url { req, .. } => {
+                let file_name = req.url.split('/').next_back().unwrap_or_default();
+                file_name
+                    .strip_suffix(".wasm")
+                    .or_else(|| file_name.strip_suffix(".wast"))
+                    .unwrap_or("main")
+                    .to_string()
+            }
+        }
+    }
 }
 
 #[cfg(feature = "json_schema")]
@@ -149,7 +235,103 @@ fn base64_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::
     schema.into()
 }
 
-#[derive(Default, serde::Serialize, serde::Deserialize)]
+/// An entry in `allowed_hosts`, either a host name or a rule that also restricts the scheme,
+/// port, path and methods
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum AllowedHost {
+    /// Host name, which may contain glob patterns. Any scheme, port, path and method is allowed
+    Host(String),
+    Rule(HostRule),
+}
+
+impl From<String> for AllowedHost {
+    fn from(host: String) -> Self {
+        AllowedHost::Host(host)
+    }
+}
+
+impl From<&str> for AllowedHost {
+    fn from(host: &str) -> Self {
+        AllowedHost::Host(host.to_string())
+    }
+}
+
+impl From<HostRule> for AllowedHost {
+    fn from(rule: HostRule) -> Self {
+        AllowedHost::Rule(rule)
+    }
+}
+
+impl AllowedHost {
+    /// Host name pattern
+    pub fn host(&self) -> &str {
+        match self {
+            AllowedHost::Host(host) => host,
+            AllowedHost::Rule(rule) => &rule.host,
+        }
+    }
+}
+
+/// Allows requests to `host` that match all of the other fields that are set
+///
+/// Unknown fields are rejected, since a misspelled field would otherwise allow any value
+#[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct HostRule {
+    /// Host name, which may contain glob patterns
+    pub host: String,
+    /// URL scheme, for example `https`
+    #[serde(default)]
+    pub scheme: Option<String>,
+    /// Port, if the URL doesn't include a port the default port for the scheme is used
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Path prefix, this only matches whole path segments so `/api` allows `/api` and `/api/x`
+    /// but not `/apix`
+    #[serde(default)]
+    #[serde(alias = "path")]
+    pub path_prefix: Option<String>,
+    /// HTTP methods, compared without case
+    #[serde(default)]
+    pub methods: Option<Vec<String>>,
+}
+
+impl HostRule {
+    /// Create a rule that allows any request to `host`
+    pub fn new(host: impl Into<String>) -> HostRule {
+        HostRule {
+            host: host.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> HostRule {
+        self.scheme = Some(scheme.into());
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> HostRule {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn with_path_prefix(mut self, path: impl Into<String>) -> HostRule {
+        self.path_prefix = Some(path.into());
+        self
+    }
+
+    pub fn with_method(mut self, method: impl Into<String>) -> HostRule {
+        self.methods
+            .get_or_insert_with(Vec::new)
+            .push(method.into());
+        self
+    }
+}
+
+#[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 pub struct Manifest {
     #[serde(default)]
@@ -159,11 +341,13 @@ pub struct Manifest {
     #[serde(default)]
     pub config: BTreeMap<String, String>,
     #[serde(default)]
-    pub allowed_hosts: Option<Vec<String>>,
+    pub allowed_hosts: Option<Vec<AllowedHost>>,
     #[serde(default)]
     pub allowed_paths: Option<BTreeMap<PathBuf, PathBuf>>,
     #[serde(default = "default_timeout")]
     pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub fuel_limit: Option<u64>,
 }
 
 fn default_timeout() -> Option<u64> {
@@ -192,8 +376,8 @@ impl Manifest {
         self
     }
 
-    /// Add a hostname to `allowed_hosts`
-    pub fn with_allowed_host(mut self, host: impl Into<String>) -> Self {
+    /// Add a hostname or `HostRule` to `allowed_hosts`
+    pub fn with_allowed_host(mut self, host: impl Into<AllowedHost>) -> Self {
         match &mut self.allowed_hosts {
             Some(h) => {
                 h.push(host.into());
@@ -205,8 +389,11 @@ impl Manifest {
     }
 
     /// Set `allowed_hosts`
-    pub fn with_allowed_hosts(mut self, hosts: impl Iterator<Item = String>) -> Self {
-        self.allowed_hosts = Some(hosts.collect());
+    pub fn with_allowed_hosts(
+        mut self,
+        hosts: impl Iterator<Item = impl Into<AllowedHost>>,
+    ) -> Self {
+        self.allowed_hosts = Some(hosts.map(|x| x.into()).collect());
         self
     }
 
@@ -247,6 +434,15 @@ impl Manifest {
         self.timeout_ms = Some(timeout.as_millis() as u64);
         self
     }
+
+    /// Set `fuel_limit`, the amount of fuel available to each plugin function call. Most
+    /// WebAssembly instructions consume one unit of fuel, when it runs out the call will fail
+    /// with an "out of fuel" error. Fuel is only metered by contexts that were created with fuel
+    /// enabled, loading a manifest with a limit in any other context fails.
+    pub fn with_fuel_limit(mut self, fuel: u64) -> Self {
+        self.fuel_limit = Some(fuel);
+        self
+    }
 }
 
 mod base64 {
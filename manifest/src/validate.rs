@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+
+use crate::{expand_header, AllowedHost, Checksum, HttpRequest, Manifest, Wasm};
+
+/// The kind of problem found by `Manifest::validate`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// The manifest couldn't be parsed
+    Parse,
+    /// The manifest doesn't contain any modules
+    NoWasm,
+    /// A hash uses an unsupported algorithm or has the wrong length for its algorithm
+    InvalidHash,
+    /// Two modules have the same name
+    DuplicateName,
+    /// A module file doesn't exist
+    MissingFile,
+    /// A module URL can't be parsed
+    InvalidUrl,
+    /// An `allowed_hosts` entry is empty or has an invalid path prefix
+    InvalidHost,
+    /// An `allowed_paths` source doesn't exist
+    MissingPath,
+    /// A value is out of range
+    InvalidValue,
+}
+
+/// A problem with a manifest
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// JSON pointer to the value with the problem, for example `/wasm/0/hash`
+    pub path: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, path: impl Into<String>, message: impl Into<String>) -> Self {
+        Diagnostic {
+            kind,
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Escape a key for use in a JSON pointer
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+impl Manifest {
+    /// Check the manifest for problems that would stop it from loading, every problem is
+    /// reported instead of only the first one. An empty list means the manifest is valid
+    ///
+    /// Module files and `allowed_paths` sources are checked on the local filesystem, URLs are
+    /// only parsed and not fetched
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let mut push = |kind, path: String, message: String| {
+            diagnostics.push(Diagnostic::new(kind, path, message))
+        };
+
+        if self.wasm.is_empty() {
+            push(
+                DiagnosticKind::NoWasm,
+                "/wasm".to_string(),
+                "No wasm files specified".to_string(),
+            );
+        }
+
+        // Names are only used to link modules when there is more than one
+        let mut names: BTreeMap<String, usize> = BTreeMap::new();
+        for (i, wasm) in self.wasm.iter().enumerate() {
+            let meta = wasm.meta();
+            if let Some(hash) = &meta.hash {
+                if let Err(e) = hash.parse::<Checksum>() {
+                    push(DiagnosticKind::InvalidHash, format!("/wasm/{i}/hash"), e);
+                }
+            }
+
+            if meta.signature.is_some() && meta.key_id.is_none() {
+                push(
+                    DiagnosticKind::InvalidValue,
+                    format!("/wasm/{i}/key_id"),
+                    "key_id is required when signature is set".to_string(),
+                );
+            }
+
+            match wasm {
+                Wasm::File { path, .. } => {
+                    if !path.is_file() {
+                        push(
+                            DiagnosticKind::MissingFile,
+                            format!("/wasm/{i}/path"),
+                            format!("File not found: {}", path.display()),
+                        );
+                    }
+                }
+                Wasm::Url { req, .. } => {
+                    if let Err(e) = url::Url::parse(&req.url) {
+                        push(
+                            DiagnosticKind::InvalidUrl,
+                            format!("/wasm/{i}/url"),
+                            format!("Invalid URL {:?}: {e}", req.url),
+                        );
+                    }
+
+                    if req.timeout_ms == Some(0) {
+                        push(
+                            DiagnosticKind::InvalidValue,
+                            format!("/wasm/{i}/timeout_ms"),
+                            "timeout_ms must be greater than 0".to_string(),
+                        );
+                    }
+
+                    if req.retries.is_some_and(|x| x > HttpRequest::MAX_RETRIES) {
+                        push(
+                            DiagnosticKind::InvalidValue,
+                            format!("/wasm/{i}/retries"),
+                            format!("retries must be at most {}", HttpRequest::MAX_RETRIES),
+                        );
+                    }
+
+                    if req
+                        .retry_backoff_ms
+                        .is_some_and(|x| x > HttpRequest::MAX_RETRY_BACKOFF_MS)
+                    {
+                        push(
+                            DiagnosticKind::InvalidValue,
+                            format!("/wasm/{i}/retry_backoff_ms"),
+                            format!(
+                                "retry_backoff_ms must be at most {}",
+                                HttpRequest::MAX_RETRY_BACKOFF_MS
+                            ),
+                        );
+                    }
+
+                    // References are only checked for syntax, environment variables and
+                    // credentials are resolved when the module is downloaded
+                    for (k, v) in &req.headers {
+                        if let Err(e) = expand_header(v, |_, _| Ok(String::new())) {
+                            push(
+                                DiagnosticKind::InvalidValue,
+                                format!("/wasm/{i}/headers/{}", escape(k)),
+                                format!("Invalid value for header {k}: {e}"),
+                            );
+                        }
+                    }
+                }
+                Wasm::Data { .. } => (),
+            }
+
+            if self.wasm.len() > 1 {
+                let name = wasm.module_name();
+                match names.get(&name) {
+                    Some(first) => push(
+                        DiagnosticKind::DuplicateName,
+                        if meta.name.is_some() {
+                            format!("/wasm/{i}/name")
+                        } else {
+                            format!("/wasm/{i}")
+                        },
+                        format!("Module name {name:?} is already used by /wasm/{first}"),
+                    ),
+                    None => {
+                        names.insert(name, i);
+                    }
+                }
+            }
+        }
+
+        if self.memory.max_pages == Some(0) {
+            push(
+                DiagnosticKind::InvalidValue,
+                "/memory/max_pages".to_string(),
+                "max_pages must be greater than 0".to_string(),
+            );
+        }
+
+        for (i, host) in self.allowed_hosts.iter().flatten().enumerate() {
+            let path = match host {
+                AllowedHost::Host(_) => format!("/allowed_hosts/{i}"),
+                AllowedHost::Rule(_) => format!("/allowed_hosts/{i}/host"),
+            };
+            if host.host().is_empty() {
+                push(DiagnosticKind::InvalidHost, path, "Empty host".to_string());
+            }
+
+            if let AllowedHost::Rule(rule) = host {
+                if let Some(prefix) = &rule.path_prefix {
+                    if !prefix.starts_with('/') {
+                        push(
+                            DiagnosticKind::InvalidHost,
+                            format!("/allowed_hosts/{i}/path_prefix"),
+                            format!("Path prefix {prefix:?} must start with /"),
+                        );
+                    }
+                }
+            }
+        }
+
+        for src in self.allowed_paths.iter().flat_map(|x| x.keys()) {
+            if !src.exists() {
+                push(
+                    DiagnosticKind::MissingPath,
+                    format!("/allowed_paths/{}", escape(&src.to_string_lossy())),
+                    format!("Path not found: {}", src.display()),
+                );
+            }
+        }
+
+        diagnostics
+    }
+}
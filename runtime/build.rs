@@ -9,6 +9,16 @@ fn main() {
         .rename_item("Size", "ExtismSize")
         .rename_item("PluginIndex", "ExtismPlugin")
         .rename_item("Context", "ExtismContext")
+        .rename_item("CancelHandle", "ExtismCancelHandle")
+        .rename_item("PluginPool", "ExtismPool")
+        .rename_item("ValType", "ExtismValType")
+        .rename_item("ValUnion", "ExtismValUnion")
+        .rename_item("Internal", "ExtismCurrentPlugin")
+        .rename_item("Function", "ExtismFunction")
+        .rename_item("ErrorCode", "ExtismErrorCode")
+        .rename_item("LogLevel", "ExtismLogLevel")
+        .rename_item("LogCallback", "ExtismLogCallback")
+        .rename_item("CredentialCallback", "ExtismCredentialCallback")
         .generate()
     {
         bindings.write_to_file("extism.h");
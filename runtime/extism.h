@@ -3,25 +3,360 @@
 #include <stdint.h>
 #include <stdbool.h>
 
+/**
+ * Default maximum number of modules kept in memory, see `ModuleCache::set_max_modules`
+ */
+#define DEFAULT_MAX_MODULES 64
+
+/**
+ * Identifies the kind of failure that caused the last error, this allows callers to handle
+ * errors without comparing error messages
+ *
+ */
+typedef enum ExtismErrorCode {
+  /**
+   * No error
+   */
+  EXTISM_ERROR_CODE_NONE,
+  /**
+   * An error that doesn't fit into any of the other categories
+   */
+  EXTISM_ERROR_CODE_UNKNOWN,
+  /**
+   * The plugin ID doesn't refer to a plugin in the context
+   */
+  EXTISM_ERROR_CODE_PLUGIN_NOT_FOUND,
+  /**
+   * The plugin doesn't export the function being called
+   */
+  EXTISM_ERROR_CODE_FUNCTION_NOT_FOUND,
+  /**
+   * The call took longer than the timeout
+   */
+  EXTISM_ERROR_CODE_TIMEOUT,
+  /**
+   * The call was cancelled using a cancel handle
+   */
+  EXTISM_ERROR_CODE_CANCELLED,
+  /**
+   * The call used all of its fuel
+   */
+  EXTISM_ERROR_CODE_OUT_OF_FUEL,
+  /**
+   * The WebAssembly code trapped
+   */
+  EXTISM_ERROR_CODE_TRAP,
+  /**
+   * The plugin called the WASI `proc_exit` function with a non-zero exit code
+   */
+  EXTISM_ERROR_CODE_WASI_EXIT,
+  /**
+   * A host function returned an error
+   */
+  EXTISM_ERROR_CODE_HOST_FUNCTION_ERROR,
+  /**
+   * The plugin's memory couldn't be grown
+   */
+  EXTISM_ERROR_CODE_OUT_OF_MEMORY,
+  /**
+   * The manifest couldn't be parsed or refers to modules that can't be loaded
+   */
+  EXTISM_ERROR_CODE_MANIFEST_INVALID,
+  /**
+   * The hash of a module doesn't match the hash in the manifest
+   */
+  EXTISM_ERROR_CODE_HASH_MISMATCH,
+  /**
+   * An HTTP request was made to a host that isn't in `allowed_hosts`
+   */
+  EXTISM_ERROR_CODE_HTTP_DENIED,
+  /**
+   * The variable store or an HTTP response is larger than the limit set in the manifest's
+   * memory options
+   */
+  EXTISM_ERROR_CODE_LIMIT_EXCEEDED,
+  /**
+   * A module isn't signed when signatures are required, or its signature is invalid
+   */
+  EXTISM_ERROR_CODE_SIGNATURE_INVALID,
+} ExtismErrorCode;
+
+/**
+ * Log level passed to an `ExtismLogCallback`
+ *
+ */
+typedef enum ExtismLogLevel {
+  EXTISM_LOG_LEVEL_ERROR = 1,
+  EXTISM_LOG_LEVEL_WARN,
+  EXTISM_LOG_LEVEL_INFO,
+  EXTISM_LOG_LEVEL_DEBUG,
+  EXTISM_LOG_LEVEL_TRACE,
+} ExtismLogLevel;
+
+/**
+ * A list of all possible value types in WebAssembly.
+ */
+typedef enum ExtismValType {
+  /**
+   * Signed 32 bit integer.
+   */
+  I32,
+  /**
+   * Signed 64 bit integer.
+   */
+  I64,
+  /**
+   * Floating point 32 bit integer.
+   */
+  F32,
+  /**
+   * Floating point 64 bit integer.
+   */
+  F64,
+  /**
+   * A 128 bit number.
+   */
+  V128,
+  /**
+   * A reference to a Wasm function.
+   */
+  FuncRef,
+  /**
+   * A reference to opaque data in the Wasm instance.
+   */
+  ExternRef,
+} ExtismValType;
+
+/**
+ * Used to cancel a running plugin call from another thread
+ */
+typedef struct ExtismCancelHandle ExtismCancelHandle;
+
 /**
  * A `Context` is used to store and manage plugins
+ *
+ * A context can be shared between threads: the plugin registry is protected by a lock and each
+ * plugin has its own lock, so different plugins in the same context can be called in parallel.
+ * Calls to the same plugin from multiple threads are serialized.
  */
 typedef struct ExtismContext ExtismContext;
 
-typedef int32_t ExtismPlugin;
+typedef struct ExtismFunction ExtismFunction;
+
+typedef struct ExtismCurrentPlugin ExtismCurrentPlugin;
+
+/**
+ * A pool of plugin instances created from the same compiled modules, this allows the same plugin
+ * to be called from multiple threads at once
+ *
+ * Each instance has its own store and memory, instances that have been used are replaced with a
+ * new instance before they're handed out again so no data from one call is visible to the next
+ * caller
+ */
+typedef struct ExtismPool ExtismPool;
 
 typedef uint64_t ExtismSize;
 
+/**
+ * Called to get the value of a credential used in the headers of a module download
+ *
+ * `name`: the name of the credential
+ * `url`: the URL of the module
+ * `user_data`: the pointer passed to `extism_context_set_credential_provider`
+ *
+ * Returns the value of the credential or `NULL` if it isn't available. The value is copied, so it
+ * only needs to stay valid until the callback is called again
+ */
+typedef const char *(*ExtismCredentialCallback)(const char *name, const char *url, void *user_data);
+
+typedef int32_t ExtismPlugin;
+
+/**
+ * A union type for host function argument/return values
+ */
+typedef union ExtismValUnion {
+  int32_t i32;
+  int64_t i64;
+  float f32;
+  double f64;
+} ExtismValUnion;
+
+/**
+ * `ExtismVal` holds the type and value of a function argument/return
+ */
+typedef struct ExtismVal {
+  enum ExtismValType t;
+  union ExtismValUnion v;
+} ExtismVal;
+
+/**
+ * Host function signature, a host function can fail by calling `extism_current_plugin_set_error`
+ * before it returns
+ */
+typedef void (*ExtismFunctionType)(struct ExtismCurrentPlugin *plugin,
+                                   const struct ExtismVal *inputs,
+                                   ExtismSize n_inputs,
+                                   struct ExtismVal *outputs,
+                                   ExtismSize n_outputs,
+                                   void *data);
+
+/**
+ * Called for each log message
+ *
+ * `level`: the level of the message
+ * `target`: `extism_runtime::plugin` for messages logged by plugins, otherwise the module that
+ * logged the message
+ * `message`: the message
+ * `plugin`: the ID of the plugin that logged the message, or `-1`
+ * `user_data`: the pointer passed to `extism_log_custom`
+ */
+typedef void (*ExtismLogCallback)(enum ExtismLogLevel level,
+                                  const char *target,
+                                  const char *message,
+                                  ExtismPlugin plugin,
+                                  void *user_data);
+
 /**
  * Create a new context
+ *
+ * A context can be used from multiple threads at once. Different plugins in the same context
+ * can be called in parallel, calls to the same plugin are serialized. Errors set on the context
+ * are stored per thread. The pointers returned by `extism_error` and
+ * `extism_plugin_output_data` for a plugin are only valid until the plugin is used again, so
+ * when a plugin is shared between threads they should be read before another thread can call
+ * the plugin. `extism_context_free` must not be called while the context is in use.
  */
 struct ExtismContext *extism_context_new(void);
 
+/**
+ * Create a new context that meters fuel, this is required to use `fuel_limit` in a manifest and
+ * for `extism_plugin_fuel_consumed` to report the fuel used by a call. Metering fuel adds some
+ * overhead to every call, so it's disabled in contexts created using `extism_context_new`
+ */
+struct ExtismContext *extism_context_new_with_fuel(void);
+
 /**
  * Free a context
  */
 void extism_context_free(struct ExtismContext *ctx);
 
+/**
+ * Set the directory used to cache precompiled modules, passing `NULL` disables the cache
+ *
+ * Compiled modules are written to this directory and re-used by later calls to
+ * `extism_plugin_new` with the same WASM code, even across process restarts
+ */
+bool extism_context_set_cache_dir(struct ExtismContext *ctx, const char *dir);
+
+/**
+ * Set the maximum number of compiled modules kept in memory, the least recently used modules are
+ * removed when there are more than this. `0` removes the limit
+ */
+void extism_context_set_module_cache_size(struct ExtismContext *ctx, ExtismSize max);
+
+/**
+ * Get the number of compiled modules kept in memory and how often they've been re-used as
+ * JSON, the result must be freed using `extism_string_free`. Returns NULL on error
+ */
+char *extism_context_module_cache_stats(struct ExtismContext *ctx);
+
+/**
+ * Configure the cache used for modules downloaded from URLs
+ *
+ * `dir`: is the directory used to store modules, passing `NULL` disables the cache. By default
+ * a per-user cache directory is used
+ * `max_size`: is the maximum total size of the cached modules in bytes, the least recently used
+ * modules are removed when the cache is larger than this. `0` removes the limit
+ */
+bool extism_context_set_download_cache(struct ExtismContext *ctx,
+                                       const char *dir,
+                                       ExtismSize max_size);
+
+/**
+ * Cache modules downloaded from URLs that don't have a hash, this is disabled by default
+ *
+ * Modules without a hash aren't checked when they're loaded from the cache and aren't downloaded
+ * again until they're removed, so a URL that always points to the latest version of a module
+ * will keep loading the cached version. Requests that use credentials, environment variables or
+ * `Authorization` and `Cookie` headers are never cached without a hash
+ */
+void extism_context_download_cache_unhashed(struct ExtismContext *ctx, bool enabled);
+
+/**
+ * List the modules in the download cache as JSON, the least recently used module is first.
+ * Returns NULL on error. The result must be freed using `extism_string_free`
+ *
+ * Each entry has a `key`, which is `algorithm:hex` for modules with a hash, a `path`, a `size` in
+ * bytes and a `last_used` time in seconds since the Unix epoch
+ */
+char *extism_context_download_cache_list(struct ExtismContext *ctx);
+
+/**
+ * Remove the least recently used modules until the download cache is no larger than its
+ * maximum size, returns the number of modules removed or -1 on error
+ */
+int64_t extism_context_download_cache_prune(struct ExtismContext *ctx);
+
+/**
+ * Remove every module from the download cache, returns the number of modules removed or -1 on
+ * error
+ */
+int64_t extism_context_download_cache_clear(struct ExtismContext *ctx);
+
+/**
+ * Set the callback used to get the values of `${credential:NAME}` references in the headers used
+ * to download modules, replacing the previous callback
+ *
+ * `user_data`: is passed to the callback, the callback may be called from any thread that
+ * creates a plugin
+ */
+void extism_context_set_credential_provider(struct ExtismContext *ctx,
+                                            ExtismCredentialCallback callback,
+                                            void *user_data);
+
+/**
+ * Remove the callback set using `extism_context_set_credential_provider`, modules that use
+ * credentials fail to load until a new callback is set
+ */
+void extism_context_remove_credential_provider(struct ExtismContext *ctx);
+
+/**
+ * Allow `${env:NAME}` references to the environment variable `name` in the headers used to
+ * download modules, no environment variables can be used until they're allowed
+ */
+bool extism_context_allow_env_var(struct ExtismContext *ctx, const char *name);
+
+/**
+ * Stop allowing an environment variable to be used in headers, returns `false` if the variable
+ * wasn't allowed
+ */
+bool extism_context_remove_allowed_env_var(struct ExtismContext *ctx, const char *name);
+
+/**
+ * Trust modules signed by `public_key`, which must be a 32 byte ed25519 public key
+ *
+ * `key_id`: is the name modules use to refer to the key in the `key_id` field of their metadata,
+ * adding a key with an existing ID replaces the old key
+ */
+bool extism_context_trust_key(struct ExtismContext *ctx,
+                              const char *key_id,
+                              const uint8_t *public_key,
+                              ExtismSize public_key_size);
+
+/**
+ * Stop trusting the key with the ID `key_id`, returns `false` if the key wasn't trusted
+ */
+bool extism_context_remove_trusted_key(struct ExtismContext *ctx, const char *key_id);
+
+/**
+ * Require modules to be signed by a trusted key
+ *
+ * When `required` is `true`, creating or updating a plugin fails with
+ * `EXTISM_ERROR_CODE_SIGNATURE_INVALID` if any of its modules is unsigned or isn't signed by a
+ * key added using `extism_context_trust_key`. Signatures from trusted keys are always verified
+ */
+void extism_context_require_signatures(struct ExtismContext *ctx, bool required);
+
 /**
  * Create a new plugin
  *
@@ -34,11 +369,94 @@ ExtismPlugin extism_plugin_new(struct ExtismContext *ctx,
                                ExtismSize wasm_size,
                                bool with_wasi);
 
+/**
+ * Create a new host function
+ *
+ * `name`: function name, this should be valid UTF-8
+ * `inputs`: argument types
+ * `n_inputs`: number of argument types
+ * `outputs`: return types
+ * `n_outputs`: number of return types
+ * `func`: the function to call, it can fail by calling `extism_current_plugin_set_error`
+ * `user_data`: a pointer that will be passed to the function when it's called
+ * `free_user_data`: a callback to release the `user_data` value when the resulting
+ *   `ExtismFunction` and any plugins using it are freed, this may be NULL
+ *
+ * Returns a new `ExtismFunction` or NULL if the `name` argument is invalid. The function
+ * can be freed using `extism_function_free` once the plugins using it have been created.
+ */
+struct ExtismFunction *extism_function_new(const char *name,
+                                           const enum ExtismValType *inputs,
+                                           ExtismSize n_inputs,
+                                           const enum ExtismValType *outputs,
+                                           ExtismSize n_outputs,
+                                           ExtismFunctionType func,
+                                           void *user_data,
+                                           void (*free_user_data)(void *_));
+
+/**
+ * Free an `ExtismFunction`
+ */
+void extism_function_free(struct ExtismFunction *f);
+
+/**
+ * Get a pointer to the start of the current plugin's memory, this should only be used from
+ * inside a host function. Offsets passed to host functions can be added to this pointer to
+ * read and write the plugin's memory.
+ */
+uint8_t *extism_current_plugin_memory(struct ExtismCurrentPlugin *plugin);
+
+/**
+ * Allocate a block of `n` bytes in the current plugin's memory, this should only be used from
+ * inside a host function. Returns the offset of the new block or 0 on error.
+ */
+uint64_t extism_current_plugin_memory_alloc(struct ExtismCurrentPlugin *plugin, ExtismSize n);
+
+/**
+ * Get the length of the block at `offset` in the current plugin's memory, returns 0 if there
+ * is no block at that offset
+ */
+ExtismSize extism_current_plugin_memory_length(struct ExtismCurrentPlugin *plugin, uint64_t offset);
+
+/**
+ * Free the block at `offset` in the current plugin's memory
+ */
+void extism_current_plugin_memory_free(struct ExtismCurrentPlugin *plugin, uint64_t offset);
+
+/**
+ * Get a pointer to the block at `offset` in the current plugin's memory, the pointer is only
+ * valid until the plugin's memory grows. Returns NULL if there is no block at that offset.
+ */
+uint8_t *extism_current_plugin_memory_ptr(struct ExtismCurrentPlugin *plugin, uint64_t offset);
+
+/**
+ * Fail the host function that's running with the error `msg`, this should only be used from
+ * inside a host function. The call to the plugin fails with `msg` once the host function
+ * returns, the values written to its outputs are ignored
+ */
+void extism_current_plugin_set_error(struct ExtismCurrentPlugin *plugin, const char *msg);
+
+/**
+ * Create a new plugin with additional host functions
+ *
+ * `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+ * `wasm_size`: the length of the `wasm` parameter
+ * `functions`: an array of `ExtismFunction*`
+ * `n_functions`: the number of functions provided
+ * `with_wasi`: enables/disables WASI
+ */
+ExtismPlugin extism_plugin_new_with_functions(struct ExtismContext *ctx,
+                                              const uint8_t *wasm,
+                                              ExtismSize wasm_size,
+                                              const struct ExtismFunction *const *functions,
+                                              ExtismSize n_functions,
+                                              bool with_wasi);
+
 /**
  * Update a plugin, keeping the existing ID
  *
  * Similar to `extism_plugin_new` but takes an `index` argument to specify
- * which plugin to update
+ * which plugin to update, the host functions the plugin was created with are re-used
  *
  * Memory for this plugin will be reset upon update
  */
@@ -48,6 +466,24 @@ bool extism_plugin_update(struct ExtismContext *ctx,
                           ExtismSize wasm_size,
                           bool with_wasi);
 
+/**
+ * Update a plugin, replacing its host functions
+ *
+ * `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+ * `wasm_size`: the length of the `wasm` parameter
+ * `functions`: an array of `ExtismFunction*`, these replace the functions the plugin was created
+ * with
+ * `n_functions`: the number of functions provided
+ * `with_wasi`: enables/disables WASI
+ */
+bool extism_plugin_update_with_functions(struct ExtismContext *ctx,
+                                         ExtismPlugin index,
+                                         const uint8_t *wasm,
+                                         ExtismSize wasm_size,
+                                         const struct ExtismFunction *const *functions,
+                                         ExtismSize n_functions,
+                                         bool with_wasi);
+
 /**
  * Remove a plugin from the registry and free associated memory
  */
@@ -73,6 +509,37 @@ bool extism_plugin_function_exists(struct ExtismContext *ctx,
                                    ExtismPlugin plugin,
                                    const char *func_name);
 
+/**
+ * Get the exports of a plugin's main module and the imports required by all of its modules as
+ * JSON, returns NULL on error. The result must be freed using `extism_string_free`
+ *
+ * Each import includes a `provider` field, which is one of `builtin`, `host_function`, `module`
+ * or `wasi`
+ */
+char *extism_plugin_info(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Check a manifest for problems without creating a plugin, returns a JSON array of diagnostics,
+ * which is empty if the manifest is valid. The result must be freed using `extism_string_free`
+ *
+ * Each diagnostic has a `kind`, a `path`, which is a JSON pointer to the value with the problem,
+ * and a `message`. If the manifest can't be parsed a single diagnostic with the kind `parse` is
+ * returned. Returns NULL and sets the context error if the diagnostics can't be encoded
+ */
+char *extism_manifest_validate(struct ExtismContext *ctx,
+                               const uint8_t *data,
+                               ExtismSize data_size);
+
+/**
+ * Get the memory usage of a plugin as JSON, returns NULL on error. The result must be freed
+ * using `extism_string_free`
+ *
+ * The `extism` field describes the memory used for inputs, outputs and `extism_alloc`, and the
+ * `guest` field describes the memory exported by the main module, or is `null` if the module
+ * doesn't export a memory
+ */
+char *extism_plugin_memory_stats(struct ExtismContext *ctx, ExtismPlugin plugin);
+
 /**
  * Call a function
  *
@@ -86,12 +553,174 @@ int32_t extism_plugin_call(struct ExtismContext *ctx,
                            const uint8_t *data,
                            ExtismSize data_len);
 
+/**
+ * Call a function with a timeout that overrides the `timeout_ms` value from the manifest
+ * for this call only
+ *
+ * `func_name`: is the function to call
+ * `data`: is the input data
+ * `data_len`: is the length of `data`
+ * `timeout_ms`: is the maximum amount of time the call can run for, in milliseconds
+ */
+int32_t extism_plugin_call_with_timeout(struct ExtismContext *ctx,
+                                        ExtismPlugin plugin_id,
+                                        const char *func_name,
+                                        const uint8_t *data,
+                                        ExtismSize data_len,
+                                        uint64_t timeout_ms);
+
+/**
+ * Get a handle that can be used to cancel a running call from another thread, the handle must
+ * be freed using `extism_cancel_handle_free`
+ *
+ * The handle owns its state, so it can still be used after the plugin is freed or updated.
+ * Cancelling a plugin that has been freed has no effect, and a plugin that has been updated
+ * needs a new handle
+ */
+struct ExtismCancelHandle *extism_plugin_cancel_handle(struct ExtismContext *ctx,
+                                                       ExtismPlugin plugin);
+
+/**
+ * Free a handle returned by `extism_plugin_cancel_handle`
+ */
+void extism_cancel_handle_free(struct ExtismCancelHandle *handle);
+
+/**
+ * Cancel the call that is currently running, or the next call if no call is running. Calls
+ * that are cancelled fail with the error message "cancelled"
+ */
+bool extism_plugin_cancel(const struct ExtismCancelHandle *handle);
+
+/**
+ * Get the amount of fuel consumed by the last call to `extism_plugin_call`, this is always 0 if
+ * the context wasn't created using `extism_context_new_with_fuel`
+ */
+uint64_t extism_plugin_fuel_consumed(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Capture the state of a plugin, returns NULL on error. The length of the snapshot is written to
+ * `length` and the snapshot must be freed using `extism_snapshot_free`
+ */
+uint8_t *extism_plugin_snapshot(struct ExtismContext *ctx, ExtismPlugin plugin, ExtismSize *length);
+
+/**
+ * Free a snapshot returned by `extism_plugin_snapshot`
+ */
+void extism_snapshot_free(uint8_t *data, ExtismSize length);
+
+/**
+ * Restore a snapshot created by `extism_plugin_snapshot`, the plugin must have been created
+ * from the same module as the snapshot
+ */
+bool extism_plugin_restore(struct ExtismContext *ctx,
+                           ExtismPlugin plugin,
+                           const uint8_t *data,
+                           ExtismSize length);
+
+/**
+ * Get the names of a plugin's vars as a JSON encoded array of strings, returns NULL on error.
+ * The result must be freed using `extism_string_free`
+ */
+char *extism_plugin_vars(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Free a string returned by the runtime
+ */
+void extism_string_free(char *s);
+
+/**
+ * Get the value of a plugin var, returns NULL if the var isn't set or on error, use
+ * `extism_error_code` to tell them apart. The length of the value is written to `length`, the
+ * returned pointer is valid until the var is changed or the plugin is called again
+ *
+ * `key`: is the name of the var
+ * `length`: is set to the length of the value
+ */
+const uint8_t *extism_plugin_var_get(struct ExtismContext *ctx,
+                                     ExtismPlugin plugin,
+                                     const char *key,
+                                     ExtismSize *length);
+
+/**
+ * Set the value of a plugin var
+ *
+ * `key`: is the name of the var
+ * `value`: is the new value, if `value` is NULL then the var is removed
+ * `value_size`: is the length of `value`
+ */
+bool extism_plugin_var_set(struct ExtismContext *ctx,
+                           ExtismPlugin plugin,
+                           const char *key,
+                           const uint8_t *value,
+                           ExtismSize value_size);
+
+/**
+ * Remove a plugin var, returns true if the var existed
+ */
+bool extism_plugin_var_delete(struct ExtismContext *ctx, ExtismPlugin plugin, const char *key);
+
+/**
+ * Remove all of a plugin's vars
+ */
+bool extism_plugin_vars_clear(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Create a pool of plugin instances, returns NULL on error
+ *
+ * `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+ * `wasm_size`: the length of the `wasm` parameter
+ * `with_wasi`: enables/disables WASI
+ * `size`: the number of instances to create up front
+ * `max_size`: the maximum number of instances the pool will grow to
+ *
+ * The pool is thread-safe and can be shared by threads that each use their own context
+ */
+struct ExtismPool *extism_pool_new(struct ExtismContext *ctx,
+                                   const uint8_t *wasm,
+                                   ExtismSize wasm_size,
+                                   bool with_wasi,
+                                   ExtismSize size,
+                                   ExtismSize max_size);
+
+/**
+ * Free a pool, plugins that are still acquired are not affected
+ */
+void extism_pool_free(struct ExtismPool *pool);
+
+/**
+ * Take an idle instance out of the pool and add it to `ctx`, the returned plugin can be used
+ * like any other plugin until it's given back using `extism_pool_release`
+ *
+ * A new instance is created if none are idle and the pool hasn't reached its maximum size,
+ * otherwise this waits up to `timeout_ms` for an instance to be released. If `timeout_ms` is
+ * 0 then this waits until an instance is available. Returns -1 on error.
+ */
+ExtismPlugin extism_pool_acquire(struct ExtismContext *ctx,
+                                 const struct ExtismPool *pool,
+                                 uint64_t timeout_ms);
+
+/**
+ * Remove a plugin acquired using `extism_pool_acquire` from `ctx` and return it to the pool,
+ * the plugin is replaced with a new instance before it's acquired again so none of its state is
+ * visible to the next caller
+ */
+bool extism_pool_release(struct ExtismContext *ctx,
+                         const struct ExtismPool *pool,
+                         ExtismPlugin plugin);
+
 /**
  * Get the error associated with a `Context` or `Plugin`, if `plugin` is `-1` then the context
  * error will be returned
  */
 const char *extism_error(struct ExtismContext *ctx, ExtismPlugin plugin);
 
+/**
+ * Get the code for the error associated with a `Context` or `Plugin`, if `plugin` is `-1` then
+ * the context error code will be returned. `EXTISM_ERROR_CODE_NONE` is returned if there is no
+ * error
+ */
+enum ExtismErrorCode extism_error_code(struct ExtismContext *ctx, ExtismPlugin plugin);
+
 /**
  * Get the length of a plugin's output data
  */
@@ -104,9 +733,26 @@ const uint8_t *extism_plugin_output_data(struct ExtismContext *ctx, ExtismPlugin
 
 /**
  * Set log file and level
+ *
+ * This can be called more than once to change the log file or level, but it fails if the host
+ * application has already installed its own logger
  */
 bool extism_log_file(const char *filename, const char *log_level);
 
+/**
+ * Pass log messages from the runtime and plugins to `callback` instead of writing them to a
+ * file, this replaces any log file set using `extism_log_file`
+ *
+ * `callback`: is called for each log message, it may be called from any thread
+ * `log_level`: is the maximum level of messages passed to `callback`, if `log_level` is `NULL`
+ * then only errors are logged
+ * `user_data`: is passed to `callback`
+ *
+ * Returns `false` if `log_level` is invalid or the host application has already installed its
+ * own logger
+ */
+bool extism_log_custom(ExtismLogCallback callback, const char *log_level, void *user_data);
+
 /**
  * Get the Extism version string
  */
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sha2::Digest;
+
+use crate::*;
+
+/// Default maximum number of modules kept in memory, see `ModuleCache::set_max_modules`
+pub const DEFAULT_MAX_MODULES: usize = 64;
+
+/// Caches compiled modules in memory and, if a cache directory has been configured, on disk
+///
+/// The cache is internally synchronized, locks are only held while a module is looked up or
+/// added so compiling a module doesn't block other threads. When the cache holds more than its
+/// maximum number of modules the least recently used modules are removed from memory, plugins
+/// that are using them aren't affected
+pub struct ModuleCache {
+    modules: parking_lot::Mutex<Modules>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+
+    /// Directory used to store precompiled modules
+    dir: parking_lot::RwLock<Option<PathBuf>>,
+
+    /// Identifies the runtime version and engine configuration used to compile modules, this is
+    /// part of the name of every file written to `dir` so artifacts from other configurations are
+    /// never loaded
+    engine_key: String,
+}
+
+/// Compiled modules kept in memory
+struct Modules {
+    /// Modules and the value of `clock` when they were last used, keyed by the SHA-256 hash of
+    /// the WASM code
+    entries: BTreeMap<String, (Module, u64)>,
+    clock: u64,
+    max: Option<usize>,
+}
+
+impl Modules {
+    fn get(&mut self, hash: &str) -> Option<Module> {
+        self.clock += 1;
+        let (module, last_used) = self.entries.get_mut(hash)?;
+        *last_used = self.clock;
+        Some(module.clone())
+    }
+
+    fn insert(&mut self, hash: String, module: Module) {
+        self.clock += 1;
+        self.entries.insert(hash, (module, self.clock));
+        self.evict();
+    }
+
+    /// Remove the least recently used modules until there are no more than `max`
+    fn evict(&mut self) {
+        let max = match self.max {
+            Some(x) => x,
+            None => return,
+        };
+
+        while self.entries.len() > max {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(k, _)| k.clone());
+            match oldest {
+                Some(k) => {
+                    trace!("Removing module from cache: {k}");
+                    self.entries.remove(&k);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Usage of a `ModuleCache`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ModuleCacheStats {
+    /// Number of modules in memory
+    pub modules: usize,
+    pub max_modules: Option<usize>,
+    /// Number of times a module was found in memory
+    pub hits: u64,
+    /// Number of times a module had to be compiled or loaded from the cache directory
+    pub misses: u64,
+}
+
+/// The smallest valid module, see `ModuleCache::new`
+const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+impl ModuleCache {
+    /// Create a new cache for modules compiled using `engine`
+    pub fn new(engine: &Engine) -> Result<ModuleCache, Error> {
+        // Serialized modules include the wasmtime version, target and every compiler setting and
+        // WASM feature that affects the generated code, so the serialized empty module identifies
+        // the engine's configuration without having to list its settings here
+        let empty = Module::new(engine, EMPTY_MODULE)?.serialize()?;
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(&empty);
+        Ok(ModuleCache {
+            modules: parking_lot::Mutex::new(Modules {
+                entries: BTreeMap::new(),
+                clock: 0,
+                max: Some(DEFAULT_MAX_MODULES),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            dir: parking_lot::RwLock::new(None),
+            engine_key: manifest::hex(&hasher.finalize()[..8]),
+        })
+    }
+
+    /// Set the directory used to store precompiled modules, `None` disables the on-disk cache
+    pub fn set_dir(&self, dir: Option<&Path>) -> Result<(), Error> {
+        if let Some(dir) = dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        *self.dir.write() = dir.map(|x| x.to_path_buf());
+        Ok(())
+    }
+
+    /// Set the maximum number of modules kept in memory, `None` removes the limit. Modules
+    /// removed from memory are compiled again or loaded from the cache directory the next time
+    /// they're used
+    pub fn set_max_modules(&self, max: Option<usize>) {
+        let mut modules = self.modules.lock();
+        modules.max = max;
+        modules.evict();
+    }
+
+    /// Get the number of modules in memory and how often they've been re-used
+    pub fn stats(&self) -> ModuleCacheStats {
+        let modules = self.modules.lock();
+        ModuleCacheStats {
+            modules: modules.entries.len(),
+            max_modules: modules.max,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn path(&self, hash: &str) -> Option<PathBuf> {
+        self.dir
+            .read()
+            .as_ref()
+            .map(|dir| dir.join(format!("{hash}-{}.cwasm", self.engine_key)))
+    }
+
+    /// Compile a module, re-using a previously compiled module if one exists for the same code
+    pub fn compile(&self, engine: &Engine, data: impl AsRef<[u8]>) -> Result<Module, Error> {
+        Ok(self.compile_with_hash(engine, data)?.0)
+    }
+
+    /// Same as `ModuleCache::compile`, but the hex encoded SHA-256 of `data` is also returned
+    pub(crate) fn compile_with_hash(
+        &self,
+        engine: &Engine,
+        data: impl AsRef<[u8]>,
+    ) -> Result<(Module, String), Error> {
+        let hash = manifest::hex(&sha2::Sha256::digest(data.as_ref()));
+        if let Some(module) = self.modules.lock().get(&hash) {
+            trace!("Using cached module: {hash}");
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((module, hash));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let path = self.path(&hash);
+        if let Some(path) = &path {
+            if path.exists() {
+                // Safety: the cache directory is trusted to only contain files written by
+                // `ModuleCache::compile`, wasmtime will also refuse to load artifacts built by a
+                // different version or with incompatible settings
+                match unsafe { Module::deserialize_file(engine, path) } {
+                    Ok(module) => {
+                        debug!("Loaded precompiled module from {}", path.display());
+                        self.modules.lock().insert(hash.clone(), module.clone());
+                        return Ok((module, hash));
+                    }
+                    Err(e) => {
+                        debug!(
+                            "Unable to load precompiled module from {}, recompiling: {e:?}",
+                            path.display()
+                        );
+                    }
+                }
+            }
+        }
+
+        let module = Module::new(engine, data)?;
+
+        if let Some(path) = &path {
+            if let Err(e) = write_atomic(path, &module.serialize()?) {
+                error!(
+                    "Unable to write precompiled module to {}: {e:?}",
+                    path.display()
+                );
+            }
+        }
+
+        self.modules.lock().insert(hash.clone(), module.clone());
+        Ok((module, hash))
+    }
+}
+
+/// Write to a temporary file then rename it so partially written files are never visible
+fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    std::fs::write(&tmp, data)?;
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
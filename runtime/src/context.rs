@@ -1,21 +1,71 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
 use crate::*;
 
 static mut TIMER: std::sync::Mutex<Option<Timer>> = std::sync::Mutex::new(None);
 
+/// Context errors keyed by the thread that set them, errors are stored per thread so an error
+/// can't be cleared by another thread before it's read
+type Errors = parking_lot::Mutex<HashMap<std::thread::ThreadId, (std::ffi::CString, ErrorCode)>>;
+
+/// The error maps a thread has set errors in, the thread's errors are removed from the maps that
+/// still exist when the thread exits
+struct ThreadErrors {
+    thread: std::thread::ThreadId,
+    maps: std::cell::RefCell<Vec<std::sync::Weak<Errors>>>,
+}
+
+impl Drop for ThreadErrors {
+    fn drop(&mut self) {
+        for map in self.maps.get_mut().iter().filter_map(|x| x.upgrade()) {
+            map.lock().remove(&self.thread);
+        }
+    }
+}
+
+thread_local! {
+    static THREAD_ERRORS: ThreadErrors = ThreadErrors {
+        thread: std::thread::current().id(),
+        maps: std::cell::RefCell::new(Vec::new()),
+    };
+}
+
+/// A plugin stored in a context, the plugin is `None` once it has been removed from the context
+pub(crate) type PluginSlot = std::sync::Arc<parking_lot::Mutex<Option<Plugin>>>;
+
 /// A `Context` is used to store and manage plugins
+///
+/// A context can be shared between threads: the plugin registry is protected by a lock and each
+/// plugin has its own lock, so different plugins in the same context can be called in parallel.
+/// Calls to the same plugin from multiple threads are serialized.
 pub struct Context {
     /// Plugin registry
-    pub plugins: BTreeMap<PluginIndex, Plugin>,
+    plugins: parking_lot::RwLock<BTreeMap<PluginIndex, PluginSlot>>,
 
-    /// Error message
-    pub error: Option<std::ffi::CString>,
+    /// Context errors, they're freed when the context is dropped or when the thread that set
+    /// them exits
+    errors: std::sync::Arc<Errors>,
     next_id: std::sync::atomic::AtomicI32,
-    reclaimed_ids: VecDeque<PluginIndex>,
+    reclaimed_ids: parking_lot::Mutex<VecDeque<PluginIndex>>,
+
+    /// Engine shared by all plugins in the context
+    pub(crate) engine: Engine,
+
+    /// Compiled module cache
+    pub(crate) modules: ModuleCache,
 
-    // Timeout thread
-    pub(crate) epoch_timer_tx: std::sync::mpsc::SyncSender<TimerAction>,
+    /// Enables async plugin calls, see `Context::new_async`
+    pub(crate) async_support: bool,
+
+    /// Keys used to verify module signatures, see `Context::trust_key`
+    pub(crate) trusted_keys: parking_lot::RwLock<TrustedKeys>,
+
+    /// Modules downloaded from URLs
+    pub(crate) downloads: parking_lot::RwLock<DownloadCache>,
+
+    /// Credential provider and environment variables used in the headers of module downloads, see
+    /// `Context::set_credential_provider` and `Context::allow_env_var`
+    pub(crate) credentials: parking_lot::RwLock<Credentials>,
 }
 
 impl Default for Context {
@@ -28,7 +78,7 @@ const START_REUSING_IDS: usize = 25;
 
 impl Context {
     pub(crate) fn timer() -> std::sync::MutexGuard<'static, Option<Timer>> {
-        match unsafe { TIMER.lock() } {
+        match unsafe { (*std::ptr::addr_of!(TIMER)).lock() } {
             Ok(x) => x,
             Err(e) => e.into_inner(),
         }
@@ -36,24 +86,61 @@ impl Context {
 
     /// Create a new context
     pub fn new() -> Context {
+        Self::create(false, false)
+    }
+
+    /// Create a new context that meters fuel, this is required to use `fuel_limit` in a manifest
+    /// and to report the amount of fuel consumed by a call
+    ///
+    /// Metering fuel adds some overhead to every call, so it's disabled in contexts created using
+    /// `Context::new`
+    pub fn new_with_fuel() -> Context {
+        Self::create(false, true)
+    }
+
+    /// Create a new context that supports `Plugin::call_async`
+    ///
+    /// Plugins created in an async context yield to the caller periodically and when their
+    /// timeout is reached instead of trapping. Synchronous calls are still supported, but
+    /// they have some additional overhead compared to plugins from a context created using
+    /// `Context::new` and block the calling thread until they finish, even when they're made
+    /// from inside an async runtime. Async contexts always meter fuel, since it's used to decide
+    /// when a call yields
+    pub fn new_async() -> Context {
+        Self::create(true, true)
+    }
+
+    fn create(async_support: bool, consume_fuel: bool) -> Context {
+        // Start the timeout thread
         let timer = &mut *Self::timer();
+        if timer.is_none() {
+            Timer::init(timer);
+        }
 
-        let tx = match timer {
-            None => Timer::init(timer),
-            Some(t) => t.tx.clone(),
-        };
+        let engine = Engine::new(
+            Config::new()
+                .epoch_interruption(true)
+                .consume_fuel(consume_fuel)
+                .async_support(async_support),
+        )
+        .expect("Unable to create wasmtime Engine");
 
         Context {
-            plugins: BTreeMap::new(),
-            error: None,
+            plugins: parking_lot::RwLock::new(BTreeMap::new()),
+            errors: Default::default(),
             next_id: std::sync::atomic::AtomicI32::new(0),
-            reclaimed_ids: VecDeque::new(),
-            epoch_timer_tx: tx,
+            reclaimed_ids: parking_lot::Mutex::new(VecDeque::new()),
+            modules: ModuleCache::new(&engine).expect("Unable to create module cache"),
+            engine,
+            async_support,
+            trusted_keys: parking_lot::RwLock::new(TrustedKeys::default()),
+            downloads: parking_lot::RwLock::new(DownloadCache::default()),
+            credentials: parking_lot::RwLock::new(Credentials::default()),
         }
     }
 
     /// Get the next valid plugin ID
-    pub fn next_id(&mut self) -> Result<PluginIndex, Error> {
+    pub fn next_id(&self) -> Result<PluginIndex, Error> {
         // Make sure we haven't exhausted all plugin IDs, to reach this it would require the machine
         // running this code to have a lot of memory - no computer I tested on was able to allocate
         // the max number of plugins.
@@ -63,8 +150,9 @@ impl Context {
         let exhausted = self.next_id.load(std::sync::atomic::Ordering::SeqCst) == PluginIndex::MAX;
 
         // If there are a significant number of old IDs we can start to re-use them
-        if self.reclaimed_ids.len() >= START_REUSING_IDS || exhausted {
-            if let Some(x) = self.reclaimed_ids.pop_front() {
+        let mut reclaimed_ids = self.reclaimed_ids.lock();
+        if reclaimed_ids.len() >= START_REUSING_IDS || exhausted {
+            if let Some(x) = reclaimed_ids.pop_front() {
                 return Ok(x);
             }
 
@@ -80,7 +168,7 @@ impl Context {
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst))
     }
 
-    pub fn insert(&mut self, plugin: Plugin) -> PluginIndex {
+    pub fn insert(&self, plugin: Plugin) -> PluginIndex {
         // Generate a new plugin ID
         let id: i32 = match self.next_id() {
             Ok(id) => id,
@@ -90,16 +178,22 @@ impl Context {
                 return -1;
             }
         };
-        self.plugins.insert(id, plugin);
+        let mut plugin = plugin;
+        plugin.id = id;
+        self.plugins.write().insert(
+            id,
+            std::sync::Arc::new(parking_lot::Mutex::new(Some(plugin))),
+        );
         id
     }
 
-    pub fn new_plugin(&mut self, data: impl AsRef<[u8]>, with_wasi: bool) -> PluginIndex {
-        let plugin = match Plugin::new(data, with_wasi) {
+    pub fn new_plugin(&self, data: impl AsRef<[u8]>, with_wasi: bool) -> PluginIndex {
+        let plugin = match Plugin::new(self, data, with_wasi) {
             Ok(x) => x,
             Err(e) => {
                 error!("Error creating Plugin: {:?}", e);
-                self.set_error(e);
+                let code = ErrorCode::from_error(&e);
+                self.set_error_with_code(e, code);
                 return -1;
             }
         };
@@ -107,48 +201,283 @@ impl Context {
     }
 
     pub fn new_plugin_with_functions(
-        &mut self,
+        &self,
         data: impl AsRef<[u8]>,
         imports: impl IntoIterator<Item = Function>,
         with_wasi: bool,
     ) -> PluginIndex {
-        let plugin = match Plugin::new_with_functions(data, imports, with_wasi) {
+        let plugin = match Plugin::new_with_functions(self, data, imports, with_wasi) {
             Ok(x) => x,
             Err(e) => {
                 error!("Error creating Plugin: {:?}", e);
-                self.set_error(e);
+                let code = ErrorCode::from_error(&e);
+                self.set_error_with_code(e, code);
                 return -1;
             }
         };
         self.insert(plugin)
     }
 
-    /// Set the context error
-    pub fn set_error(&mut self, e: impl std::fmt::Debug) {
+    /// Replace a plugin with a new plugin created from `data`, keeping the same ID. If
+    /// `functions` is `None` then the host functions the existing plugin was created with are
+    /// used. Returns `false` and sets the context error on failure
+    pub fn update_plugin(
+        &self,
+        id: PluginIndex,
+        data: impl AsRef<[u8]>,
+        functions: Option<Vec<Function>>,
+        with_wasi: bool,
+    ) -> bool {
+        let functions = match functions {
+            Some(f) => f,
+            None => match self
+                .slot(id)
+                .and_then(|slot| Some(slot.lock().as_ref()?.functions.clone()))
+            {
+                Some(f) => f,
+                None => {
+                    self.set_error_with_code(
+                        format!("Plugin does not exist: {id}"),
+                        ErrorCode::PluginNotFound,
+                    );
+                    return false;
+                }
+            },
+        };
+
+        let plugin = match Plugin::new_with_functions(self, data, functions, with_wasi) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Error creating Plugin: {:?}", e);
+                let code = ErrorCode::from_error(&e);
+                self.set_error_with_code(e, code);
+                return false;
+            }
+        };
+
+        if !self.replace(id, plugin) {
+            self.set_error_with_code(
+                format!("Plugin does not exist: {id}"),
+                ErrorCode::PluginNotFound,
+            );
+            return false;
+        }
+
+        info!("Plugin updated: {id}");
+        true
+    }
+
+    /// Load a manifest using the context's module cache, trusted keys, download cache and
+    /// credential provider
+    ///
+    /// The settings are copied before any modules are loaded, so slow downloads and credential
+    /// providers don't block other threads and a credential provider can use the context
+    pub(crate) fn load_manifest(
+        &self,
+        data: &[u8],
+    ) -> Result<(Manifest, BTreeMap<String, Module>, String), Error> {
+        let keys = self.trusted_keys.read().clone();
+        let downloads = self.download_cache();
+        let credentials = self.credentials.read().clone();
+        Manifest::load(
+            &self.engine,
+            &self.modules,
+            &keys,
+            &downloads,
+            &credentials,
+            data,
+        )
+    }
+
+    /// Set the directory used to store precompiled modules, `None` disables the on-disk cache
+    ///
+    /// The directory should only be writable by trusted users, since its contents are loaded
+    /// as native code
+    pub fn set_cache_dir(&self, dir: Option<&std::path::Path>) -> Result<(), Error> {
+        self.modules.set_dir(dir)
+    }
+
+    /// Set the maximum number of compiled modules kept in memory, `None` removes the limit
+    pub fn set_module_cache_size(&self, max: Option<usize>) {
+        self.modules.set_max_modules(max)
+    }
+
+    /// Get the number of compiled modules kept in memory and how often they've been re-used
+    pub fn module_cache_stats(&self) -> ModuleCacheStats {
+        self.modules.stats()
+    }
+
+    /// Set the directory used to cache modules downloaded from URLs and the maximum total size
+    /// of the cached modules, `None` disables the cache or removes the size limit
+    pub fn set_download_cache(
+        &self,
+        dir: Option<&std::path::Path>,
+        max_size: Option<u64>,
+    ) -> Result<(), Error> {
+        let mut downloads = self.downloads.write();
+        downloads.set_dir(dir)?;
+        downloads.set_max_size(max_size);
+        Ok(())
+    }
+
+    /// Cache modules downloaded from URLs that don't have a hash, this is disabled by default.
+    /// See `DownloadCache::set_cache_unhashed`
+    pub fn set_download_cache_unhashed(&self, enabled: bool) {
+        self.downloads.write().set_cache_unhashed(enabled);
+    }
+
+    /// Get the cache used for modules downloaded from URLs
+    pub fn download_cache(&self) -> DownloadCache {
+        self.downloads.read().clone()
+    }
+
+    /// Set the provider used for `${credential:NAME}` references in the headers used to download
+    /// modules, `None` removes the provider and modules that use credentials fail to load
+    pub fn set_credential_provider(&self, provider: Option<CredentialProvider>) {
+        self.credentials.write().provider = provider;
+    }
+
+    /// Allow `${env:NAME}` references to the environment variable `name` in the headers used to
+    /// download modules, no environment variables can be used until they're allowed
+    pub fn allow_env_var(&self, name: impl Into<String>) {
+        self.credentials.write().allowed_env.insert(name.into());
+    }
+
+    /// Stop allowing an environment variable to be used in headers, returns `false` if the
+    /// variable wasn't allowed
+    pub fn remove_allowed_env_var(&self, name: &str) -> bool {
+        self.credentials.write().allowed_env.remove(name)
+    }
+
+    /// Trust modules signed by `public_key`, which must be a 32 byte ed25519 public key. Modules
+    /// refer to the key using `key_id`
+    pub fn trust_key(&self, key_id: impl Into<String>, public_key: &[u8]) -> Result<(), Error> {
+        self.trusted_keys.write().add(key_id, public_key)
+    }
+
+    /// Stop trusting a key, returns `false` if the key wasn't trusted. Plugins that have already
+    /// been created aren't affected
+    pub fn remove_trusted_key(&self, key_id: &str) -> bool {
+        self.trusted_keys.write().remove(key_id)
+    }
+
+    /// When `required` is `true`, plugins can only be created from modules that are signed by a
+    /// trusted key. This applies to modules loaded from files, URLs and data
+    pub fn require_signatures(&self, required: bool) {
+        self.trusted_keys.write().required = required;
+    }
+
+    /// Set the context error for the current thread
+    pub fn set_error(&self, e: impl std::fmt::Debug) {
+        self.set_error_with_code(e, ErrorCode::Unknown)
+    }
+
+    /// Set the context error and error code for the current thread
+    pub fn set_error_with_code(&self, e: impl std::fmt::Debug, code: ErrorCode) {
         trace!("Set context error: {:?}", e);
-        self.error = Some(error_string(e));
+        let error = (error_string(e), code);
+        self.errors
+            .lock()
+            .insert(std::thread::current().id(), error);
+
+        // The error is only left in the map until the context is dropped if the thread is
+        // already exiting
+        let _ = THREAD_ERRORS.try_with(|thread| {
+            // Maps of contexts that have been dropped are removed
+            let mut maps = thread.maps.borrow_mut();
+            maps.retain(|x| x.strong_count() > 0);
+            if !maps
+                .iter()
+                .any(|x| std::ptr::eq(x.as_ptr(), std::sync::Arc::as_ptr(&self.errors)))
+            {
+                maps.push(std::sync::Arc::downgrade(&self.errors));
+            }
+        });
     }
 
     /// Convenience function to set error and return the value passed as the final parameter
-    pub fn error<T>(&mut self, e: impl std::fmt::Debug, x: T) -> T {
+    pub fn error<T>(&self, e: impl std::fmt::Debug, x: T) -> T {
         self.set_error(e);
         x
     }
 
-    /// Get a plugin from the context
-    pub fn plugin(&mut self, id: PluginIndex) -> Option<&mut Plugin> {
-        self.plugins.get_mut(&id)
+    /// Unset the context error for the current thread
+    pub fn clear_error(&self) {
+        self.errors.lock().remove(&std::thread::current().id());
     }
 
-    pub fn plugin_exists(&mut self, id: PluginIndex) -> bool {
-        self.plugins.contains_key(&id)
+    /// Get the code for the current thread's context error, `ErrorCode::None` is returned if
+    /// there is no error
+    pub fn error_code(&self) -> ErrorCode {
+        match self.errors.lock().get(&std::thread::current().id()) {
+            Some((_, code)) => *code,
+            None => ErrorCode::None,
+        }
     }
 
-    /// Remove a plugin from the context
-    pub fn remove(&mut self, id: PluginIndex) {
-        if self.plugins.remove(&id).is_some() {
-            // Collect old IDs in case we need to re-use them
-            self.reclaimed_ids.push_back(id);
+    /// Get a pointer to the current thread's context error message, the pointer is valid until
+    /// the error is changed by the same thread
+    pub(crate) fn error_ptr(&self) -> *const std::ffi::c_char {
+        // The message is stored on the heap, so the pointer stays valid when other threads
+        // change the map
+        match self.errors.lock().get(&std::thread::current().id()) {
+            Some((e, _)) => e.as_ptr(),
+            None => std::ptr::null(),
+        }
+    }
+
+    /// Get the slot for a plugin, the plugin must be locked before it can be used
+    pub(crate) fn slot(&self, id: PluginIndex) -> Option<PluginSlot> {
+        self.plugins.read().get(&id).cloned()
+    }
+
+    pub fn plugin_exists(&self, id: PluginIndex) -> bool {
+        self.plugins.read().contains_key(&id)
+    }
+
+    /// Replace a plugin, this waits for any calls to the existing plugin to finish. Returns
+    /// `false` if there is no plugin with the given ID
+    pub fn replace(&self, id: PluginIndex, plugin: Plugin) -> bool {
+        let slot = match self.slot(id) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        let mut current = slot.lock();
+        if current.is_none() {
+            return false;
         }
+
+        let mut plugin = plugin;
+        plugin.id = id;
+        *current = Some(plugin);
+        true
+    }
+
+    /// Remove a plugin from the context
+    pub fn remove(&self, id: PluginIndex) {
+        self.take(id);
+    }
+
+    /// Remove a plugin from the context without dropping it, this waits for any calls to the
+    /// plugin to finish
+    pub fn take(&self, id: PluginIndex) -> Option<Plugin> {
+        let slot = self.plugins.write().remove(&id)?;
+
+        // Collect old IDs in case we need to re-use them
+        self.reclaimed_ids.lock().push_back(id);
+
+        let plugin = slot.lock().take();
+        plugin
+    }
+
+    /// Remove all plugins from the context
+    pub fn clear(&self) {
+        let plugins = std::mem::take(&mut *self.plugins.write());
+        trace!(
+            "Resetting context, plugins cleared: {:?}",
+            plugins.keys().collect::<Vec<&i32>>()
+        );
+        drop(plugins);
     }
 }
@@ -0,0 +1,83 @@
+use std::collections::BTreeSet;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+
+use extism_manifest::HeaderSource;
+
+/// Provides the values of `${credential:NAME}` references in the headers used to download
+/// modules, it's called with the name of the credential and the URL of the module. Returning
+/// `None` stops the module from loading
+pub type CredentialProvider = std::sync::Arc<dyn Fn(&str, &str) -> Option<String> + Send + Sync>;
+
+/// Sources for the references in the headers used to download modules
+///
+/// Manifests may come from less trusted sources than the host, so environment variables are only
+/// expanded if the host has allowed them and credentials are only available if the host has
+/// set a provider, which is given the URL the credential will be sent to
+#[derive(Clone, Default)]
+pub(crate) struct Credentials {
+    pub(crate) provider: Option<CredentialProvider>,
+
+    /// Environment variables that can be used in headers
+    pub(crate) allowed_env: BTreeSet<String>,
+}
+
+impl Credentials {
+    /// Get the value of a reference in a header sent to `url`
+    #[cfg_attr(not(feature = "register-http"), allow(dead_code))]
+    pub(crate) fn resolve(
+        &self,
+        source: HeaderSource,
+        name: &str,
+        url: &str,
+    ) -> Result<String, String> {
+        match source {
+            HeaderSource::Env if !self.allowed_env.contains(name) => Err(format!(
+                "environment variable {name} isn't allowed by the host"
+            )),
+            HeaderSource::Env => {
+                std::env::var(name).map_err(|_| format!("environment variable {name} isn't set"))
+            }
+            HeaderSource::Credential => self
+                .provider
+                .as_ref()
+                .and_then(|f| f(name, url))
+                .ok_or_else(|| format!("credential {name} isn't available")),
+        }
+    }
+}
+
+/// Called to get the value of a credential used in the headers of a module download
+///
+/// `name`: the name of the credential
+/// `url`: the URL of the module
+/// `user_data`: the pointer passed to `extism_context_set_credential_provider`
+///
+/// Returns the value of the credential or `NULL` if it isn't available. The value is copied, so it
+/// only needs to stay valid until the callback is called again
+pub type CredentialCallback =
+    extern "C" fn(name: *const c_char, url: *const c_char, user_data: *mut c_void) -> *const c_char;
+
+/// Credential provider that calls a C callback
+pub(crate) struct CallbackProvider {
+    pub(crate) callback: CredentialCallback,
+    pub(crate) user_data: *mut c_void,
+}
+
+// The embedder is responsible for making `user_data` safe to use from any thread
+unsafe impl Send for CallbackProvider {}
+unsafe impl Sync for CallbackProvider {}
+
+impl CallbackProvider {
+    pub(crate) fn get(&self, name: &str, url: &str) -> Option<String> {
+        let name = CString::new(name).ok()?;
+        let url = CString::new(url).ok()?;
+        let value = (self.callback)(name.as_ptr(), url.as_ptr(), self.user_data);
+        if value.is_null() {
+            return None;
+        }
+
+        let value = unsafe { CStr::from_ptr(value) };
+        Some(value.to_string_lossy().into_owned())
+    }
+}
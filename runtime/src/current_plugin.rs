@@ -0,0 +1,104 @@
+use crate::*;
+
+/// Handle to the plugin that called a host function, this provides access to the plugin's input
+/// and memory without using `Internal` or `PluginMemory` directly
+pub struct CurrentPlugin<'a> {
+    internal: &'a mut Internal,
+}
+
+impl<'a> CurrentPlugin<'a> {
+    /// Get the plugin that made the call currently being handled by a host function
+    pub fn new(caller: &'a mut Caller<Internal>) -> CurrentPlugin<'a> {
+        CurrentPlugin {
+            internal: caller.data_mut(),
+        }
+    }
+
+    pub(crate) fn from_internal(internal: &'a mut Internal) -> CurrentPlugin<'a> {
+        CurrentPlugin { internal }
+    }
+
+    fn memory(&self) -> Result<&PluginMemory, Error> {
+        if self.internal.plugin.is_null() {
+            return Err(Error::msg("No plugin call in progress"));
+        }
+
+        Ok(self.internal.memory())
+    }
+
+    fn memory_mut(&mut self) -> Result<&mut PluginMemory, Error> {
+        if self.internal.plugin.is_null() {
+            return Err(Error::msg("No plugin call in progress"));
+        }
+
+        Ok(self.internal.memory_mut())
+    }
+
+    /// Convert `handle` to a `MemoryBlock`, making sure it's inside of the plugin's memory
+    fn block(memory: &PluginMemory, handle: impl ToMemoryBlock) -> Result<MemoryBlock, Error> {
+        let block = handle.to_memory_block(memory)?;
+        match block.offset.checked_add(block.length) {
+            Some(end) if end <= memory.size() => Ok(block),
+            _ => Err(Error::msg(format!(
+                "Invalid memory block: offset {}, length {}",
+                block.offset, block.length
+            ))),
+        }
+    }
+
+    /// Get the input of the current call
+    pub fn input(&self) -> &[u8] {
+        if self.internal.input.is_null() {
+            return &[];
+        }
+
+        unsafe { std::slice::from_raw_parts(self.internal.input, self.internal.input_length) }
+    }
+
+    /// Allocate a block of `n` bytes
+    pub fn memory_alloc(&mut self, n: usize) -> Result<MemoryBlock, Error> {
+        self.memory_mut()?.alloc(n)
+    }
+
+    /// Allocate a block and copy `data` into it
+    pub fn memory_alloc_bytes(&mut self, data: impl AsRef<[u8]>) -> Result<MemoryBlock, Error> {
+        self.memory_mut()?.alloc_bytes(data)
+    }
+
+    /// Free the block allocated at `offset`
+    pub fn memory_free(&mut self, offset: usize) -> Result<(), Error> {
+        self.memory_mut()?.free(offset);
+        Ok(())
+    }
+
+    /// Get the length of the block allocated at `offset`, `None` is returned if there is no block
+    /// at that offset
+    pub fn memory_length(&self, offset: usize) -> Option<usize> {
+        self.memory().ok()?.block_length(offset)
+    }
+
+    /// Get the contents of a block
+    pub fn memory_bytes(&self, handle: impl ToMemoryBlock) -> Result<&[u8], Error> {
+        let memory = self.memory()?;
+        memory.get(Self::block(memory, handle)?)
+    }
+
+    /// Get the contents of a block as mutable bytes
+    pub fn memory_bytes_mut(&mut self, handle: impl ToMemoryBlock) -> Result<&mut [u8], Error> {
+        let memory = self.memory_mut()?;
+        let handle = Self::block(memory, handle)?;
+        memory.get_mut(handle)
+    }
+
+    /// Get the contents of a block as a string
+    pub fn memory_str(&self, handle: impl ToMemoryBlock) -> Result<&str, Error> {
+        let memory = self.memory()?;
+        memory.get_str(Self::block(memory, handle)?)
+    }
+
+    /// Get a pointer to the contents of a block
+    pub fn memory_ptr(&self, handle: impl ToMemoryBlock) -> Result<*mut u8, Error> {
+        let memory = self.memory()?;
+        memory.ptr(Self::block(memory, handle)?)
+    }
+}
@@ -0,0 +1,274 @@
+use std::path::{Path, PathBuf};
+
+use sha2::Digest;
+
+use crate::*;
+
+/// Limit on the total size of the download cache when `set_max_size` hasn't been called
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Requests with these headers are never cached when they don't have a hash
+const SECRET_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+
+/// Modules are written to files starting with this prefix and then renamed, so other processes
+/// never see partially written modules
+const TMP_PREFIX: &str = ".tmp-";
+
+/// Temporary files older than this are left over from a process that exited while writing and
+/// are removed by `DownloadCache::prune`
+const TMP_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Stores modules downloaded from URLs on disk so they don't have to be downloaded again
+///
+/// Modules with a hash are stored using their normalized hash. Modules without a hash are only
+/// cached if `set_cache_unhashed` is enabled, since they aren't checked when they're loaded from
+/// the cache and aren't downloaded again until they're removed, they're stored using their
+/// method, URL and headers. When the cache is larger than its maximum size the least recently
+/// used modules are removed
+#[derive(Clone, Debug)]
+pub struct DownloadCache {
+    /// Directory used to store modules, `None` disables the cache
+    dir: Option<PathBuf>,
+    max_size: Option<u64>,
+
+    /// Cache modules that don't have a hash
+    cache_unhashed: bool,
+}
+
+/// A module stored in the download cache
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheEntry {
+    /// `algorithm:hex` for modules with a hash, or `url:hex` with the SHA-256 hash of the method,
+    /// URL and headers for modules without a hash
+    pub key: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Time the module was last added to or loaded from the cache, in seconds since the Unix
+    /// epoch
+    pub last_used: u64,
+}
+
+impl Default for DownloadCache {
+    fn default() -> Self {
+        DownloadCache {
+            dir: Some(default_dir()),
+            max_size: Some(DEFAULT_MAX_SIZE),
+            cache_unhashed: false,
+        }
+    }
+}
+
+/// Per-user cache directory, so modules downloaded by one user are never loaded by another
+fn default_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("extism").join("downloads")
+}
+
+fn unix_time(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+impl DownloadCache {
+    /// Key used for a module with a hash
+    pub(crate) fn checksum_key(checksum: &extism_manifest::Checksum) -> String {
+        format!("{}-{}", checksum.algorithm.name(), checksum.hex)
+    }
+
+    /// Key used for a module without a hash, this includes the headers so requests that may
+    /// return different modules don't share an entry
+    ///
+    /// Returns `None` for requests that use credentials, environment variables or headers that
+    /// usually contain secrets, since the response may depend on values that aren't part of the
+    /// request in the manifest
+    pub(crate) fn url_key(method: &str, req: &extism_manifest::HttpRequest) -> Option<String> {
+        let mut s = format!("{method} {}\n", req.url);
+        for (k, v) in &req.headers {
+            let mut has_reference = false;
+            let expanded = extism_manifest::expand_header(v, |_, _| {
+                has_reference = true;
+                Ok(String::new())
+            });
+
+            let secret = SECRET_HEADERS.iter().any(|x| x.eq_ignore_ascii_case(k));
+            if secret || has_reference || expanded.is_err() {
+                return None;
+            }
+            s.push_str(&format!("{}: {v}\n", k.to_ascii_lowercase()));
+        }
+
+        let digest = sha2::Sha256::digest(s.as_bytes());
+        Some(format!("url-{}", manifest::hex(&digest)))
+    }
+
+    /// Cache modules that don't have a hash, these modules are loaded from the cache without
+    /// being checked and aren't downloaded again until they're removed from the cache, so a URL
+    /// that always points to the latest version of a module will keep loading the cached version
+    pub fn set_cache_unhashed(&mut self, enabled: bool) {
+        self.cache_unhashed = enabled;
+    }
+
+    pub fn cache_unhashed(&self) -> bool {
+        self.cache_unhashed
+    }
+
+    /// Set the directory used to store modules, `None` disables the cache
+    pub fn set_dir(&mut self, dir: Option<&Path>) -> Result<(), Error> {
+        if let Some(dir) = dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.dir = dir.map(|x| x.to_path_buf());
+        Ok(())
+    }
+
+    /// Directory used to store modules, or `None` if the cache is disabled
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Set the maximum total size of the cached modules in bytes, `None` removes the limit
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{key}.wasm")))
+    }
+
+    /// Load a module from the cache, this marks the module as recently used
+    pub(crate) fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(key)?;
+        let data = std::fs::read(&path).ok()?;
+        if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(std::time::SystemTime::now());
+        }
+        trace!("Loaded {key} from the download cache");
+        Some(data)
+    }
+
+    /// Add a module to the cache, then remove the least recently used modules if the cache is
+    /// larger than its maximum size
+    #[cfg_attr(not(feature = "register-http"), allow(dead_code))]
+    pub(crate) fn add(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let (dir, path) = match (&self.dir, self.path(key)) {
+            (Some(dir), Some(path)) => (dir, path),
+            _ => return Ok(()),
+        };
+
+        std::fs::create_dir_all(dir)?;
+        let tmp = dir.join(format!("{TMP_PREFIX}{}", uuid::Uuid::new_v4()));
+        if let Err(e) = std::fs::write(&tmp, data).and_then(|_| std::fs::rename(&tmp, &path)) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+
+        debug!("Added {key} to the download cache");
+        self.prune()?;
+        Ok(())
+    }
+
+    /// List the cached modules, the least recently used module is first
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, Error> {
+        let dir = match &self.dir {
+            Some(dir) if dir.exists() => dir,
+            _ => return Ok(vec![]),
+        };
+
+        let mut entries = vec![];
+        for file in std::fs::read_dir(dir)? {
+            let file = file?;
+            let path = file.path();
+            let key = match path.file_stem().and_then(|x| x.to_str()) {
+                Some(x) if path.extension().map(|x| x == "wasm").unwrap_or(false) => x,
+                _ => continue,
+            };
+
+            // Entries removed by another process while listing are skipped
+            let metadata = match file.metadata() {
+                Ok(x) if x.is_file() => x,
+                _ => continue,
+            };
+
+            let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+            let entry = CacheEntry {
+                key: key.replacen('-', ":", 1),
+                size: metadata.len(),
+                last_used: unix_time(modified),
+                path,
+            };
+            entries.push((modified, entry));
+        }
+
+        // Sort using the full precision of the modification time
+        entries.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.key.cmp(&b.1.key)));
+        Ok(entries.into_iter().map(|x| x.1).collect())
+    }
+
+    /// Remove the least recently used modules until the cache is no larger than its maximum
+    /// size, and remove temporary files left over by processes that exited while writing to the
+    /// cache. Returns the number of modules removed
+    pub fn prune(&self) -> Result<usize, Error> {
+        let dir = match &self.dir {
+            Some(dir) if dir.exists() => dir,
+            _ => return Ok(0),
+        };
+
+        for file in std::fs::read_dir(dir)?.flatten() {
+            if !file.file_name().to_string_lossy().starts_with(TMP_PREFIX) {
+                continue;
+            }
+
+            let age = file
+                .metadata()
+                .and_then(|x| x.modified())
+                .ok()
+                .and_then(|x| x.elapsed().ok());
+            if age.map(|x| x > TMP_MAX_AGE).unwrap_or(false) {
+                let _ = std::fs::remove_file(file.path());
+            }
+        }
+
+        let max_size = match self.max_size {
+            Some(x) => x,
+            None => return Ok(0),
+        };
+
+        let entries = self.entries()?;
+        let mut size: u64 = entries.iter().map(|x| x.size).sum();
+        let mut removed = 0;
+        for entry in entries {
+            if size <= max_size {
+                break;
+            }
+
+            // Another process may have already removed the entry
+            if std::fs::remove_file(&entry.path).is_ok() {
+                debug!("Evicted {} from the download cache", entry.key);
+                removed += 1;
+            }
+            size -= entry.size;
+        }
+
+        Ok(removed)
+    }
+
+    /// Remove every module from the cache, returns the number of modules removed
+    pub fn clear(&self) -> Result<usize, Error> {
+        let mut removed = 0;
+        for entry in self.entries()? {
+            if std::fs::remove_file(&entry.path).is_ok() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
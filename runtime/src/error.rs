@@ -0,0 +1,86 @@
+use crate::*;
+
+/// Identifies the kind of failure that caused the last error, this allows callers to handle
+/// errors without comparing error messages
+///
+/// cbindgen:prefix-with-name
+/// cbindgen:rename-all=ScreamingSnakeCase
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Default)]
+#[repr(C)]
+pub enum ErrorCode {
+    /// No error
+    #[default]
+    None,
+    /// An error that doesn't fit into any of the other categories
+    Unknown,
+    /// The plugin ID doesn't refer to a plugin in the context
+    PluginNotFound,
+    /// The plugin doesn't export the function being called
+    FunctionNotFound,
+    /// The call took longer than the timeout
+    Timeout,
+    /// The call was cancelled using a cancel handle
+    Cancelled,
+    /// The call used all of its fuel
+    OutOfFuel,
+    /// The WebAssembly code trapped
+    Trap,
+    /// The plugin called the WASI `proc_exit` function with a non-zero exit code
+    WasiExit,
+    /// A host function returned an error
+    HostFunctionError,
+    /// The plugin's memory couldn't be grown
+    OutOfMemory,
+    /// The manifest couldn't be parsed or refers to modules that can't be loaded
+    ManifestInvalid,
+    /// The hash of a module doesn't match the hash in the manifest
+    HashMismatch,
+    /// An HTTP request was made to a host that isn't in `allowed_hosts`
+    HttpDenied,
+    /// The variable store or an HTTP response is larger than the limit set in the manifest's
+    /// memory options
+    LimitExceeded,
+    /// A module isn't signed when signatures are required, or its signature is invalid
+    SignatureInvalid,
+}
+
+impl ErrorCode {
+    /// Create an error with this code
+    pub(crate) fn msg(self, msg: impl std::fmt::Display) -> Error {
+        Error::new(self.with(msg))
+    }
+
+    /// Create an error message with this code, this can be used as context for another error
+    pub(crate) fn with(self, msg: impl std::fmt::Display) -> Coded {
+        Coded(self, msg.to_string())
+    }
+
+    /// Get the code for an error
+    pub fn from_error(e: &Error) -> ErrorCode {
+        if let Some(Coded(code, _)) = e.downcast_ref::<Coded>() {
+            return *code;
+        }
+
+        if e.downcast_ref::<wasmtime_wasi::I32Exit>().is_some() {
+            return ErrorCode::WasiExit;
+        }
+
+        match e.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => ErrorCode::OutOfFuel,
+            Some(_) => ErrorCode::Trap,
+            None => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// An error message tagged with an `ErrorCode`
+#[derive(Debug)]
+pub(crate) struct Coded(ErrorCode, String);
+
+impl std::fmt::Display for Coded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+
+impl std::error::Error for Coded {}
@@ -1,7 +1,9 @@
 use crate::{Error, Internal};
 
 /// A list of all possible value types in WebAssembly.
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(C)]
 pub enum ValType {
     // NB: the ordering here is intended to match the ordering in
     // `wasmtime_types::WasmType` to help improve codegen when converting.
@@ -52,10 +54,11 @@ impl From<ValType> for wasmtime::ValType {
 }
 
 #[allow(clippy::type_complexity)]
+#[derive(Clone)]
 pub struct Function(
     pub(crate) String,
     pub(crate) wasmtime::FuncType,
-    pub(crate)  Box<
+    pub(crate)  std::sync::Arc<
         dyn Fn(
                 wasmtime::Caller<Internal>,
                 &[wasmtime::Val],
@@ -89,7 +92,7 @@ impl Function {
                 args.into_iter().map(wasmtime::ValType::from),
                 returns.into_iter().map(wasmtime::ValType::from),
             ),
-            Box::new(f),
+            std::sync::Arc::new(f),
         )
     }
 
@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::plugin::EXPORT_MODULE_NAME;
+use crate::*;
+
+/// The exports of a plugin's main module and the imports required by all of its modules
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PluginInfo {
+    pub exports: Vec<Export>,
+    pub imports: Vec<Import>,
+}
+
+/// The kind of value that is imported or exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExternKind {
+    Function,
+    Global,
+    Table,
+    Memory,
+}
+
+/// An export of the main module, `params` and `results` are only set for functions
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExternKind,
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+/// An import required by one of the plugin's modules, `params` and `results` are only set for
+/// functions
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Import {
+    /// Name of the module that requires the import
+    pub required_by: String,
+    pub module: String,
+    pub name: String,
+    pub kind: ExternKind,
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+    pub provider: ImportProvider,
+}
+
+/// Where the value for an import comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportProvider {
+    /// One of the `extism_*` functions provided by the runtime
+    Builtin,
+    /// A host function passed in when the plugin was created
+    HostFunction,
+    /// An export of another module from the manifest
+    Module,
+    /// WASI
+    Wasi,
+}
+
+/// Get the kind, parameters and results of an import or export
+fn describe(ty: ExternType) -> (ExternKind, Vec<ValType>, Vec<ValType>) {
+    match ty {
+        ExternType::Func(f) => (
+            ExternKind::Function,
+            f.params().map(ValType::from).collect(),
+            f.results().map(ValType::from).collect(),
+        ),
+        ExternType::Global(_) => (ExternKind::Global, vec![], vec![]),
+        ExternType::Table(_) => (ExternKind::Table, vec![], vec![]),
+        ExternType::Memory(_) => (ExternKind::Memory, vec![], vec![]),
+    }
+}
+
+/// Get the exports of a module
+pub(crate) fn exports(module: &Module) -> Vec<Export> {
+    module
+        .exports()
+        .map(|export| {
+            let (kind, params, results) = describe(export.ty());
+            Export {
+                name: export.name().to_string(),
+                kind,
+                params,
+                results,
+            }
+        })
+        .collect()
+}
+
+/// Get the imports of every module, `host_functions` are the names of the host functions the
+/// plugin was created with. The plugin has already been instantiated, so any import from `env`
+/// that isn't provided by another module or a host function is a builtin
+pub(crate) fn imports(
+    modules: &BTreeMap<String, Module>,
+    main_name: &str,
+    host_functions: &BTreeSet<&str>,
+) -> Vec<Import> {
+    // Modules other than `main` are linked using their own name and are also aliased to `env`
+    let module_exports = |name: &str| {
+        modules
+            .iter()
+            .filter(|(module_name, _)| module_name.as_str() != main_name)
+            .any(|(_, module)| module.get_export(name).is_some())
+    };
+
+    let mut imports = vec![];
+    for (required_by, module) in modules {
+        for import in module.imports() {
+            let name = import.name();
+            let provider = if import.module().starts_with("wasi") {
+                ImportProvider::Wasi
+            } else if import.module() != EXPORT_MODULE_NAME || module_exports(name) {
+                ImportProvider::Module
+            } else if host_functions.contains(name) {
+                ImportProvider::HostFunction
+            } else {
+                ImportProvider::Builtin
+            };
+
+            let (kind, params, results) = describe(import.ty());
+            imports.push(Import {
+                required_by: required_by.clone(),
+                module: import.module().to_string(),
+                name: name.to_string(),
+                kind,
+                params,
+                results,
+                provider,
+            });
+        }
+    }
+
+    imports
+}
@@ -1,22 +1,47 @@
 pub use anyhow::Error;
+pub use wasmtime::Val;
 pub(crate) use wasmtime::*;
 
+mod cache;
 mod context;
+mod credentials;
+mod current_plugin;
+mod download_cache;
+mod error;
 mod function;
+mod info;
+mod logger;
 pub mod manifest;
 mod memory;
 pub(crate) mod pdk;
 mod plugin;
 mod plugin_ref;
+mod pool;
 pub mod sdk;
+mod signature;
+mod snapshot;
 mod timer;
 
+pub use cache::{ModuleCache, ModuleCacheStats};
 pub use context::Context;
+pub(crate) use credentials::Credentials;
+pub use credentials::{CredentialCallback, CredentialProvider};
+pub use current_plugin::CurrentPlugin;
+pub use download_cache::{CacheEntry, DownloadCache};
+pub use error::ErrorCode;
 pub use function::{Function, ValType};
+pub use info::{Export, ExternKind, Import, ImportProvider, PluginInfo};
+pub use logger::{LogCallback, LogLevel, PLUGIN_LOG_TARGET};
 pub use manifest::Manifest;
-pub use memory::{MemoryBlock, PluginMemory};
-pub use plugin::{Internal, Plugin, Wasi};
+pub use memory::{
+    ExtismMemoryStats, GuestMemoryStats, MemoryBlock, MemoryStats, PluginMemory, ToMemoryBlock,
+    VarStoreStats,
+};
+pub use plugin::{CancelHandle, Internal, Plugin, Wasi};
 pub use plugin_ref::PluginRef;
+pub use pool::{PluginPool, PooledPlugin};
+pub(crate) use signature::TrustedKeys;
+pub(crate) use snapshot::Snapshot;
 pub(crate) use timer::{Timer, TimerAction};
 
 pub type Size = u64;
@@ -0,0 +1,150 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+
+use crate::*;
+
+/// Target used for messages logged by plugins, these records also include a `plugin` key with the
+/// ID of the plugin or `-1` if the plugin isn't in a context
+pub const PLUGIN_LOG_TARGET: &str = "extism_runtime::plugin";
+
+/// Logger installed by `Logger::set`, this forwards records to the current sink so the sink can
+/// be replaced after the logger is installed
+pub(crate) struct Logger;
+
+static LOGGER: Logger = Logger;
+static INSTALLED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+static SINK: std::sync::RwLock<Option<Box<dyn log::Log>>> = std::sync::RwLock::new(None);
+
+impl Logger {
+    /// Send log records to `sink`, replacing the previous sink. Returns `false` if another
+    /// logger was already installed by the host application
+    pub(crate) fn set(sink: Box<dyn log::Log>, level: log::LevelFilter) -> bool {
+        if !*INSTALLED.get_or_init(|| log::set_logger(&LOGGER).is_ok()) {
+            return false;
+        }
+
+        match SINK.write() {
+            Ok(mut x) => *x = Some(sink),
+            Err(e) => *e.into_inner() = Some(sink),
+        }
+        log::set_max_level(level);
+        true
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        match SINK.read() {
+            Ok(sink) => sink.as_ref().map(|x| x.enabled(metadata)).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Ok(sink) = SINK.read() {
+            if let Some(sink) = sink.as_ref() {
+                sink.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(sink) = SINK.read() {
+            if let Some(sink) = sink.as_ref() {
+                sink.flush();
+            }
+        }
+    }
+}
+
+/// Log level passed to an `ExtismLogCallback`
+///
+/// cbindgen:prefix-with-name
+/// cbindgen:rename-all=ScreamingSnakeCase
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[repr(C)]
+pub enum LogLevel {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<log::Level> for LogLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => LogLevel::Error,
+            log::Level::Warn => LogLevel::Warn,
+            log::Level::Info => LogLevel::Info,
+            log::Level::Debug => LogLevel::Debug,
+            log::Level::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+/// Called for each log message
+///
+/// `level`: the level of the message
+/// `target`: `extism_runtime::plugin` for messages logged by plugins, otherwise the module that
+/// logged the message
+/// `message`: the message
+/// `plugin`: the ID of the plugin that logged the message, or `-1`
+/// `user_data`: the pointer passed to `extism_log_custom`
+pub type LogCallback = extern "C" fn(
+    level: LogLevel,
+    target: *const c_char,
+    message: *const c_char,
+    plugin: PluginIndex,
+    user_data: *mut c_void,
+);
+
+/// Sink that passes records to a C callback
+pub(crate) struct CallbackSink {
+    pub(crate) callback: LogCallback,
+    pub(crate) user_data: *mut c_void,
+    pub(crate) level: log::LevelFilter,
+}
+
+// The embedder is responsible for making `user_data` safe to use from any thread
+unsafe impl Send for CallbackSink {}
+unsafe impl Sync for CallbackSink {}
+
+impl log::Log for CallbackSink {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // Only messages from the runtime and plugins are passed to the callback, to match the
+        // behavior of `extism_log_file`
+        metadata.target().starts_with("extism_runtime") && metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let plugin = record
+            .key_values()
+            .get(log::kv::Key::from("plugin"))
+            .and_then(|x| x.to_i64())
+            .unwrap_or(-1);
+        let target = c_string(record.target());
+        let message = c_string(&record.args().to_string());
+        (self.callback)(
+            record.level().into(),
+            target.as_ptr(),
+            message.as_ptr(),
+            plugin as PluginIndex,
+            self.user_data,
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+/// Convert a string to a `CString`, removing any NUL bytes
+fn c_string(s: &str) -> CString {
+    match CString::new(s) {
+        Ok(x) => x,
+        Err(_) => CString::new(s.replace('\0', "")).unwrap_or_default(),
+    }
+}
@@ -5,13 +5,14 @@ use std::io::Read;
 use sha2::Digest;
 
 use crate::*;
+use extism_manifest::{Checksum, HashAlgorithm};
 
 /// Manifest wraps the manifest exported by `extism_manifest`
-#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(transparent)]
 pub struct Manifest(extism_manifest::Manifest);
 
-fn hex(data: &[u8]) -> String {
+pub(crate) fn hex(data: &[u8]) -> String {
     let mut s = String::new();
     for &byte in data {
         write!(&mut s, "{:02x}", byte).unwrap();
@@ -19,63 +20,161 @@ fn hex(data: &[u8]) -> String {
     s
 }
 
-#[allow(unused)]
-fn cache_add_file(hash: &str, data: &[u8]) -> Result<(), Error> {
-    let cache_dir = std::env::temp_dir().join("exitsm-cache");
-    let _ = std::fs::create_dir(&cache_dir);
-    let file = cache_dir.join(hash);
-    if file.exists() {
-        return Ok(());
+/// Parse the hash from a module's metadata
+fn parse_hash(hash: &Option<String>) -> Result<Option<Checksum>, Error> {
+    match hash {
+        None => Ok(None),
+        Some(hash) => match hash.parse() {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => Err(ErrorCode::ManifestInvalid.msg(e)),
+        },
     }
-    std::fs::write(file, data)?;
-    Ok(())
 }
 
-fn cache_get_file(hash: &str) -> Result<Option<Vec<u8>>, Error> {
-    let cache_dir = std::env::temp_dir().join("exitsm-cache");
-    let file = cache_dir.join(hash);
-    if file.exists() {
-        let r = std::fs::read(file)?;
-        return Ok(Some(r));
+/// Get the hex encoded digest of `data`
+fn digest(algorithm: HashAlgorithm, data: &[u8]) -> String {
+    match algorithm {
+        HashAlgorithm::Sha256 => hex(&sha2::Sha256::digest(data)),
+        HashAlgorithm::Sha512 => hex(&sha2::Sha512::digest(data)),
+        HashAlgorithm::Blake3 => hex(blake3::hash(data).as_bytes()),
     }
-
-    Ok(None)
 }
 
 fn check_hash(hash: &Option<String>, data: &[u8]) -> Result<(), Error> {
-    match hash {
-        None => Ok(()),
-        Some(hash) => {
-            let digest = sha2::Sha256::digest(data);
-            let hex = hex(&digest);
-            if &hex != hash {
-                return Err(anyhow::format_err!(
-                    "Hash mismatch, found {} but expected {}",
-                    hex,
-                    hash
-                ));
+    let expected = match parse_hash(hash)? {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+
+    let hex = digest(expected.algorithm, data);
+    if hex != expected.hex {
+        return Err(ErrorCode::HashMismatch.msg(format!(
+            "Hash mismatch, found {}:{hex} but expected {expected}",
+            expected.algorithm.name()
+        )));
+    }
+    Ok(())
+}
+
+/// Used when `HttpRequest::timeout_ms` isn't set
+#[cfg(feature = "register-http")]
+const DEFAULT_FETCH_TIMEOUT_MS: u64 = 30_000;
+
+/// Used when `HttpRequest::retry_backoff_ms` isn't set
+#[cfg(feature = "register-http")]
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 500;
+
+/// Replace the environment variable and credential references in the headers of a request
+#[cfg(feature = "register-http")]
+fn resolve_headers(
+    req: &extism_manifest::HttpRequest,
+    credentials: &Credentials,
+) -> Result<Vec<(String, String)>, Error> {
+    let mut headers = Vec::with_capacity(req.headers.len());
+    for (k, v) in &req.headers {
+        let value = extism_manifest::expand_header(v, |source, name| {
+            credentials.resolve(source, name, &req.url)
+        })
+        .map_err(|e| {
+            ErrorCode::ManifestInvalid.msg(format!("Invalid value for header {k}: {e}"))
+        })?;
+        headers.push((k.clone(), value));
+    }
+    Ok(headers)
+}
+
+/// Download a module, retrying after connection errors, timeouts and responses that indicate the
+/// server is temporarily unavailable
+#[cfg(feature = "register-http")]
+fn fetch(
+    req: &extism_manifest::HttpRequest,
+    method: &str,
+    headers: &[(String, String)],
+) -> Result<Vec<u8>, Error> {
+    let timeout = req.timeout_ms.unwrap_or(DEFAULT_FETCH_TIMEOUT_MS);
+    use extism_manifest::HttpRequest;
+
+    let retries = req.retries.unwrap_or(0);
+    if retries > HttpRequest::MAX_RETRIES {
+        return Err(ErrorCode::ManifestInvalid.msg(format!(
+            "Invalid retries for {}: {retries}, the maximum is {}",
+            req.url,
+            HttpRequest::MAX_RETRIES
+        )));
+    }
+
+    let max_backoff = std::time::Duration::from_millis(HttpRequest::MAX_RETRY_BACKOFF_MS);
+    let mut backoff =
+        std::time::Duration::from_millis(req.retry_backoff_ms.unwrap_or(DEFAULT_RETRY_BACKOFF_MS))
+            .min(max_backoff);
+    let agent = ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_millis(timeout))
+        .build();
+
+    let mut attempt = 0;
+    loop {
+        let mut r = agent.request(method, &req.url);
+        for (k, v) in headers {
+            r = r.set(k, v);
+        }
+
+        let (e, retry): (Error, bool) = match r.call() {
+            Ok(res) => {
+                let mut data = Vec::new();
+                match res.into_reader().read_to_end(&mut data) {
+                    Ok(_) => return Ok(data),
+                    Err(e) => (e.into(), true),
+                }
+            }
+            Err(ureq::Error::Status(status, res)) => {
+                let retry = status == 408 || status == 429 || status >= 500;
+                (ureq::Error::Status(status, res).into(), retry)
             }
-            Ok(())
+            // Only errors that may succeed when the request is repeated are retried, not invalid
+            // URLs or failed DNS lookups
+            Err(e) => {
+                let retry = matches!(
+                    e.kind(),
+                    ureq::ErrorKind::ConnectionFailed
+                        | ureq::ErrorKind::Io
+                        | ureq::ErrorKind::ProxyConnect
+                );
+                (e.into(), retry)
+            }
+        };
+
+        if !retry || attempt >= retries {
+            return Err(e.context(format!("Unable to download {}", req.url)));
         }
+
+        attempt += 1;
+        debug!(
+            "Retrying download of {} in {backoff:?} ({attempt}/{retries}): {e:#}",
+            req.url
+        );
+        std::thread::sleep(backoff);
+        backoff = backoff.saturating_mul(2).min(max_backoff);
     }
 }
 
-/// Convert from manifest to a wasmtime Module
-fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, Module), Error> {
+/// Convert from manifest to a wasmtime Module, returns the module name, the module and the
+/// SHA-256 of its code
+#[cfg_attr(not(feature = "register-http"), allow(unused_variables))]
+fn to_module(
+    engine: &Engine,
+    cache: &ModuleCache,
+    keys: &TrustedKeys,
+    downloads: &DownloadCache,
+    credentials: &Credentials,
+    wasm: &extism_manifest::Wasm,
+) -> Result<(String, Module, String), Error> {
     match wasm {
         extism_manifest::Wasm::File { path, meta } => {
             if cfg!(not(feature = "register-filesystem")) {
                 return Err(anyhow::format_err!("File-based registration is disabled"));
             }
 
-            // Figure out a good name for the file
-            let name = match &meta.name {
-                None => {
-                    let name = path.with_extension("");
-                    name.file_name().unwrap().to_string_lossy().to_string()
-                }
-                Some(n) => n.clone(),
-            };
+            let name = wasm.module_name();
 
             // Load file
             let mut buf = Vec::new();
@@ -83,49 +182,32 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
             file.read_to_end(&mut buf)?;
 
             check_hash(&meta.hash, &buf)?;
+            keys.verify(meta, &buf)?;
 
-            Ok((name, Module::new(engine, buf)?))
+            let (module, hash) = cache.compile_with_hash(engine, buf)?;
+            Ok((name, module, hash))
         }
         extism_manifest::Wasm::Data { meta, data } => {
             check_hash(&meta.hash, data)?;
-            Ok((
-                meta.name.as_deref().unwrap_or("main").to_string(),
-                Module::new(engine, data)?,
-            ))
+            keys.verify(meta, data)?;
+            let (module, hash) = cache.compile_with_hash(engine, data)?;
+            Ok((wasm.module_name(), module, hash))
         }
         #[allow(unused)]
-        extism_manifest::Wasm::Url {
-            req:
-                extism_manifest::HttpRequest {
-                    url,
-                    headers,
-                    method,
-                },
-            meta,
-        } => {
-            // Get the file name
-            let file_name = url.split('/').last().unwrap_or_default();
-            let name = match &meta.name {
-                Some(name) => name.as_str(),
-                None => {
-                    let mut name = "main";
-                    if let Some(n) = file_name.strip_suffix(".wasm") {
-                        name = n;
-                    }
-
-                    if let Some(n) = file_name.strip_suffix(".wast") {
-                        name = n;
-                    }
-                    name
-                }
+        extism_manifest::Wasm::Url { req, meta } => {
+            let name = wasm.module_name();
+
+            let method = req.method.as_deref().unwrap_or("GET");
+            let key = match parse_hash(&meta.hash)? {
+                Some(checksum) => Some(DownloadCache::checksum_key(&checksum)),
+                None if downloads.cache_unhashed() => DownloadCache::url_key(method, req),
+                None => None,
             };
-
-            if let Some(h) = &meta.hash {
-                if let Ok(Some(data)) = cache_get_file(h) {
-                    check_hash(&meta.hash, &data)?;
-                    let module = Module::new(engine, data)?;
-                    return Ok((name.to_string(), module));
-                }
+            if let Some(data) = key.as_deref().and_then(|key| downloads.get(key)) {
+                check_hash(&meta.hash, &data)?;
+                keys.verify(meta, &data)?;
+                let (module, hash) = cache.compile_with_hash(engine, data)?;
+                return Ok((name, module, hash));
             }
 
             #[cfg(not(feature = "register-http"))]
@@ -135,28 +217,24 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
 
             #[cfg(feature = "register-http")]
             {
-                // Setup request
-                let mut req = ureq::request(method.as_deref().unwrap_or("GET"), url);
+                // Credentials are only resolved when the module isn't in the download cache
+                let headers = resolve_headers(req, credentials)?;
+                let data = fetch(req, method, &headers)?;
 
-                for (k, v) in headers.iter() {
-                    req = req.set(k, v);
-                }
-
-                // Fetch WASM code
-                let mut r = req.call()?.into_reader();
-                let mut data = Vec::new();
-                r.read_to_end(&mut data)?;
+                check_hash(&meta.hash, &data)?;
+                keys.verify(meta, &data)?;
 
-                // Try to cache file
-                if let Some(hash) = &meta.hash {
-                    cache_add_file(hash, &data);
+                // Modules are only cached once the hash has been checked, failing to cache a
+                // module doesn't stop it from being loaded
+                if let Some(key) = &key {
+                    if let Err(e) = downloads.add(key, &data) {
+                        debug!("Unable to add {} to the download cache: {e:?}", req.url);
+                    }
                 }
 
-                check_hash(&meta.hash, &data)?;
-
                 // Convert fetched data to module
-                let module = Module::new(engine, data)?;
-                Ok((name.to_string(), module))
+                let (module, hash) = cache.compile_with_hash(engine, data)?;
+                Ok((name, module, hash))
             }
         }
     }
@@ -164,48 +242,136 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
 
 const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
 
+/// Check whether `data` is a WebAssembly module instead of a manifest
+fn is_module(data: &[u8]) -> bool {
+    let has_magic = data.len() >= 4 && data[0..4] == WASM_MAGIC;
+    let is_wast = data.starts_with(b"(module") || data.starts_with(b";;");
+    has_magic || is_wast
+}
+
 impl Manifest {
     /// Create a new Manifest, returns the manifest and a map of modules
-    pub fn new(engine: &Engine, data: &[u8]) -> Result<(Self, BTreeMap<String, Module>), Error> {
-        let has_magic = data.len() >= 4 && data[0..4] == WASM_MAGIC;
-        let is_wast = data.starts_with(b"(module") || data.starts_with(b";;");
-        if !has_magic && !is_wast {
-            if let Ok(t) = toml::from_slice::<Self>(data) {
-                let m = t.modules(engine)?;
-                return Ok((t, m));
-            }
+    ///
+    /// Modules that have already been compiled are loaded from `cache`, newly compiled
+    /// modules are added to it. Module signatures aren't required, use `Plugin::new` to apply
+    /// the context's signature policy
+    pub fn new(
+        engine: &Engine,
+        cache: &ModuleCache,
+        data: &[u8],
+    ) -> Result<(Self, BTreeMap<String, Module>), Error> {
+        let (manifest, modules, _) = Self::load(
+            engine,
+            cache,
+            &TrustedKeys::default(),
+            &DownloadCache::default(),
+            &Credentials::default(),
+            data,
+        )?;
+        Ok((manifest, modules))
+    }
 
-            let t = serde_json::from_slice::<Self>(data)?;
-            let m = t.modules(engine)?;
-            return Ok((t, m));
+    /// Same as `Manifest::new`, but module signatures are checked using `keys`, downloaded
+    /// modules are cached in `downloads` and `credentials` provides the values of references in
+    /// download headers. The SHA-256 of the main module's code is also returned
+    pub(crate) fn load(
+        engine: &Engine,
+        cache: &ModuleCache,
+        keys: &TrustedKeys,
+        downloads: &DownloadCache,
+        credentials: &Credentials,
+        data: &[u8],
+    ) -> Result<(Self, BTreeMap<String, Module>, String), Error> {
+        if !is_module(data) {
+            let t = Self::parse(data)
+                .map_err(|e| e.context(ErrorCode::ManifestInvalid.with("Invalid manifest")))?;
+            let (m, hash) = t.modules(engine, cache, keys, downloads, credentials)?;
+            return Ok((t, m, hash));
         }
 
-        let m = Module::new(engine, data)?;
+        // Modules passed without a manifest can't be signed
+        keys.verify(&Default::default(), data)?;
+        let (m, hash) = cache.compile_with_hash(engine, data)?;
         let mut modules = BTreeMap::new();
         modules.insert("main".to_string(), m);
-        Ok((Manifest::default(), modules))
+        Ok((Manifest::default(), modules, hash))
+    }
+
+    /// Parse a JSON or TOML manifest, JSON is used when the first non-whitespace character is
+    /// `{` so the error from the right parser is reported
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        let is_json = data.iter().find(|x| !x.is_ascii_whitespace()) == Some(&b'{');
+        if is_json {
+            Ok(serde_json::from_slice(data)?)
+        } else {
+            Ok(toml::from_slice(data)?)
+        }
     }
 
-    fn modules(&self, engine: &Engine) -> Result<BTreeMap<String, Module>, Error> {
+    /// Parse and validate a manifest without loading any modules, a WebAssembly module is always
+    /// valid. See `extism_manifest::Manifest::validate`
+    pub fn validate(data: &[u8]) -> Vec<extism_manifest::Diagnostic> {
+        if is_module(data) {
+            return vec![];
+        }
+
+        match Self::parse(data) {
+            Ok(t) => t.0.validate(),
+            Err(e) => vec![extism_manifest::Diagnostic::new(
+                extism_manifest::DiagnosticKind::Parse,
+                "",
+                format!("Invalid manifest: {e}"),
+            )],
+        }
+    }
+
+    fn modules(
+        &self,
+        engine: &Engine,
+        cache: &ModuleCache,
+        keys: &TrustedKeys,
+        downloads: &DownloadCache,
+        credentials: &Credentials,
+    ) -> Result<(BTreeMap<String, Module>, String), Error> {
         if self.0.wasm.is_empty() {
-            return Err(anyhow::format_err!("No wasm files specified"));
+            return Err(ErrorCode::ManifestInvalid.msg("No wasm files specified"));
         }
 
         let mut modules = BTreeMap::new();
 
         // If there's only one module, it should be called `main`
         if self.0.wasm.len() == 1 {
-            let (_, m) = to_module(engine, &self.0.wasm[0])?;
+            let (_, m, hash) =
+                to_module(engine, cache, keys, downloads, credentials, &self.0.wasm[0])?;
             modules.insert("main".to_string(), m);
-            return Ok(modules);
+            return Ok((modules, hash));
+        }
+
+        // Names are checked before anything is loaded, a module with the same name as another
+        // module would replace it
+        let mut names = std::collections::BTreeSet::new();
+        for f in &self.0.wasm {
+            let name = f.module_name();
+            if !names.insert(name.clone()) {
+                return Err(
+                    ErrorCode::ManifestInvalid.msg(format!("Duplicate module name: {name}"))
+                );
+            }
         }
 
+        let mut hashes = BTreeMap::new();
         for f in &self.0.wasm {
-            let (name, m) = to_module(engine, f)?;
-            modules.insert(name, m);
+            let (name, m, hash) = to_module(engine, cache, keys, downloads, credentials, f)?;
+            modules.insert(name.clone(), m);
+            hashes.insert(name, hash);
         }
 
-        Ok(modules)
+        // Same as the main module used by `Plugin::instantiate`
+        let hash = match hashes.remove("main") {
+            Some(hash) => hash,
+            None => hashes.into_values().last().unwrap(),
+        };
+        Ok((modules, hash))
     }
 }
 
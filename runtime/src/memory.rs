@@ -11,6 +11,60 @@ pub struct PluginMemory {
     pub live_blocks: BTreeMap<usize, usize>,
     pub free: Vec<MemoryBlock>,
     pub position: usize,
+
+    /// Largest value of `position` since the memory was created, this isn't cleared by `reset`
+    pub high_water_mark: usize,
+}
+
+/// Memory usage of a plugin
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MemoryStats {
+    /// Memory used for inputs, outputs and blocks allocated using `extism_alloc`
+    pub extism: ExtismMemoryStats,
+    /// Memory exported by the main module as `memory`, this is `None` if the module doesn't
+    /// export a memory
+    pub guest: Option<GuestMemoryStats>,
+    pub vars: VarStoreStats,
+    /// Responses larger than this cause `extism_http_request` to fail
+    pub max_http_response_bytes: u64,
+}
+
+/// Usage of the plugin's variables
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VarStoreStats {
+    pub count: usize,
+    /// Total size of the values in bytes
+    pub bytes: usize,
+    /// Limit on `bytes`, set using `max_var_bytes` in the manifest's memory options
+    pub max_bytes: u64,
+}
+
+/// Usage of the memory managed by `PluginMemory`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ExtismMemoryStats {
+    pub pages: u32,
+    pub max_pages: Option<u32>,
+    /// Number of bytes in blocks that haven't been freed
+    pub live_bytes: usize,
+    pub live_blocks: usize,
+    /// Number of bytes in the free-list
+    pub free_bytes: usize,
+    pub free_blocks: usize,
+    /// `1 - largest_free_block / free_bytes`, `0` means all free bytes are in a single block
+    pub fragmentation: f64,
+    /// Largest number of bytes allocated at once since the plugin was created
+    pub high_water_mark: usize,
+}
+
+/// Usage of the plugin's own memory, the allocator used by the plugin isn't known so only the
+/// size is reported
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GuestMemoryStats {
+    pub pages: u32,
+    pub max_pages: Option<u32>,
+    /// Largest size in bytes since the plugin was created, this includes memory used by
+    /// previous instances when the plugin has been reinstantiated
+    pub high_water_mark: usize,
 }
 
 pub trait ToMemoryBlock {
@@ -56,6 +110,7 @@ impl PluginMemory {
             store,
             memory,
             position: 1,
+            high_water_mark: 1,
         }
     }
 
@@ -175,7 +230,11 @@ impl PluginMemory {
 
             info!("Requesting {pages_needed} more pages");
             // This will fail if we've already allocated the maximum amount of memory allowed
-            self.memory.grow(&mut self.store, pages_needed)?;
+            if let Err(e) = self.memory.grow(&mut self.store, pages_needed) {
+                return Err(e.context(
+                    ErrorCode::OutOfMemory.with(format!("Unable to allocate {n} bytes")),
+                ));
+            }
         }
 
         let mem = MemoryBlock {
@@ -190,6 +249,7 @@ impl PluginMemory {
 
         self.live_blocks.insert(mem.offset, mem.length);
         self.position += n;
+        self.high_water_mark = self.high_water_mark.max(self.position);
         Ok(mem)
     }
 
@@ -249,6 +309,28 @@ impl PluginMemory {
         self.position = 1;
     }
 
+    /// Get the current usage of the memory
+    pub fn stats(&self) -> ExtismMemoryStats {
+        let free_bytes: usize = self.free.iter().map(|x| x.length).sum();
+        let largest_free = self.free.iter().map(|x| x.length).max().unwrap_or(0);
+        let fragmentation = if free_bytes == 0 {
+            0.0
+        } else {
+            1.0 - largest_free as f64 / free_bytes as f64
+        };
+
+        ExtismMemoryStats {
+            pages: self.pages(),
+            max_pages: self.memory.ty(&self.store).maximum().map(|x| x as u32),
+            live_bytes: self.live_blocks.values().sum(),
+            live_blocks: self.live_blocks.len(),
+            free_bytes,
+            free_blocks: self.free.len(),
+            fragmentation,
+            high_water_mark: self.high_water_mark,
+        }
+    }
+
     /// Get memory as a slice of bytes
     pub fn data(&self) -> &[u8] {
         self.memory.data(&self.store)
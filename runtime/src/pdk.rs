@@ -253,33 +253,20 @@ pub(crate) fn var_set(
     let data: &mut Internal = caller.data_mut();
     let plugin = data.plugin_mut();
 
-    let mut size = 0;
-    for v in plugin.vars.values() {
-        size += v.len();
-    }
-
-    let voffset = args!(input, 1, i64) as usize;
-
-    // If the store is larger than 100MB then stop adding things
-    if size > 1024 * 1024 * 100 && voffset != 0 {
-        return Err(Error::msg("Variable store is full"));
-    }
-
     let key_offs = args!(input, 0, i64) as usize;
-    let key = plugin.memory.get_str(key_offs)?;
+    let key = plugin.memory.get_str(key_offs)?.to_string();
+    let voffset = args!(input, 1, i64) as usize;
 
     // Remove if the value offset is 0
     if voffset == 0 {
-        plugin.vars.remove(key);
+        plugin.vars.remove(&key);
         return Ok(());
     }
 
-    let value = plugin.memory.get(voffset)?;
+    let value = plugin.memory.get(voffset)?.to_vec();
 
     // Insert the value from memory into the `vars` map
-    plugin.vars.insert(key.to_string(), value.to_vec());
-
-    Ok(())
+    plugin.set_var(key, value)
 }
 
 /// Make an HTTP request
@@ -314,45 +301,86 @@ pub(crate) fn http_request(
             Ok(u) => u,
             Err(e) => return Err(Error::msg(format!("Invalid URL: {e:?}"))),
         };
-        let allowed_hosts = &data.plugin().manifest.as_ref().allowed_hosts;
-        let host_str = url.host_str().unwrap_or_default();
-        if let Some(allowed_hosts) = allowed_hosts {
-            let host_matches_allowed = allowed_hosts.iter().any(|url| {
-                let pat = match glob::Pattern::new(url) {
-                    Ok(x) => x,
-                    Err(_) => return url == host_str,
-                };
-
-                pat.matches(host_str)
-            });
-            if !host_matches_allowed {
-                return Err(Error::msg(format!(
-                    "HTTP request to {} is not allowed",
-                    req.url
-                )));
+        let mut method = req.method.as_deref().unwrap_or("GET").to_string();
+        let mut body = if body_offset > 0 {
+            Some(data.memory().get(body_offset)?.to_vec())
+        } else {
+            None
+        };
+
+        // Redirects are followed here instead of by ureq, so every URL is checked against
+        // `allowed_hosts`
+        let agent = ureq::AgentBuilder::new().redirects(0).build();
+        let mut url = url;
+        let mut redirects = 0;
+        let res = loop {
+            let allowed_hosts = &data.plugin().manifest.as_ref().allowed_hosts;
+            if let Some(allowed_hosts) = allowed_hosts {
+                if !allowed_hosts.iter().any(|x| host_allowed(x, &url, &method)) {
+                    return Err(ErrorCode::HttpDenied
+                        .msg(format!("HTTP {method} request to {url} is not allowed")));
+                }
             }
-        }
 
-        let mut r = ureq::request(req.method.as_deref().unwrap_or("GET"), &req.url);
+            let mut r = agent.request_url(&method, &url);
+            for (k, v) in req.headers.iter() {
+                // Credentials aren't sent to the target of a redirect
+                let secret =
+                    k.eq_ignore_ascii_case("authorization") || k.eq_ignore_ascii_case("cookie");
+                if redirects == 0 || !secret {
+                    r = r.set(k, v);
+                }
+            }
 
-        for (k, v) in req.headers.iter() {
-            r = r.set(k, v);
-        }
+            let res = match &body {
+                Some(body) => r.send_bytes(body)?,
+                None => r.call()?,
+            };
+
+            // Follow redirects the same way ureq does, 301, 302 and 303 change the method to
+            // `GET` and 307 and 308 are only followed for methods that can't have a body
+            let location = match res.status() {
+                301..=303 | 307 | 308 => res.header("location"),
+                _ => None,
+            };
+            let location = match location {
+                Some(x) if redirects < MAX_HTTP_REDIRECTS => x,
+                Some(_) => {
+                    return Err(Error::msg(format!(
+                        "Too many redirects, the limit is {MAX_HTTP_REDIRECTS}"
+                    )))
+                }
+                None => break res,
+            };
+            match res.status() {
+                301..=303 if method != "GET" && method != "HEAD" => {
+                    method = "GET".to_string();
+                    body = None;
+                }
+                307 | 308 if !["GET", "HEAD", "OPTIONS", "TRACE"].contains(&method.as_str()) => {
+                    break res
+                }
+                _ => (),
+            }
 
-        let res = if body_offset > 0 {
-            let buf = data.memory().get(body_offset)?;
-            let res = r.send_bytes(buf)?;
-            data.http_status = res.status();
-            res.into_reader()
-        } else {
-            let res = r.call()?;
-            data.http_status = res.status();
-            res.into_reader()
+            url = url
+                .join(location)
+                .map_err(|e| Error::msg(format!("Invalid redirect URL: {e:?}")))?;
+            redirects += 1;
         };
+        data.http_status = res.status();
+        let res = res.into_reader();
 
+        // Read one byte past the limit to detect responses that are too large
+        let limit = data.plugin().max_http_response_bytes();
         let mut buf = Vec::new();
-        res.take(1024 * 1024 * 50) // TODO: make this limit configurable
-            .read_to_end(&mut buf)?;
+        res.take(limit.saturating_add(1)).read_to_end(&mut buf)?;
+        if buf.len() as u64 > limit {
+            return Err(ErrorCode::LimitExceeded.msg(format!(
+                "HTTP response from {} is larger than the limit of {limit} bytes",
+                req.url
+            )));
+        }
 
         let mem = data.memory_mut().alloc_bytes(buf)?;
 
@@ -361,6 +389,61 @@ pub(crate) fn http_request(
     }
 }
 
+/// Maximum number of redirects followed by `http_request`
+#[cfg(feature = "http")]
+const MAX_HTTP_REDIRECTS: usize = 5;
+
+/// Check whether a request matches an entry in `allowed_hosts`
+#[cfg(feature = "http")]
+fn host_allowed(allowed: &extism_manifest::AllowedHost, url: &url::Url, method: &str) -> bool {
+    let host_str = url.host_str().unwrap_or_default();
+    let pattern = allowed.host();
+    let host_matches = match glob::Pattern::new(pattern) {
+        Ok(x) => x.matches(host_str),
+        Err(_) => pattern == host_str,
+    };
+
+    if !host_matches {
+        return false;
+    }
+
+    let rule = match allowed {
+        extism_manifest::AllowedHost::Host(_) => return true,
+        extism_manifest::AllowedHost::Rule(rule) => rule,
+    };
+
+    if let Some(scheme) = &rule.scheme {
+        if !scheme.eq_ignore_ascii_case(url.scheme()) {
+            return false;
+        }
+    }
+
+    if let Some(port) = rule.port {
+        if url.port_or_known_default() != Some(port) {
+            return false;
+        }
+    }
+
+    if let Some(prefix) = &rule.path_prefix {
+        let path = url.path();
+        let matches = match path.strip_prefix(prefix.as_str()) {
+            Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        };
+        if !matches {
+            return false;
+        }
+    }
+
+    if let Some(methods) = &rule.methods {
+        if !methods.iter().any(|x| x.eq_ignore_ascii_case(method)) {
+            return false;
+        }
+    }
+
+    true
+}
+
 /// Get the status code of the last HTTP request
 /// Params: none
 /// Returns: i32 (status code)
@@ -405,10 +488,11 @@ pub fn log(
     let data: &Internal = caller.data();
     let offset = args!(input, 0, i64) as usize;
     let buf = data.memory().get(offset)?;
+    let id = data.plugin().id;
 
     match std::str::from_utf8(buf) {
-        Ok(buf) => log::log!(level, "{}", buf),
-        Err(_) => log::log!(level, "{:?}", buf),
+        Ok(buf) => log::log!(target: PLUGIN_LOG_TARGET, level, plugin = id; "{}", buf),
+        Err(_) => log::log!(target: PLUGIN_LOG_TARGET, level, plugin = id; "{:?}", buf),
     }
     Ok(())
 }
@@ -8,11 +8,59 @@ pub struct Plugin {
     pub linker: Linker<Internal>,
     pub instance: Instance,
     pub last_error: std::cell::RefCell<Option<std::ffi::CString>>,
+    pub last_error_code: std::cell::Cell<ErrorCode>,
     pub memory: PluginMemory,
     pub manifest: Manifest,
     pub vars: BTreeMap<String, Vec<u8>>,
     pub should_reinstantiate: bool,
     pub timer_id: uuid::Uuid,
+
+    /// ID of the plugin in its context, or `-1` if the plugin hasn't been added to a context
+    pub(crate) id: PluginIndex,
+
+    /// Imports required by the plugin's modules, see `Plugin::info`
+    pub(crate) imports: Vec<Import>,
+
+    /// Host functions the plugin was created with, these are re-used when the plugin is updated
+    pub(crate) functions: Vec<Function>,
+
+    /// Compiled modules the plugin was created with, used by `Plugin::reinstantiate`
+    modules: BTreeMap<String, Module>,
+
+    /// SHA-256 of the main module's code, used to check that a snapshot belongs to this plugin
+    pub(crate) hash: String,
+
+    /// Largest size of the guest memory used by previous instances, see `Plugin::memory_stats`
+    guest_high_water_mark: usize,
+
+    /// Amount of fuel consumed by the last call, this is always `0` if the context doesn't
+    /// meter fuel
+    pub fuel_consumed: u64,
+    fuel_start: u64,
+
+    /// Set when the plugin was created in a context returned by `Context::new_async`
+    pub(crate) async_support: bool,
+
+    /// Used to cancel running calls, clones of the handle can outlive the plugin
+    pub(crate) cancel_handle: CancelHandle,
+}
+
+/// Used to cancel a running plugin call from another thread
+#[derive(Clone)]
+pub struct CancelHandle {
+    engine: Engine,
+    cancelled: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl CancelHandle {
+    /// Cancel the current call, if no call is running the next call is cancelled before it
+    /// starts
+    pub fn cancel(&self) {
+        trace!("Cancelling plugin call");
+        self.cancelled
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.engine.increment_epoch();
+    }
 }
 
 pub struct Internal {
@@ -23,8 +71,17 @@ pub struct Internal {
     pub plugin: *mut Plugin,
     pub wasi: Option<Wasi>,
     pub http_status: u16,
+    pub(crate) deadline: Option<std::time::Instant>,
+    pub(crate) cancelled: std::sync::Arc<std::sync::atomic::AtomicBool>,
+
+    /// Set by `extism_current_plugin_set_error` to fail the host function that's running
+    pub(crate) host_error: Option<String>,
 }
 
+// `input` and `plugin` are only dereferenced during a call, while the plugin is borrowed by the
+// thread making the call, so it's safe to move `Internal` to another thread between calls
+unsafe impl Send for Internal {}
+
 pub struct Wasi {
     pub ctx: wasmtime_wasi::WasiCtx,
     #[cfg(feature = "nn")]
@@ -72,6 +129,9 @@ impl Internal {
             wasi,
             plugin: std::ptr::null_mut(),
             http_status: 0,
+            deadline: None,
+            cancelled: Default::default(),
+            host_error: None,
         })
     }
 
@@ -92,26 +152,81 @@ impl Internal {
     }
 }
 
-const EXPORT_MODULE_NAME: &str = "env";
+pub(crate) const EXPORT_MODULE_NAME: &str = "env";
 
 impl Plugin {
-    /// Create a new plugin from the given WASM code
-    pub fn new(wasm: impl AsRef<[u8]>, with_wasi: bool) -> Result<Plugin, Error> {
-        Self::new_with_functions(wasm, [], with_wasi)
+    /// Create a new plugin from the given WASM code, using the engine and module cache
+    /// owned by `ctx`
+    pub fn new(ctx: &Context, wasm: impl AsRef<[u8]>, with_wasi: bool) -> Result<Plugin, Error> {
+        Self::new_with_functions(ctx, wasm, [], with_wasi)
     }
 
     /// Create a new plugin from the given WASM code and imported functions
     pub fn new_with_functions(
+        ctx: &Context,
         wasm: impl AsRef<[u8]>,
         imports: impl IntoIterator<Item = Function>,
         with_wasi: bool,
     ) -> Result<Plugin, Error> {
-        let engine = Engine::new(Config::new().epoch_interruption(true))?;
-        let mut imports = imports.into_iter();
-        let (manifest, modules) = Manifest::new(&engine, wasm.as_ref())?;
-        let mut store = Store::new(&engine, Internal::new(&manifest, with_wasi)?);
+        let engine = ctx.engine.clone();
+        let (manifest, modules, hash) = ctx.load_manifest(wasm.as_ref())?;
+        let imports: Vec<Function> = imports.into_iter().collect();
+        Self::instantiate(
+            &engine,
+            ctx.async_support,
+            manifest,
+            &modules,
+            hash,
+            &imports,
+            with_wasi,
+        )
+    }
 
-        store.epoch_deadline_callback(|_internal| Err(Error::msg("timeout")));
+    /// Create a new plugin from modules that have already been compiled
+    pub(crate) fn instantiate(
+        engine: &Engine,
+        async_support: bool,
+        manifest: Manifest,
+        modules: &BTreeMap<String, Module>,
+        hash: String,
+        functions: &[Function],
+        with_wasi: bool,
+    ) -> Result<Plugin, Error> {
+        let host_functions: std::collections::BTreeSet<&str> =
+            functions.iter().map(|f| f.name()).collect();
+        let mut imports = functions.iter();
+        let mut store = Store::new(engine, Internal::new(&manifest, with_wasi)?);
+        if manifest.as_ref().fuel_limit.is_some() && store.fuel_consumed().is_none() {
+            return Err(ErrorCode::ManifestInvalid.msg(
+                "fuel_limit is set but the context doesn't meter fuel, use Context::new_with_fuel",
+            ));
+        }
+
+        if async_support {
+            // Async calls yield when the deadline is reached, timeouts and cancellation are
+            // handled by `interruptible` when the call is resumed
+            store.epoch_deadline_async_yield_and_update(1);
+        } else {
+            // The engine is shared by every plugin in the context, so the epoch may also be
+            // incremented when another plugin times out or is cancelled
+            store.epoch_deadline_callback(|internal| {
+                if internal.cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+                    return Err(ErrorCode::Cancelled.msg("cancelled"));
+                }
+
+                match internal.deadline {
+                    Some(deadline) if std::time::Instant::now() >= deadline => {
+                        Err(ErrorCode::Timeout.msg("timeout"))
+                    }
+                    _ => Ok(1),
+                }
+            });
+        }
+
+        let cancel_handle = CancelHandle {
+            engine: engine.clone(),
+            cancelled: store.data().cancelled.clone(),
+        };
 
         let memory = Memory::new(
             &mut store,
@@ -119,7 +234,7 @@ impl Plugin {
         )?;
         let mut memory = PluginMemory::new(store, memory);
 
-        let mut linker = Linker::new(&engine);
+        let mut linker = Linker::new(engine);
         linker.allow_shadowing(true);
 
         if with_wasi {
@@ -188,7 +303,17 @@ impl Plugin {
 
                     for f in &mut imports {
                         let name = f.name().to_string();
-                        let func = Func::new(&mut memory.store, f.ty().clone(), f.2);
+                        let callback = f.2.clone();
+                        let context = format!("Host function {name} failed");
+                        let func = Func::new(
+                            &mut memory.store,
+                            f.ty().clone(),
+                            move |caller, params, results| {
+                                callback(caller, params, results).map_err(|e| {
+                                    e.context(ErrorCode::HostFunctionError.with(&context))
+                                })
+                            },
+                        );
                         linker.define(EXPORT_MODULE_NAME, &name, func)?;
                     }
                 }
@@ -198,23 +323,43 @@ impl Plugin {
         // Add modules to linker
         for (name, module) in modules.iter() {
             if name != main_name {
-                linker.module(&mut memory.store, name, module)?;
+                if async_support {
+                    block_on(linker.module_async(&mut memory.store, name, module))?;
+                } else {
+                    linker.module(&mut memory.store, name, module)?;
+                }
                 linker.alias_module(name, "env")?;
             }
         }
 
-        let instance = linker.instantiate(&mut memory.store, main)?;
+        let instance = if async_support {
+            block_on(linker.instantiate_async(&mut memory.store, main))?
+        } else {
+            linker.instantiate(&mut memory.store, main)?
+        };
 
+        let imports = info::imports(modules, main_name, &host_functions);
         let mut plugin = Plugin {
             module: main.clone(),
+            imports,
+            functions: functions.to_vec(),
+            modules: modules.clone(),
+            hash,
+            guest_high_water_mark: 0,
+            id: -1,
             linker,
             memory,
             instance,
             last_error: std::cell::RefCell::new(None),
+            last_error_code: std::cell::Cell::new(ErrorCode::None),
             manifest,
             vars: BTreeMap::new(),
             should_reinstantiate: false,
             timer_id: uuid::Uuid::new_v4(),
+            fuel_consumed: 0,
+            fuel_start: 0,
+            async_support,
+            cancel_handle,
         };
 
         plugin.initialize_runtime()?;
@@ -222,6 +367,44 @@ impl Plugin {
         Ok(plugin)
     }
 
+    /// List the exports of the main module and the imports required by all of the plugin's
+    /// modules
+    pub fn info(&self) -> PluginInfo {
+        PluginInfo {
+            exports: info::exports(&self.module),
+            imports: self.imports.clone(),
+        }
+    }
+
+    /// Get the memory usage of the extism heap and the main module's memory
+    pub fn memory_stats(&mut self) -> MemoryStats {
+        let guest = self.guest_memory().map(|memory| {
+            let store = &self.memory.store;
+            let size = memory.data_size(store);
+            GuestMemoryStats {
+                pages: memory.size(store) as u32,
+                max_pages: memory.ty(store).maximum().map(|x| x as u32),
+                high_water_mark: self.guest_high_water_mark.max(size),
+            }
+        });
+
+        MemoryStats {
+            extism: self.memory.stats(),
+            guest,
+            vars: VarStoreStats {
+                count: self.vars.len(),
+                bytes: self.var_bytes(),
+                max_bytes: self.max_var_bytes(),
+            },
+            max_http_response_bytes: self.max_http_response_bytes(),
+        }
+    }
+
+    /// Get the memory exported by the main module
+    fn guest_memory(&mut self) -> Option<Memory> {
+        self.instance.get_memory(&mut self.memory.store, "memory")
+    }
+
     /// Get a function by name
     pub fn get_func(&mut self, function: impl AsRef<str>) -> Option<Func> {
         self.instance
@@ -232,6 +415,14 @@ impl Plugin {
     pub fn set_error(&self, e: impl std::fmt::Debug) {
         debug!("Set error: {:?}", e);
         *self.last_error.borrow_mut() = Some(error_string(e));
+        self.last_error_code.set(ErrorCode::Unknown);
+    }
+
+    /// Set `last_error` and `last_error_code`, the code is determined by `e`
+    pub(crate) fn call_error(&self, msg: impl std::fmt::Debug, e: Error) -> Error {
+        self.set_error(msg);
+        self.last_error_code.set(ErrorCode::from_error(&e));
+        e
     }
 
     pub fn error<E>(&self, e: impl std::fmt::Debug, x: E) -> E {
@@ -242,6 +433,7 @@ impl Plugin {
     /// Unset `last_error` field
     pub fn clear_error(&self) {
         *self.last_error.borrow_mut() = None;
+        self.last_error_code.set(ErrorCode::None);
     }
 
     /// Store input in memory and initialize `Internal` pointer
@@ -260,15 +452,103 @@ impl Plugin {
         self.memory.dump();
     }
 
+    /// Replace the plugin's instance with a new one, the new instance is created in a new store
+    /// since instances can't be removed from a store, so reusing it would keep the memory of
+    /// every previous instance alive until the plugin is dropped
+    ///
+    /// Vars, the cancel handle and memory statistics are kept, the extism memory is cleared
     pub fn reinstantiate(&mut self) -> Result<(), Error> {
-        let instance = self
-            .linker
-            .instantiate(&mut self.memory.store, &self.module)?;
-        self.instance = instance;
-        self.initialize_runtime()?;
+        if let Some(memory) = self.guest_memory() {
+            let size = memory.data_size(&self.memory.store);
+            self.guest_high_water_mark = self.guest_high_water_mark.max(size);
+        }
+
+        let engine = self.memory.store.engine().clone();
+        let mut plugin = Plugin::instantiate(
+            &engine,
+            self.async_support,
+            self.manifest.clone(),
+            &self.modules,
+            self.hash.clone(),
+            &self.functions,
+            self.has_wasi(),
+        )?;
+
+        // Existing cancel handles have to keep working with the new store
+        plugin.memory.store.data_mut().cancelled = self.cancel_handle.cancelled.clone();
+        plugin.cancel_handle = self.cancel_handle.clone();
+
+        plugin.id = self.id;
+        plugin.timer_id = self.timer_id;
+        plugin.vars = std::mem::take(&mut self.vars);
+        plugin.guest_high_water_mark = self.guest_high_water_mark;
+        plugin.memory.high_water_mark = self.memory.high_water_mark;
+        plugin.fuel_consumed = self.fuel_consumed;
+        plugin.last_error = self.last_error.take().into();
+        plugin.last_error_code = self.last_error_code.get().into();
+
+        *self = plugin;
+        Ok(())
+    }
+
+    /// Capture the plugin's guest memory, globals, extism memory and vars, the result can be
+    /// passed to `Plugin::restore` to roll back to this point or copy the state to another
+    /// instance of the same module
+    pub fn snapshot(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(Snapshot::capture(self)?.encode())
+    }
+
+    /// Reinstantiate the plugin and restore the state captured by `Plugin::snapshot`
+    pub fn restore(&mut self, snapshot: impl AsRef<[u8]>) -> Result<(), Error> {
+        Snapshot::decode(snapshot.as_ref())?.restore(self)
+    }
+
+    /// Set a variable, this fails if the total size of the variables would be larger than
+    /// `max_var_bytes`
+    pub fn set_var(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let key = key.into();
+        let value = value.into();
+        let existing = self.vars.get(&key).map(|v| v.len()).unwrap_or(0);
+        let size = self.var_bytes() - existing + value.len();
+
+        let limit = self.max_var_bytes();
+        if size as u64 > limit {
+            return Err(ErrorCode::LimitExceeded.msg(format!(
+                "Variable store is full: setting {key} would use {size} bytes, the limit is {limit} bytes"
+            )));
+        }
+
+        self.vars.insert(key, value);
         Ok(())
     }
 
+    /// Total size of the plugin's variables in bytes
+    pub fn var_bytes(&self) -> usize {
+        self.vars.values().map(|v| v.len()).sum()
+    }
+
+    /// Maximum total size of the plugin's variables in bytes
+    pub fn max_var_bytes(&self) -> u64 {
+        self.manifest
+            .as_ref()
+            .memory
+            .max_var_bytes
+            .unwrap_or(DEFAULT_MAX_VAR_BYTES)
+    }
+
+    /// Maximum size of an HTTP response body in bytes
+    pub fn max_http_response_bytes(&self) -> u64 {
+        self.manifest
+            .as_ref()
+            .memory
+            .max_http_response_bytes
+            .unwrap_or(DEFAULT_MAX_HTTP_RESPONSE_BYTES)
+    }
+
     pub fn has_wasi(&self) -> bool {
         self.memory.store.data().wasi.is_some()
     }
@@ -298,7 +578,8 @@ impl Plugin {
         if let Some(runtime) = self.detect_runtime() {
             if let Some(timer) = Context::timer().as_ref() {
                 self.memory.store.set_epoch_deadline(1);
-                self.start_timer(&timer.tx)?;
+                self.start_fuel()?;
+                self.start_timer(&timer.tx, self.manifest.as_ref().timeout_ms)?;
                 let x = runtime.init(self);
                 self.stop_timer(&timer.tx)?;
                 self.memory.store.set_epoch_deadline(0);
@@ -309,30 +590,335 @@ impl Plugin {
         Ok(())
     }
 
+    /// Start the timeout timer for a call, `timeout_ms` is usually the value from the manifest
+    /// but can be overridden for individual calls
     pub(crate) fn start_timer(
         &mut self,
         tx: &std::sync::mpsc::SyncSender<TimerAction>,
+        timeout_ms: Option<u64>,
     ) -> Result<(), Error> {
-        if let Some(duration) = self.manifest.as_ref().timeout_ms {
-            self.memory.store.set_epoch_deadline(1);
+        // Timeouts that are too large to be represented as a deadline never expire
+        let timeout = timeout_ms.and_then(|ms| {
+            let duration = std::time::Duration::from_millis(ms);
+            Some((duration, std::time::Instant::now().checked_add(duration)?))
+        });
+
+        self.memory.store.set_epoch_deadline(1);
+        if let Some((duration, deadline)) = timeout {
             let engine: Engine = self.memory.store.engine().clone();
+            self.memory.store.data_mut().deadline = Some(deadline);
             tx.send(TimerAction::Start {
                 id: self.timer_id,
-                duration: std::time::Duration::from_millis(duration),
+                duration,
                 engine,
             })?;
+        }
+
+        Ok(())
+    }
+
+    /// Refill the store's fuel before a call, the amount of fuel is set to `fuel_limit` from the
+    /// manifest or left unbounded if no limit is set. Does nothing if the context doesn't meter
+    /// fuel
+    pub(crate) fn start_fuel(&mut self) -> Result<(), Error> {
+        let limit = self.manifest.as_ref().fuel_limit.unwrap_or(u64::MAX);
+        let store = &mut self.memory.store;
+        if store.fuel_consumed().is_none() {
+            return Ok(());
+        }
+
+        // Async calls are given their fuel in slices, yielding to the caller after each one
+        let fuel = if self.async_support {
+            let fuel = limit.min(ASYNC_YIELD_FUEL);
+            store.out_of_fuel_async_yield((limit - fuel) / ASYNC_YIELD_FUEL, ASYNC_YIELD_FUEL);
+            fuel
         } else {
-            self.memory.store.set_epoch_deadline(1);
+            limit
+        };
+
+        // `consume_fuel` fails if the store is already out of fuel
+        let remaining = store.consume_fuel(0).unwrap_or(0);
+        if remaining < fuel {
+            store.add_fuel(fuel - remaining)?;
+        } else if remaining > fuel {
+            store.consume_fuel(remaining - fuel)?;
         }
 
+        self.fuel_start = store.fuel_consumed().unwrap_or_default();
         Ok(())
     }
 
+    /// Record the amount of fuel used since the last call to `start_fuel`
+    pub(crate) fn stop_fuel(&mut self) {
+        let consumed = self.memory.store.fuel_consumed().unwrap_or_default();
+        self.fuel_consumed = consumed.saturating_sub(self.fuel_start);
+    }
+
+    /// Call `func`, blocking until the call completes if the plugin supports async calls
+    pub(crate) fn call_func(
+        &mut self,
+        func: &Func,
+        params: &[Val],
+        results: &mut [Val],
+    ) -> Result<(), Error> {
+        if self.async_support {
+            let deadline = self.memory.store.data().deadline;
+            let cancelled = self.memory.store.data().cancelled.clone();
+            block_on(interruptible(
+                func.call_async(&mut self.memory.store, params, results),
+                deadline,
+                &cancelled,
+            ))
+        } else {
+            func.call(&mut self.memory.store, params, results)
+        }
+    }
+
+    /// Call a function, `timeout_ms` is usually the value from the manifest but can be
+    /// overridden for individual calls
+    ///
+    /// Returns the function's return value (or `0` if it has none), the output can be read
+    /// using `Plugin::output`. The error is also stored in `last_error`
+    pub fn call(
+        &mut self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+        timeout_ms: Option<u64>,
+    ) -> Result<i32, Error> {
+        let name = name.as_ref();
+        let input = input.as_ref();
+
+        let (func, n_results) = match self.prepare_call(name, input) {
+            Ok(x) => x,
+            Err(e) => return Err(self.call_error(format!("{e:#}"), e)),
+        };
+
+        let tx = match Context::timer().as_ref() {
+            Some(timer) => timer.tx.clone(),
+            None => {
+                let e = Error::msg("Timeout manager is not running");
+                return Err(self.call_error(format!("{e:#}"), e));
+            }
+        };
+
+        if let Err(e) = self.start_fuel() {
+            let e = e.context("Unable to set fuel for call");
+            return Err(self.call_error(format!("{e:#}"), e));
+        }
+
+        if let Err(e) = self.start_timer(&tx, timeout_ms) {
+            let e = e.context(format!(
+                "Unable to start timeout manager for {}",
+                self.timer_id
+            ));
+            return Err(self.call_error(format!("{e:#}"), e));
+        }
+
+        let mut results = vec![Val::null(); n_results];
+        let res = match self.check_cancelled() {
+            Ok(()) => self.call_func(&func, &[], results.as_mut_slice()),
+            Err(e) => Err(e),
+        };
+
+        self.finish_call(name, &tx, res, &results)
+    }
+
+    /// Call a function asynchronously, the plugin must have been created in a context returned
+    /// by `Context::new_async`
+    ///
+    /// Returns the function's return value (or `0` if it has none), the output can be read
+    /// using `Plugin::output`
+    pub async fn call_async(
+        &mut self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+    ) -> Result<i32, Error> {
+        let name = name.as_ref();
+        let input = input.as_ref();
+
+        if !self.async_support {
+            let e = Error::msg(
+                "Async calls are only supported for plugins created using Context::new_async",
+            );
+            return Err(self.call_error(format!("{e:#}"), e));
+        }
+
+        let (func, n_results) = match self.prepare_call(name, input) {
+            Ok(x) => x,
+            Err(e) => return Err(self.call_error(format!("{e:#}"), e)),
+        };
+
+        let tx = match Context::timer().as_ref() {
+            Some(timer) => timer.tx.clone(),
+            None => {
+                let e = Error::msg("Timeout manager is not running");
+                return Err(self.call_error(format!("{e:#}"), e));
+            }
+        };
+
+        if let Err(e) = self.start_fuel() {
+            let e = e.context("Unable to set fuel for call");
+            return Err(self.call_error(format!("{e:#}"), e));
+        }
+
+        if let Err(e) = self.start_timer(&tx, self.manifest.as_ref().timeout_ms) {
+            let e = e.context(format!(
+                "Unable to start timeout manager for {}",
+                self.timer_id
+            ));
+            return Err(self.call_error(format!("{e:#}"), e));
+        }
+
+        let deadline = self.memory.store.data().deadline;
+        let cancelled = self.memory.store.data().cancelled.clone();
+        let mut results = vec![Val::null(); n_results];
+        let res = match self.check_cancelled() {
+            Ok(()) => {
+                interruptible(
+                    func.call_async(&mut self.memory.store, &[], results.as_mut_slice()),
+                    deadline,
+                    &cancelled,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        self.finish_call(name, &tx, res, &results)
+    }
+
+    /// Reset the plugin's memory and input before a call and look up the function to call
+    fn prepare_call(&mut self, name: &str, input: &[u8]) -> Result<(Func, usize), Error> {
+        self.clear_error();
+        if self.should_reinstantiate {
+            self.should_reinstantiate = false;
+            self.reinstantiate()?;
+        }
+
+        self.memory.reset();
+        self.set_input(input.as_ptr(), input.len());
+
+        let func = match self.get_func(name) {
+            Some(x) => x,
+            None => {
+                return Err(ErrorCode::FunctionNotFound.msg(format!("Function not found: {name}")))
+            }
+        };
+
+        // Reject functions with more than 1 result
+        let n_results = func.ty(&self.memory.store).results().len();
+        if n_results > 1 {
+            return Err(Error::msg(format!(
+                "Function {name} has {n_results} results, expected 0 or 1"
+            )));
+        }
+
+        Ok((func, n_results))
+    }
+
+    /// Fail with a cancelled error if the call was cancelled before it started, the epoch was
+    /// incremented before the call set its deadline so the call wouldn't be interrupted
+    fn check_cancelled(&self) -> Result<(), Error> {
+        if self
+            .memory
+            .store
+            .data()
+            .cancelled
+            .load(std::sync::atomic::Ordering::SeqCst)
+        {
+            return Err(ErrorCode::Cancelled.msg("cancelled"));
+        }
+        Ok(())
+    }
+
+    /// Stop the fuel counter and timer after a call and convert the result of the call
+    fn finish_call(
+        &mut self,
+        name: &str,
+        tx: &std::sync::mpsc::SyncSender<TimerAction>,
+        res: Result<(), Error>,
+        results: &[Val],
+    ) -> Result<i32, Error> {
+        self.stop_fuel();
+        self.dump_memory();
+
+        // A cancellation is consumed by the call it applies to, this happens after the call
+        // finishes so a cancellation requested just before the call starts isn't lost
+        self.memory
+            .store
+            .data()
+            .cancelled
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+
+        if self.has_wasi() && name == "_start" {
+            self.should_reinstantiate = true;
+        }
+
+        if let Err(e) = self.stop_timer(tx) {
+            let e = e.context(format!(
+                "Failed to stop timeout manager for {}",
+                self.timer_id
+            ));
+            return Err(self.call_error(format!("{e:#}"), e));
+        }
+
+        if let Err(e) = res {
+            if let Some(exit) = e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                trace!("WASI return code: {}", exit.0);
+                if exit.0 != 0 {
+                    self.set_error(&e);
+                    self.last_error_code.set(ErrorCode::WasiExit);
+                }
+                return Ok(exit.0);
+            }
+
+            // Replace the error with just the message for timeouts, cancellation and running out
+            // of fuel since the backtrace isn't useful in those cases
+            let code = ErrorCode::from_error(&e);
+            match code {
+                ErrorCode::Timeout => return Err(self.call_error("timeout", code.msg("timeout"))),
+                ErrorCode::Cancelled => {
+                    return Err(self.call_error("cancelled", code.msg("cancelled")))
+                }
+                ErrorCode::OutOfFuel => {
+                    return Err(self.call_error("out of fuel", code.msg("out of fuel")))
+                }
+                _ => (),
+            }
+
+            error!("Call: {e:?}");
+            let e = e.context("Call failed");
+            self.set_error(&e);
+            self.last_error_code.set(ErrorCode::from_error(&e));
+            return Err(e);
+        }
+
+        // If `results` is empty and the return value wasn't a WASI exit code then
+        // the call succeeded
+        match results.first() {
+            Some(x) => Ok(x.unwrap_i32()),
+            None => Ok(0),
+        }
+    }
+
+    /// Get a handle that can be used to cancel running calls from another thread
+    pub fn cancel_handle(&self) -> &CancelHandle {
+        &self.cancel_handle
+    }
+
+    /// Get the output of the last call
+    pub fn output(&self) -> &[u8] {
+        let data = self.memory.store.data();
+        self.memory
+            .get(MemoryBlock::new(data.output_offset, data.output_length))
+            .unwrap_or_default()
+    }
+
     pub(crate) fn stop_timer(
         &mut self,
         tx: &std::sync::mpsc::SyncSender<TimerAction>,
     ) -> Result<(), Error> {
-        if self.manifest.as_ref().timeout_ms.is_some() {
+        if self.memory.store.data_mut().deadline.take().is_some() {
             tx.send(TimerAction::Stop { id: self.timer_id })?;
         }
 
@@ -340,6 +926,59 @@ impl Plugin {
     }
 }
 
+// Maximum total size of a plugin's variables, when `max_var_bytes` isn't set
+const DEFAULT_MAX_VAR_BYTES: u64 = 1024 * 1024 * 100;
+
+// Maximum size of an HTTP response body, when `max_http_response_bytes` isn't set
+const DEFAULT_MAX_HTTP_RESPONSE_BYTES: u64 = 1024 * 1024 * 50;
+
+// Amount of fuel given to async calls before they yield to the caller
+const ASYNC_YIELD_FUEL: u64 = 10000;
+
+/// Run a future to completion on the current thread, this is used to drive wasmtime's async
+/// APIs from synchronous code
+pub(crate) fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = std::sync::Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut f = std::pin::pin!(f);
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(x) => return x,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// Wrap an async call, failing with a timeout error if the call is still running after
+/// `deadline` or a cancelled error if `cancelled` is set. Async stores yield instead of trapping
+/// when the epoch deadline is reached, so this is what enforces `timeout_ms` and
+/// `CancelHandle::cancel` for async calls
+pub(crate) async fn interruptible<F: std::future::Future<Output = Result<(), Error>>>(
+    f: F,
+    deadline: Option<std::time::Instant>,
+    cancelled: &std::sync::atomic::AtomicBool,
+) -> Result<(), Error> {
+    let mut f = std::pin::pin!(f);
+    std::future::poll_fn(|cx| match f.as_mut().poll(cx) {
+        std::task::Poll::Pending if cancelled.load(std::sync::atomic::Ordering::SeqCst) => {
+            std::task::Poll::Ready(Err(ErrorCode::Cancelled.msg("cancelled")))
+        }
+        std::task::Poll::Pending if deadline.is_some_and(|d| std::time::Instant::now() >= d) => {
+            std::task::Poll::Ready(Err(ErrorCode::Timeout.msg("timeout")))
+        }
+        x => x,
+    })
+    .await
+}
+
 // Enumerates the supported PDK language runtimes
 enum Runtime {
     Haskell { init: Func, cleanup: Func },
@@ -350,11 +989,7 @@ impl Runtime {
         match self {
             Runtime::Haskell { init, cleanup: _ } => {
                 let mut results = vec![Val::null(); init.ty(&plugin.memory.store).results().len()];
-                init.call(
-                    &mut plugin.memory.store,
-                    &[Val::I32(0), Val::I32(0)],
-                    results.as_mut_slice(),
-                )?;
+                plugin.call_func(init, &[Val::I32(0), Val::I32(0)], results.as_mut_slice())?;
                 info!("Initialized Haskell language runtime");
             }
         }
@@ -368,7 +1003,7 @@ impl Runtime {
             Runtime::Haskell { init: _, cleanup } => {
                 let mut results =
                     vec![Val::null(); cleanup.ty(&plugin.memory.store).results().len()];
-                cleanup.call(&mut plugin.memory.store, &[], results.as_mut_slice())?;
+                plugin.call_func(cleanup, &[], results.as_mut_slice())?;
                 info!("Cleaned up Haskell language runtime");
             }
         }
@@ -382,7 +1017,11 @@ impl Drop for Plugin {
         if let Some(runtime) = self.detect_runtime() {
             self.memory.store.set_epoch_deadline(1);
             if let Some(timer) = Context::timer().as_ref() {
-                if self.start_timer(&timer.tx).is_ok() {
+                if self.start_fuel().is_ok()
+                    && self
+                        .start_timer(&timer.tx, self.manifest.as_ref().timeout_ms)
+                        .is_ok()
+                {
                     if let Err(e) = runtime.cleanup(self) {
                         error!("Unable to cleanup runtime: {e:?}");
                     }
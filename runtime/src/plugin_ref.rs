@@ -1,13 +1,13 @@
 use crate::*;
 
-// PluginRef is used to access a plugin from a context-scoped plugin registry
-pub struct PluginRef<'a> {
+// PluginRef is used to access a plugin from a context-scoped plugin registry, the plugin is
+// locked until the `PluginRef` is dropped
+pub struct PluginRef {
     pub id: PluginIndex,
-    plugin: &'a mut Plugin,
-    pub(crate) epoch_timer_tx: std::sync::mpsc::SyncSender<TimerAction>,
+    plugin: parking_lot::ArcMutexGuard<parking_lot::RawMutex, Option<Plugin>>,
 }
 
-impl<'a> PluginRef<'a> {
+impl PluginRef {
     /// Initialize the plugin for a new call
     ///
     /// - Resets memory offsets
@@ -15,70 +15,100 @@ impl<'a> PluginRef<'a> {
     pub fn init(mut self, data: *const u8, data_len: usize) -> Self {
         trace!("PluginRef::init: {}", self.id,);
         self.as_mut().memory.reset();
-        self.plugin.set_input(data, data_len);
+        self.as_mut().set_input(data, data_len);
 
         self
     }
 
-    /// Create a `PluginRef` from a context
+    /// Create a `PluginRef` from a context, this blocks until the plugin isn't being used by
+    /// another thread
     ///
     /// - Reinstantiates the plugin if `should_reinstantiate` is set to `true` and WASI is enabled
-    pub fn new(ctx: &'a mut Context, plugin_id: PluginIndex, clear_error: bool) -> Option<Self> {
+    pub fn new(ctx: &Context, plugin_id: PluginIndex, clear_error: bool) -> Option<Self> {
+        Self::load(ctx, plugin_id, clear_error, true)
+    }
+
+    /// Same as `PluginRef::new`, but instead of blocking this fails with a context error if the
+    /// plugin is being used by another thread or task
+    pub fn try_new(ctx: &Context, plugin_id: PluginIndex, clear_error: bool) -> Option<Self> {
+        Self::load(ctx, plugin_id, clear_error, false)
+    }
+
+    fn load(ctx: &Context, plugin_id: PluginIndex, clear_error: bool, wait: bool) -> Option<Self> {
         trace!("Loading plugin {plugin_id}");
 
-        let epoch_timer_tx = ctx.epoch_timer_tx.clone();
+        let slot = ctx.slot(plugin_id);
+        let plugin = match slot {
+            Some(slot) if wait => Some(slot.lock_arc()),
+            Some(slot) => match slot.try_lock_arc() {
+                Some(plugin) => Some(plugin),
+                None => {
+                    debug!("Plugin is in use: {plugin_id}");
+                    ctx.set_error(format!("Plugin is in use: {plugin_id}"));
+                    return None;
+                }
+            },
+            None => None,
+        };
 
-        if !ctx.plugin_exists(plugin_id) {
-            error!("Plugin does not exist: {plugin_id}");
-            return ctx.error(format!("Plugin does not exist: {plugin_id}"), None);
-        }
+        // The plugin is `None` if it was removed by another thread after the slot was found
+        let mut plugin = match plugin {
+            Some(plugin) if plugin.is_some() => plugin,
+            _ => {
+                error!("Plugin does not exist: {plugin_id}");
+                ctx.set_error_with_code(
+                    format!("Plugin does not exist: {plugin_id}"),
+                    ErrorCode::PluginNotFound,
+                );
+                return None;
+            }
+        };
 
         if clear_error {
             trace!("Clearing context error");
-            ctx.error = None;
+            ctx.clear_error();
         }
 
-        // `unwrap` is okay here because we already checked with `ctx.plugin_exists` above
-        let plugin = ctx.plugin(plugin_id).unwrap();
+        // `unwrap` is okay here because we already checked that the plugin is set above
+        let p = plugin.as_mut().unwrap();
         if clear_error {
             trace!("Clearing plugin error: {plugin_id}");
-            plugin.clear_error();
+            p.clear_error();
         }
 
         // Reinstantiate plugin after calling _start because according to the WASI
         // applicate ABI _start should be called "at most once":
         // https://github.com/WebAssembly/WASI/blob/main/legacy/application-abi.md
-        if plugin.should_reinstantiate {
-            plugin.should_reinstantiate = false;
-            if let Err(e) = plugin.reinstantiate() {
+        if p.should_reinstantiate {
+            p.should_reinstantiate = false;
+            if let Err(e) = p.reinstantiate() {
                 error!("Failed to reinstantiate: {e:?}");
-                return plugin.error(format!("Failed to reinstantiate: {e:?}"), None);
+                return p.error(format!("Failed to reinstantiate: {e:?}"), None);
             }
         }
 
         Some(PluginRef {
             id: plugin_id,
             plugin,
-            epoch_timer_tx,
         })
     }
 }
 
-impl<'a> AsRef<Plugin> for PluginRef<'a> {
+impl AsRef<Plugin> for PluginRef {
     fn as_ref(&self) -> &Plugin {
-        self.plugin
+        // `PluginRef::new` only returns plugins that are set
+        self.plugin.as_ref().unwrap()
     }
 }
 
-impl<'a> AsMut<Plugin> for PluginRef<'a> {
+impl AsMut<Plugin> for PluginRef {
     fn as_mut(&mut self) -> &mut Plugin {
-        self.plugin
+        self.plugin.as_mut().unwrap()
     }
 }
 
-impl<'a> Drop for PluginRef<'a> {
+impl Drop for PluginRef {
     fn drop(&mut self) {
         trace!("Dropping plugin {}", self.id);
-        // Cleanup?
     }
 }
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+
+use crate::*;
+
+/// A pool of plugin instances created from the same compiled modules, this allows the same plugin
+/// to be called from multiple threads at once
+///
+/// Each instance has its own store and memory, instances that have been used are replaced with a
+/// new instance before they're handed out again so no data from one call is visible to the next
+/// caller
+pub struct PluginPool {
+    engine: Engine,
+    async_support: bool,
+    manifest: Manifest,
+    modules: BTreeMap<String, Module>,
+    hash: String,
+    functions: Vec<Function>,
+    with_wasi: bool,
+    max_size: usize,
+    state: std::sync::Mutex<PoolState>,
+    available: std::sync::Condvar,
+}
+
+struct PoolState {
+    /// Instances that haven't been used yet
+    idle: Vec<Plugin>,
+
+    /// Instances that have been returned to the pool and need to be replaced before they're
+    /// handed out again
+    used: Vec<Plugin>,
+
+    /// Total number of instances, including instances that are in use
+    size: usize,
+
+    /// Tasks waiting in `PluginPool::acquire_async` for an instance to be returned
+    waiters: Vec<std::task::Waker>,
+}
+
+/// How an instance is taken out of the pool
+enum Take {
+    Idle(Plugin),
+    Replace(Plugin),
+    Grow,
+}
+
+impl PluginPool {
+    /// Create a new pool, `size` instances are created up front and the pool will grow to at most
+    /// `max_size` instances when all of the existing instances are in use
+    pub fn new(
+        ctx: &Context,
+        wasm: impl AsRef<[u8]>,
+        functions: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+        size: usize,
+        max_size: usize,
+    ) -> Result<PluginPool, Error> {
+        if max_size == 0 || size > max_size {
+            return Err(Error::msg(format!(
+                "Invalid pool size: {size}, the maximum size is {max_size}"
+            )));
+        }
+
+        let engine = ctx.engine.clone();
+        let (manifest, modules, hash) = ctx.load_manifest(wasm.as_ref())?;
+        let pool = PluginPool {
+            engine,
+            async_support: ctx.async_support,
+            manifest,
+            modules,
+            hash,
+            functions: functions.into_iter().collect(),
+            with_wasi,
+            max_size,
+            state: std::sync::Mutex::new(PoolState {
+                idle: Vec::with_capacity(max_size),
+                used: Vec::with_capacity(max_size),
+                size: 0,
+                waiters: Vec::new(),
+            }),
+            available: std::sync::Condvar::new(),
+        };
+
+        let mut idle = Vec::with_capacity(size);
+        for _ in 0..size {
+            idle.push(pool.instantiate()?);
+        }
+
+        let mut state = pool.lock();
+        state.size = idle.len();
+        state.idle = idle;
+        drop(state);
+
+        Ok(pool)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        match self.state.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn instantiate(&self) -> Result<Plugin, Error> {
+        Plugin::instantiate(
+            &self.engine,
+            self.async_support,
+            self.manifest.clone(),
+            &self.modules,
+            self.hash.clone(),
+            &self.functions,
+            self.with_wasi,
+        )
+    }
+
+    /// Take an idle instance out of the pool, creating a new instance if none are idle and the
+    /// pool hasn't reached its maximum size, otherwise this waits up to `timeout` for an
+    /// instance to be returned. Instances that have already been used are replaced with a new
+    /// instance here, outside of the pool lock, instead of when they're returned. Passing `None`, or a timeout too large to represent as a
+    /// deadline, waits until an instance is available.
+    ///
+    /// The instance should be returned to the pool using `PluginPool::release`
+    pub fn acquire(&self, timeout: Option<std::time::Duration>) -> Result<Plugin, Error> {
+        let deadline = timeout.and_then(|t| std::time::Instant::now().checked_add(t));
+        let mut state = self.lock();
+        loop {
+            if let Some(take) = Self::take(&mut state, self.max_size) {
+                drop(state);
+                return self.finish_take(take);
+            }
+
+            state = match deadline {
+                None => match self.available.wait(state) {
+                    Ok(x) => x,
+                    Err(e) => e.into_inner(),
+                },
+                Some(deadline) => {
+                    let now = std::time::Instant::now();
+                    if now >= deadline {
+                        return Err(Error::msg("Timed out waiting for an idle plugin instance"));
+                    }
+
+                    match self.available.wait_timeout(state, deadline - now) {
+                        Ok(x) => x.0,
+                        Err(e) => e.into_inner().0,
+                    }
+                }
+            };
+        }
+    }
+
+    /// Take an idle instance out of the pool without waiting, returns `None` if all instances
+    /// are in use and the pool has reached its maximum size
+    pub fn try_acquire(&self) -> Result<Option<Plugin>, Error> {
+        let take = Self::take(&mut self.lock(), self.max_size);
+        match take {
+            Some(take) => self.finish_take(take).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Take an idle instance out of the pool, like `PluginPool::acquire` but the current task
+    /// yields instead of blocking the thread while it waits for an instance to be returned
+    pub async fn acquire_async(&self) -> Result<Plugin, Error> {
+        let take = std::future::poll_fn(|cx| {
+            let mut state = self.lock();
+            match Self::take(&mut state, self.max_size) {
+                Some(take) => std::task::Poll::Ready(take),
+                None => {
+                    if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                        state.waiters.push(cx.waker().clone());
+                    }
+                    std::task::Poll::Pending
+                }
+            }
+        })
+        .await;
+        self.finish_take(take)
+    }
+
+    /// Reserve an instance while the pool is locked, any instantiation happens in
+    /// `PluginPool::finish_take` after the lock is released
+    fn take(state: &mut PoolState, max_size: usize) -> Option<Take> {
+        if let Some(plugin) = state.idle.pop() {
+            return Some(Take::Idle(plugin));
+        }
+
+        if let Some(plugin) = state.used.pop() {
+            return Some(Take::Replace(plugin));
+        }
+
+        if state.size < max_size {
+            state.size += 1;
+            return Some(Take::Grow);
+        }
+
+        None
+    }
+
+    fn finish_take(&self, take: Take) -> Result<Plugin, Error> {
+        match take {
+            Take::Idle(plugin) => return Ok(plugin),
+            Take::Replace(plugin) => {
+                drop(plugin);
+                trace!("Replacing used plugin instance");
+            }
+            Take::Grow => trace!("Growing plugin pool"),
+        }
+
+        // The new instance has already been counted in the pool size, so it's removed from the
+        // pool if it can't be created
+        match self.instantiate() {
+            Ok(plugin) => Ok(plugin),
+            Err(e) => {
+                error!("Unable to create pooled plugin instance: {e:?}");
+                let mut state = self.lock();
+                state.size -= 1;
+                self.notify(state);
+                Err(e)
+            }
+        }
+    }
+
+    /// Wake a thread waiting in `PluginPool::acquire` and every task waiting in
+    /// `PluginPool::acquire_async`
+    fn notify(&self, mut state: std::sync::MutexGuard<'_, PoolState>) {
+        let waiters = std::mem::take(&mut state.waiters);
+        drop(state);
+
+        self.available.notify_one();
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+
+    /// Return an instance taken using `PluginPool::acquire` to the pool
+    ///
+    /// The instance is replaced with a new instance in a new store the next time it's acquired,
+    /// so the guest memory, globals, tables, extism memory and vars used by one caller are never
+    /// visible to the next
+    pub fn release(&self, plugin: Plugin) {
+        let mut state = self.lock();
+        state.used.push(plugin);
+        self.notify(state);
+    }
+
+    /// Take an instance out of the pool, the instance is returned to the pool when the guard
+    /// is dropped
+    pub fn get(&self, timeout: Option<std::time::Duration>) -> Result<PooledPlugin<'_>, Error> {
+        Ok(PooledPlugin {
+            pool: self,
+            plugin: Some(self.acquire(timeout)?),
+        })
+    }
+
+    /// Take an instance out of the pool without blocking the current thread, the instance is
+    /// returned to the pool when the guard is dropped
+    pub async fn get_async(&self) -> Result<PooledPlugin<'_>, Error> {
+        Ok(PooledPlugin {
+            pool: self,
+            plugin: Some(self.acquire_async().await?),
+        })
+    }
+
+    /// Total number of instances, including instances that are in use
+    pub fn size(&self) -> usize {
+        self.lock().size
+    }
+
+    /// Number of instances that aren't in use
+    pub fn idle(&self) -> usize {
+        let state = self.lock();
+        state.idle.len() + state.used.len()
+    }
+
+    /// Maximum number of instances
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+/// A plugin instance borrowed from a `PluginPool`
+pub struct PooledPlugin<'a> {
+    pool: &'a PluginPool,
+    plugin: Option<Plugin>,
+}
+
+impl<'a> std::ops::Deref for PooledPlugin<'a> {
+    type Target = Plugin;
+
+    fn deref(&self) -> &Plugin {
+        self.plugin.as_ref().unwrap()
+    }
+}
+
+impl<'a> std::ops::DerefMut for PooledPlugin<'a> {
+    fn deref_mut(&mut self) -> &mut Plugin {
+        self.plugin.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledPlugin<'a> {
+    fn drop(&mut self) {
+        if let Some(plugin) = self.plugin.take() {
+            self.pool.release(plugin);
+        }
+    }
+}
@@ -6,12 +6,28 @@ use std::str::FromStr;
 use crate::*;
 
 /// Create a new context
+///
+/// A context can be used from multiple threads at once. Different plugins in the same context
+/// can be called in parallel, calls to the same plugin are serialized. Errors set on the context
+/// are stored per thread. The pointers returned by `extism_error` and
+/// `extism_plugin_output_data` for a plugin are only valid until the plugin is used again, so
+/// when a plugin is shared between threads they should be read before another thread can call
+/// the plugin. `extism_context_free` must not be called while the context is in use.
 #[no_mangle]
 pub unsafe extern "C" fn extism_context_new() -> *mut Context {
     trace!("Creating new Context");
     Box::into_raw(Box::new(Context::new()))
 }
 
+/// Create a new context that meters fuel, this is required to use `fuel_limit` in a manifest and
+/// for `extism_plugin_fuel_consumed` to report the fuel used by a call. Metering fuel adds some
+/// overhead to every call, so it's disabled in contexts created using `extism_context_new`
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_new_with_fuel() -> *mut Context {
+    trace!("Creating new Context with fuel");
+    Box::into_raw(Box::new(Context::new_with_fuel()))
+}
+
 /// Free a context
 #[no_mangle]
 pub unsafe extern "C" fn extism_context_free(ctx: *mut Context) {
@@ -22,290 +38,1357 @@ pub unsafe extern "C" fn extism_context_free(ctx: *mut Context) {
     drop(Box::from_raw(ctx))
 }
 
-/// Create a new plugin
-///
-/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
-/// `wasm_size`: the length of the `wasm` parameter
-/// `with_wasi`: enables/disables WASI
+/// Set the directory used to cache precompiled modules, passing `NULL` disables the cache
+///
+/// Compiled modules are written to this directory and re-used by later calls to
+/// `extism_plugin_new` with the same WASM code, even across process restarts
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_set_cache_dir(
+    ctx: *mut Context,
+    dir: *const c_char,
+) -> bool {
+    let ctx = &*ctx;
+
+    let dir = if dir.is_null() {
+        None
+    } else {
+        match std::ffi::CStr::from_ptr(dir).to_str() {
+            Ok(x) => Some(std::path::Path::new(x)),
+            Err(e) => return ctx.error(e, false),
+        }
+    };
+
+    trace!("Call to extism_context_set_cache_dir: {:?}", dir);
+
+    if let Err(e) = ctx.set_cache_dir(dir) {
+        return ctx.error(e, false);
+    }
+
+    true
+}
+
+/// Set the maximum number of compiled modules kept in memory, the least recently used modules are
+/// removed when there are more than this. `0` removes the limit
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_set_module_cache_size(ctx: *mut Context, max: Size) {
+    trace!("Call to extism_context_set_module_cache_size: {max}");
+    let ctx = &*ctx;
+    let max = if max == 0 { None } else { Some(max as usize) };
+    ctx.set_module_cache_size(max);
+}
+
+/// Get the number of compiled modules kept in memory and how often they've been re-used as
+/// JSON, the result must be freed using `extism_string_free`. Returns NULL on error
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_module_cache_stats(ctx: *mut Context) -> *mut c_char {
+    trace!("Call to extism_context_module_cache_stats");
+    let ctx = &*ctx;
+
+    let json = match serde_json::to_string(&ctx.module_cache_stats()) {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, std::ptr::null_mut()),
+    };
+
+    match std::ffi::CString::new(json) {
+        Ok(x) => x.into_raw(),
+        Err(e) => ctx.error(e, std::ptr::null_mut()),
+    }
+}
+
+/// Configure the cache used for modules downloaded from URLs
+///
+/// `dir`: is the directory used to store modules, passing `NULL` disables the cache. By default
+/// a per-user cache directory is used
+/// `max_size`: is the maximum total size of the cached modules in bytes, the least recently used
+/// modules are removed when the cache is larger than this. `0` removes the limit
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_set_download_cache(
+    ctx: *mut Context,
+    dir: *const c_char,
+    max_size: Size,
+) -> bool {
+    let ctx = &*ctx;
+
+    let dir = if dir.is_null() {
+        None
+    } else {
+        match std::ffi::CStr::from_ptr(dir).to_str() {
+            Ok(x) => Some(std::path::Path::new(x)),
+            Err(e) => return ctx.error(e, false),
+        }
+    };
+
+    trace!("Call to extism_context_set_download_cache: {dir:?}, {max_size}");
+
+    let max_size = if max_size == 0 { None } else { Some(max_size) };
+    if let Err(e) = ctx.set_download_cache(dir, max_size) {
+        return ctx.error(e, false);
+    }
+
+    true
+}
+
+/// Cache modules downloaded from URLs that don't have a hash, this is disabled by default
+///
+/// Modules without a hash aren't checked when they're loaded from the cache and aren't downloaded
+/// again until they're removed, so a URL that always points to the latest version of a module
+/// will keep loading the cached version. Requests that use credentials, environment variables or
+/// `Authorization` and `Cookie` headers are never cached without a hash
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_download_cache_unhashed(ctx: *mut Context, enabled: bool) {
+    trace!("Call to extism_context_download_cache_unhashed: {enabled}");
+    let ctx = &*ctx;
+    ctx.set_download_cache_unhashed(enabled);
+}
+
+/// List the modules in the download cache as JSON, the least recently used module is first.
+/// Returns NULL on error. The result must be freed using `extism_string_free`
+///
+/// Each entry has a `key`, which is `algorithm:hex` for modules with a hash, a `path`, a `size` in
+/// bytes and a `last_used` time in seconds since the Unix epoch
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_download_cache_list(ctx: *mut Context) -> *mut c_char {
+    trace!("Call to extism_context_download_cache_list");
+    let ctx = &*ctx;
+
+    let json = match ctx
+        .download_cache()
+        .entries()
+        .and_then(|x| Ok(serde_json::to_string(&x)?))
+    {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, std::ptr::null_mut()),
+    };
+
+    match std::ffi::CString::new(json) {
+        Ok(x) => x.into_raw(),
+        Err(e) => ctx.error(e, std::ptr::null_mut()),
+    }
+}
+
+/// Remove the least recently used modules until the download cache is no larger than its
+/// maximum size, returns the number of modules removed or -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_download_cache_prune(ctx: *mut Context) -> i64 {
+    trace!("Call to extism_context_download_cache_prune");
+    let ctx = &*ctx;
+
+    match ctx.download_cache().prune() {
+        Ok(x) => x as i64,
+        Err(e) => ctx.error(e, -1),
+    }
+}
+
+/// Remove every module from the download cache, returns the number of modules removed or -1 on
+/// error
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_download_cache_clear(ctx: *mut Context) -> i64 {
+    trace!("Call to extism_context_download_cache_clear");
+    let ctx = &*ctx;
+
+    match ctx.download_cache().clear() {
+        Ok(x) => x as i64,
+        Err(e) => ctx.error(e, -1),
+    }
+}
+
+/// Set the callback used to get the values of `${credential:NAME}` references in the headers used
+/// to download modules, replacing the previous callback
+///
+/// `user_data`: is passed to the callback, the callback may be called from any thread that
+/// creates a plugin
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_set_credential_provider(
+    ctx: *mut Context,
+    callback: CredentialCallback,
+    user_data: *mut std::ffi::c_void,
+) {
+    trace!("Call to extism_context_set_credential_provider");
+    let ctx = &*ctx;
+
+    let provider = crate::credentials::CallbackProvider {
+        callback,
+        user_data,
+    };
+    ctx.set_credential_provider(Some(std::sync::Arc::new(move |name, url| {
+        provider.get(name, url)
+    })));
+}
+
+/// Remove the callback set using `extism_context_set_credential_provider`, modules that use
+/// credentials fail to load until a new callback is set
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_remove_credential_provider(ctx: *mut Context) {
+    trace!("Call to extism_context_remove_credential_provider");
+    let ctx = &*ctx;
+    ctx.set_credential_provider(None);
+}
+
+/// Allow `${env:NAME}` references to the environment variable `name` in the headers used to
+/// download modules, no environment variables can be used until they're allowed
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_allow_env_var(
+    ctx: *mut Context,
+    name: *const c_char,
+) -> bool {
+    let ctx = &*ctx;
+
+    let name = match std::ffi::CStr::from_ptr(name).to_str() {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, false),
+    };
+
+    trace!("Call to extism_context_allow_env_var: {name}");
+    ctx.allow_env_var(name);
+    true
+}
+
+/// Stop allowing an environment variable to be used in headers, returns `false` if the variable
+/// wasn't allowed
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_remove_allowed_env_var(
+    ctx: *mut Context,
+    name: *const c_char,
+) -> bool {
+    let ctx = &*ctx;
+
+    let name = match std::ffi::CStr::from_ptr(name).to_str() {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, false),
+    };
+
+    trace!("Call to extism_context_remove_allowed_env_var: {name}");
+    ctx.remove_allowed_env_var(name)
+}
+
+/// Trust modules signed by `public_key`, which must be a 32 byte ed25519 public key
+///
+/// `key_id`: is the name modules use to refer to the key in the `key_id` field of their metadata,
+/// adding a key with an existing ID replaces the old key
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_trust_key(
+    ctx: *mut Context,
+    key_id: *const c_char,
+    public_key: *const u8,
+    public_key_size: Size,
+) -> bool {
+    let ctx = &*ctx;
+
+    let key_id = match std::ffi::CStr::from_ptr(key_id).to_str() {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, false),
+    };
+
+    trace!("Call to extism_context_trust_key: {key_id}");
+
+    let public_key = std::slice::from_raw_parts(public_key, public_key_size as usize);
+    if let Err(e) = ctx.trust_key(key_id, public_key) {
+        return ctx.error(e, false);
+    }
+
+    true
+}
+
+/// Stop trusting the key with the ID `key_id`, returns `false` if the key wasn't trusted
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_remove_trusted_key(
+    ctx: *mut Context,
+    key_id: *const c_char,
+) -> bool {
+    let ctx = &*ctx;
+
+    let key_id = match std::ffi::CStr::from_ptr(key_id).to_str() {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, false),
+    };
+
+    trace!("Call to extism_context_remove_trusted_key: {key_id}");
+    ctx.remove_trusted_key(key_id)
+}
+
+/// Require modules to be signed by a trusted key
+///
+/// When `required` is `true`, creating or updating a plugin fails with
+/// `EXTISM_ERROR_CODE_SIGNATURE_INVALID` if any of its modules is unsigned or isn't signed by a
+/// key added using `extism_context_trust_key`. Signatures from trusted keys are always verified
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_require_signatures(ctx: *mut Context, required: bool) {
+    trace!("Call to extism_context_require_signatures: {required}");
+    let ctx = &*ctx;
+    ctx.require_signatures(required);
+}
+
+/// Create a new plugin
+///
+/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+/// `wasm_size`: the length of the `wasm` parameter
+/// `with_wasi`: enables/disables WASI
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_new(
+    ctx: *mut Context,
+    wasm: *const u8,
+    wasm_size: Size,
+    with_wasi: bool,
+) -> PluginIndex {
+    trace!("Call to extism_plugin_new with wasm pointer {:?}", wasm);
+    let ctx = &*ctx;
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    ctx.new_plugin(data, with_wasi)
+}
+
+/// A union type for host function argument/return values
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union ValUnion {
+    pub i32: i32,
+    pub i64: i64,
+    pub f32: f32,
+    pub f64: f64,
+    // TODO: v128, ExternRef, FuncRef
+}
+
+/// `ExtismVal` holds the type and value of a function argument/return
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ExtismVal {
+    pub t: ValType,
+    pub v: ValUnion,
+}
+
+/// Host function signature, a host function can fail by calling `extism_current_plugin_set_error`
+/// before it returns
+pub type ExtismFunctionType = extern "C" fn(
+    plugin: *mut Internal,
+    inputs: *const ExtismVal,
+    n_inputs: Size,
+    outputs: *mut ExtismVal,
+    n_outputs: Size,
+    data: *mut std::ffi::c_void,
+);
+
+impl TryFrom<&Val> for ExtismVal {
+    type Error = Error;
+
+    fn try_from(value: &Val) -> Result<Self, Error> {
+        match value {
+            Val::I32(x) => Ok(ExtismVal {
+                t: ValType::I32,
+                v: ValUnion { i32: *x },
+            }),
+            Val::I64(x) => Ok(ExtismVal {
+                t: ValType::I64,
+                v: ValUnion { i64: *x },
+            }),
+            Val::F32(x) => Ok(ExtismVal {
+                t: ValType::F32,
+                v: ValUnion {
+                    f32: f32::from_bits(*x),
+                },
+            }),
+            Val::F64(x) => Ok(ExtismVal {
+                t: ValType::F64,
+                v: ValUnion {
+                    f64: f64::from_bits(*x),
+                },
+            }),
+            t => Err(Error::msg(format!(
+                "Unsupported host function argument type: {:?}",
+                t.ty()
+            ))),
+        }
+    }
+}
+
+impl ExtismVal {
+    /// Convert to a `Val` of type `ty`, the value is zeroed if it has a different type
+    unsafe fn to_val(self, ty: &wasmtime::ValType) -> Result<Val, Error> {
+        match ty {
+            wasmtime::ValType::I32 => Ok(Val::I32(match self.t {
+                ValType::I32 => self.v.i32,
+                _ => 0,
+            })),
+            wasmtime::ValType::I64 => Ok(Val::I64(match self.t {
+                ValType::I64 => self.v.i64,
+                _ => 0,
+            })),
+            wasmtime::ValType::F32 => Ok(Val::F32(match self.t {
+                ValType::F32 => self.v.f32.to_bits(),
+                _ => 0,
+            })),
+            wasmtime::ValType::F64 => Ok(Val::F64(match self.t {
+                ValType::F64 => self.v.f64.to_bits(),
+                _ => 0,
+            })),
+            t => Err(Error::msg(format!(
+                "Unsupported host function return type: {t:?}"
+            ))),
+        }
+    }
+}
+
+/// Owns the user data passed to `extism_function_new`, freeing it when the function is dropped
+struct UserData {
+    ptr: *mut std::ffi::c_void,
+    free: Option<extern "C" fn(_: *mut std::ffi::c_void)>,
+}
+
+// The user data is only accessed by the host function callback, it's up to the caller of
+// `extism_function_new` to make sure it can be used from other threads
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl Drop for UserData {
+    fn drop(&mut self) {
+        if let Some(free) = self.free {
+            if !self.ptr.is_null() {
+                free(self.ptr);
+            }
+        }
+    }
+}
+
+/// Create a new host function
+///
+/// `name`: function name, this should be valid UTF-8
+/// `inputs`: argument types
+/// `n_inputs`: number of argument types
+/// `outputs`: return types
+/// `n_outputs`: number of return types
+/// `func`: the function to call, it can fail by calling `extism_current_plugin_set_error`
+/// `user_data`: a pointer that will be passed to the function when it's called
+/// `free_user_data`: a callback to release the `user_data` value when the resulting
+///   `ExtismFunction` and any plugins using it are freed, this may be NULL
+///
+/// Returns a new `ExtismFunction` or NULL if the `name` argument is invalid. The function
+/// can be freed using `extism_function_free` once the plugins using it have been created.
+#[no_mangle]
+pub unsafe extern "C" fn extism_function_new(
+    name: *const c_char,
+    inputs: *const ValType,
+    n_inputs: Size,
+    outputs: *const ValType,
+    n_outputs: Size,
+    func: ExtismFunctionType,
+    user_data: *mut std::ffi::c_void,
+    free_user_data: Option<extern "C" fn(_: *mut std::ffi::c_void)>,
+) -> *mut Function {
+    let name = match std::ffi::CStr::from_ptr(name).to_str() {
+        Ok(x) => x.to_string(),
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    trace!("Call to extism_function_new: {name}");
+
+    let inputs = if inputs.is_null() || n_inputs == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(inputs, n_inputs as usize)
+    }
+    .to_vec();
+
+    let outputs = if outputs.is_null() || n_outputs == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(outputs, n_outputs as usize)
+    }
+    .to_vec();
+
+    let user_data = UserData {
+        ptr: user_data,
+        free: free_user_data,
+    };
+    let f = Function::new(
+        name,
+        inputs,
+        outputs.clone(),
+        move |mut caller, params, results| {
+            let user_data = &user_data;
+            let params: Vec<ExtismVal> = params
+                .iter()
+                .map(ExtismVal::try_from)
+                .collect::<Result<_, _>>()?;
+
+            // Results are passed to the callback zeroed, with the type set to the declared return type
+            let mut output_tmp: Vec<ExtismVal> = outputs
+                .iter()
+                .map(|t| ExtismVal {
+                    t: *t,
+                    v: ValUnion { i64: 0 },
+                })
+                .collect();
+
+            caller.data_mut().host_error = None;
+            func(
+                caller.data_mut(),
+                params.as_ptr(),
+                params.len() as Size,
+                output_tmp.as_mut_ptr(),
+                output_tmp.len() as Size,
+                user_data.ptr,
+            );
+
+            if let Some(e) = caller.data_mut().host_error.take() {
+                return Err(Error::msg(e));
+            }
+
+            for ((result, tmp), t) in results.iter_mut().zip(output_tmp).zip(outputs.iter()) {
+                *result = unsafe { tmp.to_val(&(*t).into())? };
+            }
+            Ok(())
+        },
+    );
+    Box::into_raw(Box::new(f))
+}
+
+/// Free an `ExtismFunction`
+#[no_mangle]
+pub unsafe extern "C" fn extism_function_free(f: *mut Function) {
+    if f.is_null() {
+        return;
+    }
+
+    drop(Box::from_raw(f))
+}
+
+/// Get a pointer to the start of the current plugin's memory, this should only be used from
+/// inside a host function. Offsets passed to host functions can be added to this pointer to
+/// read and write the plugin's memory.
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory(plugin: *mut Internal) -> *mut u8 {
+    if plugin.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    CurrentPlugin::from_internal(&mut *plugin)
+        .memory_ptr(MemoryBlock::new(0, 0))
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Allocate a block of `n` bytes in the current plugin's memory, this should only be used from
+/// inside a host function. Returns the offset of the new block or 0 on error.
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory_alloc(plugin: *mut Internal, n: Size) -> u64 {
+    if plugin.is_null() {
+        return 0;
+    }
+
+    match CurrentPlugin::from_internal(&mut *plugin).memory_alloc(n as usize) {
+        Ok(block) => block.offset as u64,
+        Err(e) => {
+            error!("Unable to allocate memory from host function: {e:?}");
+            0
+        }
+    }
+}
+
+/// Get the length of the block at `offset` in the current plugin's memory, returns 0 if there
+/// is no block at that offset
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory_length(
+    plugin: *mut Internal,
+    offset: u64,
+) -> Size {
+    if plugin.is_null() {
+        return 0;
+    }
+
+    CurrentPlugin::from_internal(&mut *plugin)
+        .memory_length(offset as usize)
+        .unwrap_or_default() as Size
+}
+
+/// Free the block at `offset` in the current plugin's memory
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory_free(plugin: *mut Internal, offset: u64) {
+    if plugin.is_null() {
+        return;
+    }
+
+    if let Err(e) = CurrentPlugin::from_internal(&mut *plugin).memory_free(offset as usize) {
+        error!("Unable to free memory from host function: {e:?}");
+    }
+}
+
+/// Get a pointer to the block at `offset` in the current plugin's memory, the pointer is only
+/// valid until the plugin's memory grows. Returns NULL if there is no block at that offset.
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory_ptr(
+    plugin: *mut Internal,
+    offset: u64,
+) -> *mut u8 {
+    if plugin.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    CurrentPlugin::from_internal(&mut *plugin)
+        .memory_ptr(offset as usize)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Fail the host function that's running with the error `msg`, this should only be used from
+/// inside a host function. The call to the plugin fails with `msg` once the host function
+/// returns, the values written to its outputs are ignored
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_set_error(
+    plugin: *mut Internal,
+    msg: *const c_char,
+) {
+    if plugin.is_null() {
+        return;
+    }
+
+    let msg = if msg.is_null() {
+        "Host function failed".to_string()
+    } else {
+        std::ffi::CStr::from_ptr(msg).to_string_lossy().into_owned()
+    };
+
+    trace!("Call to extism_current_plugin_set_error: {msg}");
+    (*plugin).host_error = Some(msg);
+}
+
+/// Create a new plugin with additional host functions
+///
+/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+/// `wasm_size`: the length of the `wasm` parameter
+/// `functions`: an array of `ExtismFunction*`
+/// `n_functions`: the number of functions provided
+/// `with_wasi`: enables/disables WASI
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_new_with_functions(
+    ctx: *mut Context,
+    wasm: *const u8,
+    wasm_size: Size,
+    functions: *const *const Function,
+    n_functions: Size,
+    with_wasi: bool,
+) -> PluginIndex {
+    trace!(
+        "Call to extism_plugin_new_with_functions with wasm pointer {:?}",
+        wasm
+    );
+    let ctx = &*ctx;
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    let funcs = functions_from_ptr(functions, n_functions);
+    ctx.new_plugin_with_functions(data, funcs, with_wasi)
+}
+
+/// Update a plugin, keeping the existing ID
+///
+/// Similar to `extism_plugin_new` but takes an `index` argument to specify
+/// which plugin to update, the host functions the plugin was created with are re-used
+///
+/// Memory for this plugin will be reset upon update
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_update(
+    ctx: *mut Context,
+    index: PluginIndex,
+    wasm: *const u8,
+    wasm_size: Size,
+    with_wasi: bool,
+) -> bool {
+    trace!("Call to extism_plugin_update with wasm pointer {:?}", wasm);
+    let ctx = &*ctx;
+
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    ctx.update_plugin(index, data, None, with_wasi)
+}
+
+/// Update a plugin, replacing its host functions
+///
+/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+/// `wasm_size`: the length of the `wasm` parameter
+/// `functions`: an array of `ExtismFunction*`, these replace the functions the plugin was created
+/// with
+/// `n_functions`: the number of functions provided
+/// `with_wasi`: enables/disables WASI
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_update_with_functions(
+    ctx: *mut Context,
+    index: PluginIndex,
+    wasm: *const u8,
+    wasm_size: Size,
+    functions: *const *const Function,
+    n_functions: Size,
+    with_wasi: bool,
+) -> bool {
+    trace!(
+        "Call to extism_plugin_update_with_functions with wasm pointer {:?}",
+        wasm
+    );
+    let ctx = &*ctx;
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    let funcs = functions_from_ptr(functions, n_functions);
+    ctx.update_plugin(index, data, Some(funcs), with_wasi)
+}
+
+/// Copy an array of `ExtismFunction*`, skipping NULL entries
+unsafe fn functions_from_ptr(
+    functions: *const *const Function,
+    n_functions: Size,
+) -> Vec<Function> {
+    let mut funcs = vec![];
+
+    if !functions.is_null() {
+        for i in 0..n_functions {
+            let f = *functions.add(i as usize);
+            if f.is_null() {
+                continue;
+            }
+            funcs.push((*f).clone());
+        }
+    }
+
+    funcs
+}
+
+/// Remove a plugin from the registry and free associated memory
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_free(ctx: *mut Context, plugin: PluginIndex) {
+    if plugin < 0 || ctx.is_null() {
+        return;
+    }
+
+    trace!("Freeing plugin {plugin}");
+
+    let ctx = &*ctx;
+    ctx.remove(plugin);
+}
+
+/// Remove all plugins from the registry
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_reset(ctx: *mut Context) {
+    let ctx = &*ctx;
+    ctx.clear();
+}
+
+/// Update plugin config values, this will merge with the existing values
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_config(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    json: *const u8,
+    json_size: Size,
+) -> bool {
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    trace!(
+        "Call to extism_plugin_config for {} with json pointer {:?}",
+        plugin.id,
+        json
+    );
+
+    let data = std::slice::from_raw_parts(json, json_size as usize);
+    let json: std::collections::BTreeMap<String, Option<String>> =
+        match serde_json::from_slice(data) {
+            Ok(x) => x,
+            Err(e) => {
+                return plugin.as_mut().error(e, false);
+            }
+        };
+
+    let plugin = plugin.as_mut();
+
+    let wasi = &mut plugin.memory.store.data_mut().wasi;
+    let config = &mut plugin.manifest.as_mut().config;
+    for (k, v) in json.into_iter() {
+        match v {
+            Some(v) => {
+                trace!("Config, adding {k}");
+                if let Some(Wasi { ctx, .. }) = wasi {
+                    let _ = ctx.push_env(&k, &v);
+                }
+                config.insert(k, v);
+            }
+            None => {
+                trace!("Config, removing {k}");
+                if let Some(Wasi { ctx, .. }) = wasi {
+                    let _ = ctx.push_env(&k, "");
+                }
+                config.remove(&k);
+            }
+        }
+    }
+
+    true
+}
+
+/// Returns true if `func_name` exists
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_function_exists(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    func_name: *const c_char,
+) -> bool {
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let name = std::ffi::CStr::from_ptr(func_name);
+    trace!("Call to extism_plugin_function_exists for: {:?}", name);
+
+    let name = match name.to_str() {
+        Ok(x) => x,
+        Err(e) => {
+            return plugin.as_mut().error(e, false);
+        }
+    };
+
+    plugin.as_mut().get_func(name).is_some()
+}
+
+/// Get the exports of a plugin's main module and the imports required by all of its modules as
+/// JSON, returns NULL on error. The result must be freed using `extism_string_free`
+///
+/// Each import includes a `provider` field, which is one of `builtin`, `host_function`, `module`
+/// or `wasi`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_info(ctx: *mut Context, plugin: PluginIndex) -> *mut c_char {
+    trace!("Call to extism_plugin_info for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null_mut(),
+        Some(p) => p,
+    };
+
+    let json = match serde_json::to_string(&plugin.as_ref().info()) {
+        Ok(x) => x,
+        Err(e) => return plugin.as_mut().error(e, std::ptr::null_mut()),
+    };
+
+    match std::ffi::CString::new(json) {
+        Ok(x) => x.into_raw(),
+        Err(e) => plugin.as_mut().error(e, std::ptr::null_mut()),
+    }
+}
+
+/// Check a manifest for problems without creating a plugin, returns a JSON array of diagnostics,
+/// which is empty if the manifest is valid. The result must be freed using `extism_string_free`
+///
+/// Each diagnostic has a `kind`, a `path`, which is a JSON pointer to the value with the problem,
+/// and a `message`. If the manifest can't be parsed a single diagnostic with the kind `parse` is
+/// returned. Returns NULL and sets the context error if the diagnostics can't be encoded
+#[no_mangle]
+pub unsafe extern "C" fn extism_manifest_validate(
+    ctx: *mut Context,
+    data: *const u8,
+    data_size: Size,
+) -> *mut c_char {
+    trace!("Call to extism_manifest_validate with {data_size} bytes");
+
+    let ctx = &*ctx;
+    let data = std::slice::from_raw_parts(data, data_size as usize);
+    let diagnostics = Manifest::validate(data);
+    let json = match serde_json::to_string(&diagnostics) {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, std::ptr::null_mut()),
+    };
+
+    match std::ffi::CString::new(json) {
+        Ok(x) => x.into_raw(),
+        Err(e) => ctx.error(e, std::ptr::null_mut()),
+    }
+}
+
+/// Get the memory usage of a plugin as JSON, returns NULL on error. The result must be freed
+/// using `extism_string_free`
+///
+/// The `extism` field describes the memory used for inputs, outputs and `extism_alloc`, and the
+/// `guest` field describes the memory exported by the main module, or is `null` if the module
+/// doesn't export a memory
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_memory_stats(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *mut c_char {
+    trace!("Call to extism_plugin_memory_stats for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null_mut(),
+        Some(p) => p,
+    };
+
+    let json = match serde_json::to_string(&plugin.as_mut().memory_stats()) {
+        Ok(x) => x,
+        Err(e) => return plugin.as_mut().error(e, std::ptr::null_mut()),
+    };
+
+    match std::ffi::CString::new(json) {
+        Ok(x) => x.into_raw(),
+        Err(e) => plugin.as_mut().error(e, std::ptr::null_mut()),
+    }
+}
+
+/// Call a function
+///
+/// `func_name`: is the function to call
+/// `data`: is the input data
+/// `data_len`: is the length of `data`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+) -> i32 {
+    let ctx = &*ctx;
+    plugin_call(ctx, plugin_id, func_name, data, data_len, None)
+}
+
+/// Call a function with a timeout that overrides the `timeout_ms` value from the manifest
+/// for this call only
+///
+/// `func_name`: is the function to call
+/// `data`: is the input data
+/// `data_len`: is the length of `data`
+/// `timeout_ms`: is the maximum amount of time the call can run for, in milliseconds
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_with_timeout(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    timeout_ms: u64,
+) -> i32 {
+    let ctx = &*ctx;
+    plugin_call(ctx, plugin_id, func_name, data, data_len, Some(timeout_ms))
+}
+
+/// Call a function, if `timeout_ms` is `None` then the timeout from the manifest is used
+unsafe fn plugin_call(
+    ctx: &Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    timeout_ms: Option<u64>,
+) -> i32 {
+    let mut plugin_ref = match PluginRef::new(ctx, plugin_id, true) {
+        None => return -1,
+        Some(p) => p,
+    };
+
+    // Find function
+    let name = std::ffi::CStr::from_ptr(func_name);
+    let name = match name.to_str() {
+        Ok(name) => name,
+        Err(e) => return plugin_ref.as_ref().error(e, -1),
+    };
+
+    debug!("Calling function: {name} in plugin {plugin_id}");
+
+    let input = if data.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, data_len as usize)
+    };
+
+    let plugin = plugin_ref.as_mut();
+    let timeout_ms = timeout_ms.or(plugin.manifest.as_ref().timeout_ms);
+
+    // Errors are stored in the plugin's `last_error` by `Plugin::call`
+    plugin.call(name, input, timeout_ms).unwrap_or(-1)
+}
+
+/// Get a handle that can be used to cancel a running call from another thread, the handle must
+/// be freed using `extism_cancel_handle_free`
+///
+/// The handle owns its state, so it can still be used after the plugin is freed or updated.
+/// Cancelling a plugin that has been freed has no effect, and a plugin that has been updated
+/// needs a new handle
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_cancel_handle(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *mut CancelHandle {
+    trace!("Call to extism_plugin_cancel_handle for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return std::ptr::null_mut(),
+        Some(p) => p,
+    };
+
+    Box::into_raw(Box::new(plugin.as_ref().cancel_handle().clone()))
+}
+
+/// Free a handle returned by `extism_plugin_cancel_handle`
+#[no_mangle]
+pub unsafe extern "C" fn extism_cancel_handle_free(handle: *mut CancelHandle) {
+    trace!("Call to extism_cancel_handle_free");
+    if handle.is_null() {
+        return;
+    }
+
+    drop(Box::from_raw(handle));
+}
+
+/// Cancel the call that is currently running, or the next call if no call is running. Calls
+/// that are cancelled fail with the error message "cancelled"
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_cancel(handle: *const CancelHandle) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+
+    (*handle).cancel();
+    true
+}
+
+/// Get the amount of fuel consumed by the last call to `extism_plugin_call`, this is always 0 if
+/// the context wasn't created using `extism_context_new_with_fuel`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_fuel_consumed(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> u64 {
+    trace!("Call to extism_plugin_fuel_consumed for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    plugin.as_ref().fuel_consumed
+}
+
+/// Capture the state of a plugin, returns NULL on error. The length of the snapshot is written to
+/// `length` and the snapshot must be freed using `extism_snapshot_free`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_snapshot(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    length: *mut Size,
+) -> *mut u8 {
+    trace!("Call to extism_plugin_snapshot for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null_mut(),
+        Some(p) => p,
+    };
+
+    let data = match plugin.as_mut().snapshot() {
+        Ok(x) => x.into_boxed_slice(),
+        Err(e) => return plugin.as_mut().error(e, std::ptr::null_mut()),
+    };
+
+    if !length.is_null() {
+        *length = data.len() as Size;
+    }
+    Box::into_raw(data) as *mut u8
+}
+
+/// Free a snapshot returned by `extism_plugin_snapshot`
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_new(
-    ctx: *mut Context,
-    wasm: *const u8,
-    wasm_size: Size,
-    with_wasi: bool,
-) -> PluginIndex {
-    trace!("Call to extism_plugin_new with wasm pointer {:?}", wasm);
-    let ctx = &mut *ctx;
-    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
-    ctx.new_plugin(data, with_wasi)
+pub unsafe extern "C" fn extism_snapshot_free(data: *mut u8, length: Size) {
+    if data.is_null() {
+        return;
+    }
+
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        data,
+        length as usize,
+    )));
 }
 
-/// Update a plugin, keeping the existing ID
-///
-/// Similar to `extism_plugin_new` but takes an `index` argument to specify
-/// which plugin to update
-///
-/// Memory for this plugin will be reset upon update
+/// Restore a snapshot created by `extism_plugin_snapshot`, the plugin must have been created
+/// from the same module as the snapshot
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_update(
+pub unsafe extern "C" fn extism_plugin_restore(
     ctx: *mut Context,
-    index: PluginIndex,
-    wasm: *const u8,
-    wasm_size: Size,
-    with_wasi: bool,
+    plugin: PluginIndex,
+    data: *const u8,
+    length: Size,
 ) -> bool {
-    trace!("Call to extism_plugin_update with wasm pointer {:?}", wasm);
-    let ctx = &mut *ctx;
+    trace!("Call to extism_plugin_restore for plugin {plugin}");
 
-    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
-    let plugin = match Plugin::new(data, with_wasi) {
-        Ok(x) => x,
-        Err(e) => {
-            error!("Error creating Plugin: {:?}", e);
-            ctx.set_error(e);
-            return false;
-        }
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
     };
 
-    if !ctx.plugins.contains_key(&index) {
-        ctx.set_error("Plugin index does not exist");
-        return false;
+    if data.is_null() {
+        return plugin.as_mut().error("Snapshot is NULL", false);
     }
 
-    ctx.plugins.insert(index, plugin);
-
-    info!("Plugin updated: {index}");
-    true
+    let data = std::slice::from_raw_parts(data, length as usize);
+    match plugin.as_mut().restore(data) {
+        Ok(()) => true,
+        Err(e) => plugin.as_mut().error(e, false),
+    }
 }
 
-/// Remove a plugin from the registry and free associated memory
+/// Get the names of a plugin's vars as a JSON encoded array of strings, returns NULL on error.
+/// The result must be freed using `extism_string_free`
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_free(ctx: *mut Context, plugin: PluginIndex) {
-    if plugin < 0 || ctx.is_null() {
-        return;
-    }
+pub unsafe extern "C" fn extism_plugin_vars(ctx: *mut Context, plugin: PluginIndex) -> *mut c_char {
+    trace!("Call to extism_plugin_vars for plugin {plugin}");
 
-    trace!("Freeing plugin {plugin}");
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null_mut(),
+        Some(p) => p,
+    };
 
-    let ctx = &mut *ctx;
-    ctx.remove(plugin);
+    let keys: Vec<&String> = plugin.as_ref().vars.keys().collect();
+    let json = match serde_json::to_string(&keys) {
+        Ok(x) => x,
+        Err(e) => return plugin.as_mut().error(e, std::ptr::null_mut()),
+    };
+
+    match std::ffi::CString::new(json) {
+        Ok(x) => x.into_raw(),
+        Err(e) => plugin.as_mut().error(e, std::ptr::null_mut()),
+    }
 }
 
-/// Remove all plugins from the registry
+/// Free a string returned by the runtime
 #[no_mangle]
-pub unsafe extern "C" fn extism_context_reset(ctx: *mut Context) {
-    let ctx = &mut *ctx;
-
-    trace!(
-        "Resetting context, plugins cleared: {:?}",
-        ctx.plugins.keys().collect::<Vec<&i32>>()
-    );
+pub unsafe extern "C" fn extism_string_free(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
 
-    ctx.plugins.clear();
+    drop(std::ffi::CString::from_raw(s));
 }
 
-/// Update plugin config values, this will merge with the existing values
+/// Get the value of a plugin var, returns NULL if the var isn't set or on error, use
+/// `extism_error_code` to tell them apart. The length of the value is written to `length`, the
+/// returned pointer is valid until the var is changed or the plugin is called again
+///
+/// `key`: is the name of the var
+/// `length`: is set to the length of the value
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_config(
+pub unsafe extern "C" fn extism_plugin_var_get(
     ctx: *mut Context,
     plugin: PluginIndex,
-    json: *const u8,
-    json_size: Size,
-) -> bool {
-    let ctx = &mut *ctx;
+    key: *const c_char,
+    length: *mut Size,
+) -> *const u8 {
+    trace!("Call to extism_plugin_var_get for plugin {plugin}");
+
+    let ctx = &*ctx;
     let mut plugin = match PluginRef::new(ctx, plugin, true) {
-        None => return false,
+        None => return std::ptr::null(),
         Some(p) => p,
     };
 
-    trace!(
-        "Call to extism_plugin_config for {} with json pointer {:?}",
-        plugin.id,
-        json
-    );
-
-    let data = std::slice::from_raw_parts(json, json_size as usize);
-    let json: std::collections::BTreeMap<String, Option<String>> =
-        match serde_json::from_slice(data) {
-            Ok(x) => x,
-            Err(e) => {
-                return plugin.as_mut().error(e, false);
-            }
-        };
+    if key.is_null() {
+        return plugin.as_mut().error("Key is NULL", std::ptr::null());
+    }
 
-    let plugin = plugin.as_mut();
+    let key = match std::ffi::CStr::from_ptr(key).to_str() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_mut().error(e, std::ptr::null()),
+    };
 
-    let wasi = &mut plugin.memory.store.data_mut().wasi;
-    let config = &mut plugin.manifest.as_mut().config;
-    for (k, v) in json.into_iter() {
-        match v {
-            Some(v) => {
-                trace!("Config, adding {k}");
-                if let Some(Wasi { ctx, .. }) = wasi {
-                    let _ = ctx.push_env(&k, &v);
-                }
-                config.insert(k, v);
-            }
-            None => {
-                trace!("Config, removing {k}");
-                if let Some(Wasi { ctx, .. }) = wasi {
-                    let _ = ctx.push_env(&k, "");
-                }
-                config.remove(&k);
+    match plugin.as_ref().vars.get(key) {
+        Some(value) => {
+            if !length.is_null() {
+                *length = value.len() as Size;
             }
+            value.as_ptr()
         }
+        None => std::ptr::null(),
     }
-
-    true
 }
 
-/// Returns true if `func_name` exists
+/// Set the value of a plugin var
+///
+/// `key`: is the name of the var
+/// `value`: is the new value, if `value` is NULL then the var is removed
+/// `value_size`: is the length of `value`
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_function_exists(
+pub unsafe extern "C" fn extism_plugin_var_set(
     ctx: *mut Context,
     plugin: PluginIndex,
-    func_name: *const c_char,
+    key: *const c_char,
+    value: *const u8,
+    value_size: Size,
 ) -> bool {
-    let ctx = &mut *ctx;
+    trace!("Call to extism_plugin_var_set for plugin {plugin}");
+
+    let ctx = &*ctx;
     let mut plugin = match PluginRef::new(ctx, plugin, true) {
         None => return false,
         Some(p) => p,
     };
 
-    let name = std::ffi::CStr::from_ptr(func_name);
-    trace!("Call to extism_plugin_function_exists for: {:?}", name);
+    if key.is_null() {
+        return plugin.as_mut().error("Key is NULL", false);
+    }
 
-    let name = match name.to_str() {
+    let key = match std::ffi::CStr::from_ptr(key).to_str() {
         Ok(x) => x,
-        Err(e) => {
-            return plugin.as_mut().error(e, false);
-        }
+        Err(e) => return plugin.as_mut().error(e, false),
     };
 
-    plugin.as_mut().get_func(name).is_some()
+    if value.is_null() {
+        plugin.as_mut().vars.remove(key);
+        return true;
+    }
+
+    let value = std::slice::from_raw_parts(value, value_size as usize);
+    match plugin.as_mut().set_var(key, value) {
+        Ok(()) => true,
+        Err(e) => {
+            let _ = plugin.as_mut().call_error(format!("{e:#}"), e);
+            false
+        }
+    }
 }
 
-/// Call a function
-///
-/// `func_name`: is the function to call
-/// `data`: is the input data
-/// `data_len`: is the length of `data`
+/// Remove a plugin var, returns true if the var existed
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_call(
+pub unsafe extern "C" fn extism_plugin_var_delete(
     ctx: *mut Context,
-    plugin_id: PluginIndex,
-    func_name: *const c_char,
-    data: *const u8,
-    data_len: Size,
-) -> i32 {
-    let ctx = &mut *ctx;
-
-    // Get a `PluginRef` and call `init` to set up the plugin input and memory, this is only
-    // needed before a new call
-    let mut plugin_ref = match PluginRef::new(ctx, plugin_id, true) {
-        None => return -1,
-        Some(p) => p.init(data, data_len as usize),
-    };
+    plugin: PluginIndex,
+    key: *const c_char,
+) -> bool {
+    trace!("Call to extism_plugin_var_delete for plugin {plugin}");
 
-    // Find function
-    let name = std::ffi::CStr::from_ptr(func_name);
-    let name = match name.to_str() {
-        Ok(name) => name,
-        Err(e) => return plugin_ref.as_ref().error(e, -1),
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
     };
 
-    debug!("Calling function: {name} in plugin {plugin_id}");
+    if key.is_null() {
+        return plugin.as_mut().error("Key is NULL", false);
+    }
 
-    let func = match plugin_ref.as_mut().get_func(name) {
-        Some(x) => x,
-        None => {
-            return plugin_ref
-                .as_ref()
-                .error(format!("Function not found: {name}"), -1)
-        }
+    let key = match std::ffi::CStr::from_ptr(key).to_str() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_mut().error(e, false),
     };
 
-    // Check the number of results, reject functions with more than 1 result
-    let n_results = func.ty(&plugin_ref.as_ref().memory.store).results().len();
-    if n_results > 1 {
-        return plugin_ref.as_ref().error(
-            format!("Function {name} has {n_results} results, expected 0 or 1"),
-            -1,
-        );
-    }
+    plugin.as_mut().vars.remove(key).is_some()
+}
 
-    // Start timer
-    let tx = plugin_ref.epoch_timer_tx.clone();
-    if let Err(e) = plugin_ref.as_mut().start_timer(&tx) {
-        let id = plugin_ref.as_ref().timer_id;
-        return plugin_ref.as_ref().error(
-            format!("Unable to start timeout manager for {id}: {e:?}"),
-            -1,
-        );
-    }
+/// Remove all of a plugin's vars
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_vars_clear(ctx: *mut Context, plugin: PluginIndex) -> bool {
+    trace!("Call to extism_plugin_vars_clear for plugin {plugin}");
 
-    // Call the function
-    let mut results = vec![Val::null(); n_results];
-    let res = func.call(
-        &mut plugin_ref.as_mut().memory.store,
-        &[],
-        results.as_mut_slice(),
-    );
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
 
-    plugin_ref.as_ref().dump_memory();
+    plugin.as_mut().vars.clear();
+    true
+}
 
-    if plugin_ref.as_ref().has_wasi() && name == "_start" {
-        plugin_ref.as_mut().should_reinstantiate = true;
+/// Create a pool of plugin instances, returns NULL on error
+///
+/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+/// `wasm_size`: the length of the `wasm` parameter
+/// `with_wasi`: enables/disables WASI
+/// `size`: the number of instances to create up front
+/// `max_size`: the maximum number of instances the pool will grow to
+///
+/// The pool is thread-safe and can be shared by threads that each use their own context
+#[no_mangle]
+pub unsafe extern "C" fn extism_pool_new(
+    ctx: *mut Context,
+    wasm: *const u8,
+    wasm_size: Size,
+    with_wasi: bool,
+    size: Size,
+    max_size: Size,
+) -> *mut PluginPool {
+    trace!("Call to extism_pool_new with wasm pointer {:?}", wasm);
+    let ctx = &*ctx;
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    match PluginPool::new(ctx, data, [], with_wasi, size as usize, max_size as usize) {
+        Ok(pool) => Box::into_raw(Box::new(pool)),
+        Err(e) => {
+            error!("Error creating PluginPool: {:?}", e);
+            ctx.error(e, std::ptr::null_mut())
+        }
     }
+}
 
-    // Stop timer
-    if let Err(e) = plugin_ref.as_mut().stop_timer(&tx) {
-        let id = plugin_ref.as_ref().timer_id;
-        return plugin_ref.as_ref().error(
-            format!("Failed to stop timeout manager for {id}: {e:?}"),
-            -1,
-        );
+/// Free a pool, plugins that are still acquired are not affected
+#[no_mangle]
+pub unsafe extern "C" fn extism_pool_free(pool: *mut PluginPool) {
+    trace!("Freeing pool");
+    if pool.is_null() {
+        return;
     }
+    drop(Box::from_raw(pool))
+}
 
-    match res {
-        Ok(()) => (),
-        Err(e) => {
-            let plugin = plugin_ref.as_ref();
-            if let Some(exit) = e.downcast_ref::<wasmtime_wasi::I32Exit>() {
-                trace!("WASI return code: {}", exit.0);
-                if exit.0 != 0 {
-                    return plugin.error(&e, exit.0);
-                }
-                return exit.0;
-            }
-
-            if e.root_cause().to_string() == "timeout" {
-                return plugin.error("timeout", -1);
-            }
+/// Take an idle instance out of the pool and add it to `ctx`, the returned plugin can be used
+/// like any other plugin until it's given back using `extism_pool_release`
+///
+/// A new instance is created if none are idle and the pool hasn't reached its maximum size,
+/// otherwise this waits up to `timeout_ms` for an instance to be released. If `timeout_ms` is
+/// 0 then this waits until an instance is available. Returns -1 on error.
+#[no_mangle]
+pub unsafe extern "C" fn extism_pool_acquire(
+    ctx: *mut Context,
+    pool: *const PluginPool,
+    timeout_ms: u64,
+) -> PluginIndex {
+    trace!("Call to extism_pool_acquire");
+    let ctx = &*ctx;
+    let pool = &*pool;
 
-            error!("Call: {e:?}");
-            return plugin.error(e.context("Call failed"), -1);
-        }
+    let timeout = if timeout_ms == 0 {
+        None
+    } else {
+        Some(std::time::Duration::from_millis(timeout_ms))
     };
 
-    // If `results` is empty and the return value wasn't a WASI exit code then
-    // the call succeeded
-    if results.is_empty() {
-        return 0;
+    match pool.acquire(timeout) {
+        Ok(plugin) => ctx.insert(plugin),
+        Err(e) => ctx.error(e, -1),
     }
+}
 
-    // Return result to caller
-    results[0].unwrap_i32()
+/// Remove a plugin acquired using `extism_pool_acquire` from `ctx` and return it to the pool,
+/// the plugin is replaced with a new instance before it's acquired again so none of its state is
+/// visible to the next caller
+#[no_mangle]
+pub unsafe extern "C" fn extism_pool_release(
+    ctx: *mut Context,
+    pool: *const PluginPool,
+    plugin: PluginIndex,
+) -> bool {
+    trace!("Call to extism_pool_release for plugin {plugin}");
+    let ctx = &*ctx;
+    let pool = &*pool;
+
+    match ctx.take(plugin) {
+        Some(plugin) => {
+            pool.release(plugin);
+            true
+        }
+        None => ctx.error(format!("Plugin does not exist: {plugin}"), false),
+    }
 }
 
 pub fn get_context_error(ctx: &Context) -> *const c_char {
-    match &ctx.error {
-        Some(e) => e.as_ptr() as *const _,
-        None => {
-            trace!("Context error is NULL");
-            std::ptr::null()
-        }
+    let err = ctx.error_ptr();
+    if err.is_null() {
+        trace!("Context error is NULL");
     }
+    err
 }
 
 /// Get the error associated with a `Context` or `Plugin`, if `plugin` is `-1` then the context
@@ -314,7 +1397,7 @@ pub fn get_context_error(ctx: &Context) -> *const c_char {
 pub unsafe extern "C" fn extism_error(ctx: *mut Context, plugin: PluginIndex) -> *const c_char {
     trace!("Call to extism_error for plugin {plugin}");
 
-    let ctx = &mut *ctx;
+    let ctx = &*ctx;
 
     if !ctx.plugin_exists(plugin) {
         return get_context_error(ctx);
@@ -335,6 +1418,25 @@ pub unsafe extern "C" fn extism_error(ctx: *mut Context, plugin: PluginIndex) ->
     }
 }
 
+/// Get the code for the error associated with a `Context` or `Plugin`, if `plugin` is `-1` then
+/// the context error code will be returned. `EXTISM_ERROR_CODE_NONE` is returned if there is no
+/// error
+#[no_mangle]
+pub unsafe extern "C" fn extism_error_code(ctx: *mut Context, plugin: PluginIndex) -> ErrorCode {
+    trace!("Call to extism_error_code for plugin {plugin}");
+
+    let ctx = &*ctx;
+
+    if !ctx.plugin_exists(plugin) {
+        return ctx.error_code();
+    }
+
+    match PluginRef::new(ctx, plugin, false) {
+        None => ErrorCode::Unknown,
+        Some(p) => p.as_ref().last_error_code.get(),
+    }
+}
+
 /// Get the length of a plugin's output data
 #[no_mangle]
 pub unsafe extern "C" fn extism_plugin_output_length(
@@ -343,7 +1445,7 @@ pub unsafe extern "C" fn extism_plugin_output_length(
 ) -> Size {
     trace!("Call to extism_plugin_output_length for plugin {plugin}");
 
-    let ctx = &mut *ctx;
+    let ctx = &*ctx;
     let plugin = match PluginRef::new(ctx, plugin, true) {
         None => return 0,
         Some(p) => p,
@@ -362,7 +1464,7 @@ pub unsafe extern "C" fn extism_plugin_output_data(
 ) -> *const u8 {
     trace!("Call to extism_plugin_output_data for plugin {plugin}");
 
-    let ctx = &mut *ctx;
+    let ctx = &*ctx;
     let plugin = match PluginRef::new(ctx, plugin, true) {
         None => return std::ptr::null(),
         Some(p) => p,
@@ -377,7 +1479,21 @@ pub unsafe extern "C" fn extism_plugin_output_data(
         .unwrap_or(std::ptr::null())
 }
 
+/// Parse a log level, `NULL` is treated as `error`
+unsafe fn log_level_from_ptr(log_level: *const c_char) -> Option<log::LevelFilter> {
+    let level = if log_level.is_null() {
+        "error"
+    } else {
+        std::ffi::CStr::from_ptr(log_level).to_str().ok()?
+    };
+
+    log::LevelFilter::from_str(level).ok()
+}
+
 /// Set log file and level
+///
+/// This can be called more than once to change the log file or level, but it fails if the host
+/// application has already installed its own logger
 #[no_mangle]
 pub unsafe extern "C" fn extism_log_file(
     filename: *const c_char,
@@ -401,21 +1517,9 @@ pub unsafe extern "C" fn extism_log_file(
         "stderr"
     };
 
-    let level = if log_level.is_null() {
-        "error"
-    } else {
-        let level = std::ffi::CStr::from_ptr(log_level);
-        match level.to_str() {
-            Ok(x) => x,
-            Err(_) => {
-                return false;
-            }
-        }
-    };
-
-    let level = match LevelFilter::from_str(level) {
-        Ok(x) => x,
-        Err(_) => {
+    let level = match log_level_from_ptr(log_level) {
+        Some(x) => x,
+        None => {
             return false;
         }
     };
@@ -455,10 +1559,36 @@ pub unsafe extern "C" fn extism_log_file(
         }
     };
 
-    if log4rs::init_config(config).is_err() {
-        return false;
-    }
-    true
+    logger::Logger::set(Box::new(log4rs::Logger::new(config)), level)
+}
+
+/// Pass log messages from the runtime and plugins to `callback` instead of writing them to a
+/// file, this replaces any log file set using `extism_log_file`
+///
+/// `callback`: is called for each log message, it may be called from any thread
+/// `log_level`: is the maximum level of messages passed to `callback`, if `log_level` is `NULL`
+/// then only errors are logged
+/// `user_data`: is passed to `callback`
+///
+/// Returns `false` if `log_level` is invalid or the host application has already installed its
+/// own logger
+#[no_mangle]
+pub unsafe extern "C" fn extism_log_custom(
+    callback: logger::LogCallback,
+    log_level: *const c_char,
+    user_data: *mut std::ffi::c_void,
+) -> bool {
+    let level = match log_level_from_ptr(log_level) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    let sink = logger::CallbackSink {
+        callback,
+        user_data,
+        level,
+    };
+    logger::Logger::set(Box::new(sink), level)
 }
 
 const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
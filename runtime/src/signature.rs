@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use crate::*;
+
+/// Public keys used to verify the signatures of modules loaded by a context
+///
+/// Modules are signed by creating an ed25519 signature of the WASM code and adding the base64
+/// encoded signature and the ID of the signing key to the module's metadata in the manifest
+#[derive(Default, Clone)]
+pub(crate) struct TrustedKeys {
+    keys: BTreeMap<String, ed25519_dalek::VerifyingKey>,
+
+    /// Refuse to load modules that aren't signed by a trusted key
+    pub(crate) required: bool,
+}
+
+impl TrustedKeys {
+    /// Trust `public_key`, which must be a 32 byte ed25519 public key
+    pub(crate) fn add(
+        &mut self,
+        key_id: impl Into<String>,
+        public_key: &[u8],
+    ) -> Result<(), Error> {
+        let key_id = key_id.into();
+        let bytes: [u8; 32] = match public_key.try_into() {
+            Ok(x) => x,
+            Err(_) => {
+                return Err(Error::msg(format!(
+                    "Invalid public key for {key_id}: expected 32 bytes, found {}",
+                    public_key.len()
+                )))
+            }
+        };
+
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map_err(|e| Error::msg(format!("Invalid public key for {key_id}: {e}")))?;
+        self.keys.insert(key_id, key);
+        Ok(())
+    }
+
+    /// Stop trusting a key, returns `false` if the key wasn't trusted
+    pub(crate) fn remove(&mut self, key_id: &str) -> bool {
+        self.keys.remove(key_id).is_some()
+    }
+
+    /// Check the signature of a module
+    ///
+    /// A signature from a trusted key is always verified. Unsigned modules and signatures from
+    /// unknown keys are only rejected when signatures are required
+    pub(crate) fn verify(
+        &self,
+        meta: &extism_manifest::WasmMetadata,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let name = meta.name.as_deref().unwrap_or("main");
+        let (signature, key_id) = match (&meta.signature, &meta.key_id) {
+            (Some(signature), Some(key_id)) => (signature, key_id),
+            (Some(_), None) => {
+                return Err(ErrorCode::SignatureInvalid
+                    .msg(format!("Module {name} has a signature but no key_id")))
+            }
+            (None, _) if self.required => {
+                return Err(ErrorCode::SignatureInvalid.msg(format!(
+                    "Module {name} isn't signed and signatures are required"
+                )))
+            }
+            (None, _) => return Ok(()),
+        };
+
+        let key = match self.keys.get(key_id) {
+            Some(x) => x,
+            None if self.required => {
+                return Err(ErrorCode::SignatureInvalid.msg(format!(
+                    "Module {name} is signed by {key_id}, which isn't a trusted key"
+                )))
+            }
+            None => {
+                debug!("Not verifying module {name}, {key_id} isn't a trusted key");
+                return Ok(());
+            }
+        };
+
+        let invalid =
+            || ErrorCode::SignatureInvalid.msg(format!("Invalid signature for module {name}"));
+        let signature = base64::decode(signature).map_err(|_| invalid())?;
+        let signature = ed25519_dalek::Signature::from_slice(&signature).map_err(|_| invalid())?;
+        key.verify_strict(data, &signature).map_err(|_| invalid())?;
+        trace!("Verified signature of module {name} using {key_id}");
+        Ok(())
+    }
+}
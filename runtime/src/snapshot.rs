@@ -0,0 +1,398 @@
+use std::collections::BTreeMap;
+
+use crate::*;
+
+const MAGIC: &[u8; 8] = b"EXTISMSS";
+const VERSION: u32 = 1;
+
+/// Captured state of a plugin instance
+///
+/// Only exported memories and mutable globals of the main module can be captured, any other
+/// state held by the instance is reset to its initial value when a snapshot is restored
+pub(crate) struct Snapshot {
+    /// SHA-256 of the code of the module the snapshot was taken from
+    hash: String,
+
+    /// Contents of the extism memory, up to `position`
+    memory: Vec<u8>,
+    position: usize,
+    live_blocks: BTreeMap<usize, usize>,
+    free: Vec<MemoryBlock>,
+    output_offset: usize,
+    output_length: usize,
+
+    /// Exported guest memories
+    memories: BTreeMap<String, Vec<u8>>,
+
+    /// Exported mutable globals
+    globals: BTreeMap<String, Val>,
+
+    vars: BTreeMap<String, Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Capture the current state of `plugin`
+    pub fn capture(plugin: &mut Plugin) -> Result<Snapshot, Error> {
+        let mut memories = BTreeMap::new();
+        let mut globals = BTreeMap::new();
+
+        let exports: Vec<(String, Extern)> = plugin
+            .instance
+            .exports(&mut plugin.memory.store)
+            .map(|x| (x.name().to_string(), x.into_extern()))
+            .collect();
+
+        let store = &mut plugin.memory.store;
+        for (name, export) in exports {
+            match export {
+                Extern::Memory(m) => {
+                    memories.insert(name, m.data(&*store).to_vec());
+                }
+                Extern::Global(g) if g.ty(&*store).mutability() == Mutability::Var => {
+                    match g.get(&mut *store) {
+                        x @ (Val::I32(_)
+                        | Val::I64(_)
+                        | Val::F32(_)
+                        | Val::F64(_)
+                        | Val::V128(_)) => {
+                            globals.insert(name, x);
+                        }
+                        _ => {
+                            return Err(Error::msg(format!(
+                                "Unable to snapshot reference type global: {name}"
+                            )))
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let internal = plugin.memory.store.data();
+        Ok(Snapshot {
+            hash: plugin.hash.clone(),
+            memory: plugin.memory.data()[..plugin.memory.position].to_vec(),
+            position: plugin.memory.position,
+            live_blocks: plugin.memory.live_blocks.clone(),
+            free: plugin.memory.free.clone(),
+            output_offset: internal.output_offset,
+            output_length: internal.output_length,
+            memories,
+            globals,
+            vars: plugin.vars.clone(),
+        })
+    }
+
+    /// Check that the snapshot can be restored into `plugin` without changing the plugin, so a
+    /// snapshot that doesn't match is rejected before the plugin is reinstantiated
+    fn validate(&self, plugin: &Plugin) -> Result<(), Error> {
+        if plugin.hash != self.hash {
+            return Err(Error::msg("Snapshot was taken from a different module"));
+        }
+
+        let exports: BTreeMap<&str, ExternType> = plugin
+            .module
+            .exports()
+            .map(|x| (x.name(), x.ty()))
+            .collect();
+
+        for (name, data) in self.memories.iter() {
+            let ty = match exports.get(name.as_str()) {
+                Some(ExternType::Memory(x)) => x,
+                _ => return Err(Error::msg(format!("Memory not found: {name}"))),
+            };
+            if !fits(data.len(), ty.maximum()) {
+                return Err(Error::msg(format!(
+                    "Snapshot of memory is too large: {name}"
+                )));
+            }
+        }
+
+        for (name, value) in self.globals.iter() {
+            let ty = match exports.get(name.as_str()) {
+                Some(ExternType::Global(x)) => x,
+                _ => return Err(Error::msg(format!("Global not found: {name}"))),
+            };
+            if ty.mutability() != Mutability::Var || *ty.content() != value.ty() {
+                return Err(Error::msg(format!("Global has a different type: {name}")));
+            }
+        }
+
+        let max_pages = plugin.manifest.as_ref().memory.max_pages;
+        if !fits(self.memory.len(), max_pages.map(u64::from)) {
+            return Err(Error::msg("Snapshot of extism memory is too large"));
+        }
+
+        let var_bytes: usize = self.vars.values().map(|v| v.len()).sum();
+        if var_bytes as u64 > plugin.max_var_bytes() {
+            return Err(Error::msg(format!(
+                "Variables in snapshot exceed the limit of {} bytes",
+                plugin.max_var_bytes()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Replace the state of `plugin` with the state stored in the snapshot, the whole snapshot
+    /// is validated and then the plugin is reinstantiated, so the snapshot is always restored
+    /// into a fresh instance
+    pub fn restore(&self, plugin: &mut Plugin) -> Result<(), Error> {
+        self.validate(plugin)?;
+
+        plugin.should_reinstantiate = false;
+        plugin.reinstantiate()?;
+
+        let store = &mut plugin.memory.store;
+        for (name, data) in self.memories.iter() {
+            let memory = match plugin.instance.get_memory(&mut *store, name) {
+                Some(x) => x,
+                None => return Err(Error::msg(format!("Memory not found: {name}"))),
+            };
+            grow(store, &memory, data.len())?;
+            let dest = memory.data_mut(&mut *store);
+            dest[..data.len()].copy_from_slice(data);
+            dest[data.len()..].fill(0);
+        }
+
+        for (name, value) in self.globals.iter() {
+            let global = match plugin.instance.get_global(&mut *store, name) {
+                Some(x) => x,
+                None => return Err(Error::msg(format!("Global not found: {name}"))),
+            };
+            global.set(&mut *store, value.clone())?;
+        }
+
+        let memory = plugin.memory.memory;
+        grow(store, &memory, self.memory.len())?;
+        memory.data_mut(&mut *store)[..self.memory.len()].copy_from_slice(&self.memory);
+
+        let internal = store.data_mut();
+        internal.output_offset = self.output_offset;
+        internal.output_length = self.output_length;
+
+        plugin.memory.position = self.position;
+        plugin.memory.high_water_mark = plugin.memory.high_water_mark.max(self.position);
+        plugin.memory.live_blocks = self.live_blocks.clone();
+        plugin.memory.free = self.free.clone();
+        plugin.vars = self.vars.clone();
+        Ok(())
+    }
+
+    /// Encode the snapshot as bytes
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::with_capacity(
+            self.memory.len() + self.memories.values().map(|x| x.len()).sum::<usize>() + 1024,
+        ));
+
+        w.0.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+        w.bytes(self.hash.as_bytes());
+
+        w.bytes(&self.memory);
+        w.u64(self.position as u64);
+        w.u64(self.live_blocks.len() as u64);
+        for (offset, length) in self.live_blocks.iter() {
+            w.u64(*offset as u64);
+            w.u64(*length as u64);
+        }
+        w.u64(self.free.len() as u64);
+        for block in self.free.iter() {
+            w.u64(block.offset as u64);
+            w.u64(block.length as u64);
+        }
+        w.u64(self.output_offset as u64);
+        w.u64(self.output_length as u64);
+
+        w.u64(self.memories.len() as u64);
+        for (name, data) in self.memories.iter() {
+            w.bytes(name.as_bytes());
+            w.bytes(data);
+        }
+
+        w.u64(self.globals.len() as u64);
+        for (name, value) in self.globals.iter() {
+            w.bytes(name.as_bytes());
+            let (tag, bits) = match value {
+                Val::I32(x) => (0, *x as u32 as u128),
+                Val::I64(x) => (1, *x as u64 as u128),
+                Val::F32(x) => (2, *x as u128),
+                Val::F64(x) => (3, *x as u128),
+                Val::V128(x) => (4, *x),
+                _ => unreachable!("reference type globals are never captured"),
+            };
+            w.0.push(tag);
+            w.0.extend_from_slice(&bits.to_le_bytes());
+        }
+
+        w.u64(self.vars.len() as u64);
+        for (k, v) in self.vars.iter() {
+            w.bytes(k.as_bytes());
+            w.bytes(v);
+        }
+
+        w.0
+    }
+
+    /// Decode a snapshot created using `Snapshot::encode`
+    pub fn decode(data: &[u8]) -> Result<Snapshot, Error> {
+        let mut r = Reader(data);
+
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(Error::msg("Invalid snapshot"));
+        }
+
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(Error::msg(format!(
+                "Unsupported snapshot version: {version}"
+            )));
+        }
+
+        let hash = r.string()?;
+
+        let memory = r.bytes()?.to_vec();
+        let position = r.usize()?;
+        let mut live_blocks = BTreeMap::new();
+        for _ in 0..r.u64()? {
+            live_blocks.insert(r.usize()?, r.usize()?);
+        }
+        let mut free = Vec::new();
+        for _ in 0..r.u64()? {
+            free.push(MemoryBlock::new(r.usize()?, r.usize()?));
+        }
+        let output_offset = r.usize()?;
+        let output_length = r.usize()?;
+
+        let mut memories = BTreeMap::new();
+        for _ in 0..r.u64()? {
+            memories.insert(r.string()?, r.bytes()?.to_vec());
+        }
+
+        let mut globals = BTreeMap::new();
+        for _ in 0..r.u64()? {
+            let name = r.string()?;
+            let tag = r.take(1)?[0];
+            let bits = u128::from_le_bytes(r.take(16)?.try_into()?);
+            let value = match tag {
+                0 => Val::I32(bits as u32 as i32),
+                1 => Val::I64(bits as u64 as i64),
+                2 => Val::F32(bits as u32),
+                3 => Val::F64(bits as u64),
+                4 => Val::V128(bits),
+                _ => {
+                    return Err(Error::msg(format!(
+                        "Invalid global type in snapshot: {tag}"
+                    )))
+                }
+            };
+            globals.insert(name, value);
+        }
+
+        let mut vars = BTreeMap::new();
+        for _ in 0..r.u64()? {
+            vars.insert(r.string()?, r.bytes()?.to_vec());
+        }
+
+        // Blocks and the output have to be inside the captured part of the extism memory, since
+        // they're used as offsets into the memory once the snapshot is restored
+        let in_bounds = |offset: usize, length: usize| {
+            offset
+                .checked_add(length)
+                .is_some_and(|end| end <= position)
+        };
+        if position != memory.len()
+            || !live_blocks
+                .iter()
+                .all(|(offset, length)| in_bounds(*offset, *length))
+            || !free.iter().all(|x| in_bounds(x.offset, x.length))
+            || !in_bounds(output_offset, output_length)
+        {
+            return Err(Error::msg("Invalid snapshot"));
+        }
+
+        Ok(Snapshot {
+            hash,
+            memory,
+            position,
+            live_blocks,
+            free,
+            output_offset,
+            output_length,
+            memories,
+            globals,
+            vars,
+        })
+    }
+}
+
+const PAGE_SIZE: usize = 65536;
+
+/// Check whether `size` bytes fit in a memory with at most `max_pages` pages
+fn fits(size: usize, max_pages: Option<u64>) -> bool {
+    match max_pages {
+        None => true,
+        Some(max) => (size as u64).div_ceil(PAGE_SIZE as u64) <= max,
+    }
+}
+
+/// Grow `memory` until it's at least `size` bytes
+fn grow(store: &mut Store<Internal>, memory: &Memory, size: usize) -> Result<(), Error> {
+    let current = memory.data_size(&*store);
+    if current < size {
+        let pages = (size - current).div_ceil(PAGE_SIZE);
+        memory.grow(&mut *store, pages as u64)?;
+    }
+    Ok(())
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, x: u32) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn bytes(&mut self, x: &[u8]) {
+        self.u64(x.len() as u64);
+        self.0.extend_from_slice(x);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.0.len() {
+            return Err(Error::msg("Unexpected end of snapshot"));
+        }
+        let (a, b) = self.0.split_at(n);
+        self.0 = b;
+        Ok(a)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, Error> {
+        Ok(usize::try_from(self.u64()?)?)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let n = self.usize()?;
+        self.take(n)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        Ok(std::str::from_utf8(self.bytes()?)?.to_string())
+    }
+}
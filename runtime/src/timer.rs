@@ -1,3 +1,7 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Instant;
+
 use crate::*;
 
 pub(crate) enum TimerAction {
@@ -24,51 +28,67 @@ extern "C" fn cleanup_timer() {
 impl Timer {
     pub fn init(timer: &mut Option<Timer>) -> std::sync::mpsc::SyncSender<TimerAction> {
         let (tx, rx) = std::sync::mpsc::sync_channel(128);
-        let thread = std::thread::spawn(move || {
-            let mut plugins = std::collections::BTreeMap::new();
+        let thread = std::thread::Builder::new()
+            .name("extism-timer".to_string())
+            .spawn(move || {
+                // Active deadlines, ordered by expiration time so the thread only needs to
+                // wake up when the first one expires or a new action is received
+                let mut queue: BTreeMap<(Instant, uuid::Uuid), Engine> = BTreeMap::new();
+                let mut deadlines: BTreeMap<uuid::Uuid, Instant> = BTreeMap::new();
+
+                loop {
+                    // Interrupt any plugins that have reached their deadline
+                    let now = Instant::now();
+                    while let Some(entry) = queue.first_entry() {
+                        if entry.key().0 > now {
+                            break;
+                        }
+
+                        let ((_, id), engine) = entry.remove_entry();
+                        deadlines.remove(&id);
+                        engine.increment_epoch();
+                    }
+
+                    let action = match queue.keys().next() {
+                        Some((deadline, _)) => {
+                            match rx.recv_timeout(deadline.saturating_duration_since(now)) {
+                                Ok(x) => x,
+                                Err(RecvTimeoutError::Timeout) => continue,
+                                Err(RecvTimeoutError::Disconnected) => return,
+                            }
+                        }
+                        None => match rx.recv() {
+                            Ok(x) => x,
+                            Err(_) => return,
+                        },
+                    };
 
-            macro_rules! handle {
-                ($x:expr) => {
-                    match $x {
+                    match action {
                         TimerAction::Start {
                             id,
                             engine,
                             duration,
                         } => {
-                            plugins.insert(id, (engine, std::time::Instant::now() + duration));
+                            if let Some(old) = deadlines.remove(&id) {
+                                queue.remove(&(old, id));
+                            }
+
+                            // Durations too large to be represented as a deadline never expire
+                            if let Some(deadline) = Instant::now().checked_add(duration) {
+                                deadlines.insert(id, deadline);
+                                queue.insert((deadline, id), engine);
+                            }
                         }
                         TimerAction::Stop { id } => {
-                            plugins.remove(&id);
+                            if let Some(deadline) = deadlines.remove(&id) {
+                                queue.remove(&(deadline, id));
+                            }
                         }
                         TimerAction::Shutdown => return,
                     }
-                };
-            }
-
-            loop {
-                if plugins.is_empty() {
-                    if let Ok(x) = rx.recv() {
-                        handle!(x)
-                    }
                 }
-
-                plugins = plugins
-                    .into_iter()
-                    .filter(|(_k, (engine, end))| {
-                        let now = std::time::Instant::now();
-                        if end <= &now {
-                            engine.increment_epoch();
-                            return false;
-                        }
-                        true
-                    })
-                    .collect();
-
-                for x in rx.try_iter() {
-                    handle!(x)
-                }
-            }
-        });
+            })
+            .expect("Unable to start timer thread");
         *timer = Some(Timer {
             thread: Some(thread),
             tx: tx.clone(),
@@ -1,6 +1,8 @@
 use crate::*;
 
-pub struct Context(pub(crate) std::sync::Arc<std::sync::Mutex<extism_runtime::Context>>);
+/// A `Context` is used to store and manage plugins, plugins in the same context can be used from
+/// multiple threads at once
+pub struct Context(pub(crate) std::sync::Arc<extism_runtime::Context>);
 
 impl Default for Context {
     fn default() -> Context {
@@ -11,20 +13,240 @@ impl Default for Context {
 impl Context {
     /// Create a new context
     pub fn new() -> Context {
-        Context(std::sync::Arc::new(std::sync::Mutex::new(
-            extism_runtime::Context::new(),
-        )))
+        Context(std::sync::Arc::new(extism_runtime::Context::new()))
+    }
+
+    /// Create a new context that meters fuel, this is required to use `fuel_limit` in a manifest
+    /// and for `Plugin::fuel_consumed` to report the fuel used by a call
+    pub fn new_with_fuel() -> Context {
+        Context(std::sync::Arc::new(extism_runtime::Context::new_with_fuel()))
+    }
+
+    /// Create a new context that supports `Plugin::call_async`, `Plugin::call` can still be used
+    /// but blocks the current thread until the call finishes
+    pub fn new_async() -> Context {
+        Context(std::sync::Arc::new(extism_runtime::Context::new_async()))
     }
 
     /// Remove all registered plugins
     pub fn reset(&mut self) {
-        unsafe { bindings::extism_context_reset(&mut *self.lock()) }
+        unsafe { bindings::extism_context_reset(self.as_ptr()) }
+    }
+
+    /// Set the directory used to cache precompiled modules, `None` disables the cache
+    pub fn set_cache_dir(&self, dir: Option<&std::path::Path>) -> Result<(), Error> {
+        let dir = match dir {
+            Some(dir) => Some(
+                std::ffi::CString::new(dir.to_string_lossy().as_bytes())
+                    .map_err(|e| Error::Message(e.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let ctx = self.as_ptr();
+        let ok = unsafe {
+            bindings::extism_context_set_cache_dir(
+                ctx,
+                dir.as_ref().map(|x| x.as_ptr()).unwrap_or(std::ptr::null()),
+            )
+        };
+        if ok {
+            return Ok(());
+        }
+
+        Err(self.last_error())
+    }
+
+    /// Set the maximum number of compiled modules kept in memory, `None` removes the limit
+    pub fn set_module_cache_size(&self, max: Option<usize>) {
+        self.0.set_module_cache_size(max)
+    }
+
+    /// Get the number of compiled modules kept in memory and how often they've been re-used
+    pub fn module_cache_stats(&self) -> ModuleCacheStats {
+        self.0.module_cache_stats()
+    }
+
+    /// Set the directory used to cache modules downloaded from URLs and the maximum total size of
+    /// the cached modules, `None` disables the cache or removes the size limit
+    pub fn set_download_cache(
+        &self,
+        dir: Option<&std::path::Path>,
+        max_size: Option<u64>,
+    ) -> Result<(), Error> {
+        let dir = match dir {
+            Some(dir) => Some(
+                std::ffi::CString::new(dir.to_string_lossy().as_bytes())
+                    .map_err(|e| Error::Message(e.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let ok = unsafe {
+            bindings::extism_context_set_download_cache(
+                self.as_ptr(),
+                dir.as_ref().map(|x| x.as_ptr()).unwrap_or(std::ptr::null()),
+                max_size.unwrap_or(0),
+            )
+        };
+        if ok {
+            return Ok(());
+        }
+
+        Err(self.last_error())
+    }
+
+    /// Cache modules downloaded from URLs that don't have a hash, this is disabled by default
+    ///
+    /// Modules without a hash aren't checked when they're loaded from the cache and aren't
+    /// downloaded again until they're removed, so a URL that always points to the latest version
+    /// of a module will keep loading the cached version
+    pub fn set_download_cache_unhashed(&self, enabled: bool) {
+        unsafe { bindings::extism_context_download_cache_unhashed(self.as_ptr(), enabled) }
+    }
+
+    /// List the modules in the download cache, the least recently used module is first
+    pub fn download_cache_entries(&self) -> Result<Vec<CacheEntry>, Error> {
+        let ptr = unsafe { bindings::extism_context_download_cache_list(self.as_ptr()) };
+        if ptr.is_null() {
+            return Err(self.last_error());
+        }
+
+        let s = unsafe { std::ffi::CStr::from_ptr(ptr) };
+        let entries = serde_json::from_slice(s.to_bytes());
+        unsafe { bindings::extism_string_free(ptr) };
+        Ok(entries?)
+    }
+
+    /// Remove the least recently used modules until the download cache is no larger than its
+    /// maximum size, returns the number of modules removed
+    pub fn prune_download_cache(&self) -> Result<usize, Error> {
+        let n = unsafe { bindings::extism_context_download_cache_prune(self.as_ptr()) };
+        if n < 0 {
+            return Err(self.last_error());
+        }
+        Ok(n as usize)
+    }
+
+    /// Remove every module from the download cache, returns the number of modules removed
+    pub fn clear_download_cache(&self) -> Result<usize, Error> {
+        let n = unsafe { bindings::extism_context_download_cache_clear(self.as_ptr()) };
+        if n < 0 {
+            return Err(self.last_error());
+        }
+        Ok(n as usize)
+    }
+
+    /// Set the function used to get the values of `${credential:NAME}` references in the headers
+    /// used to download modules, it's called with the name of the credential and the URL of the
+    /// module
+    pub fn set_credential_provider(
+        &self,
+        provider: impl Fn(&str, &str) -> Option<String> + Send + Sync + 'static,
+    ) {
+        self.0
+            .set_credential_provider(Some(std::sync::Arc::new(provider)))
+    }
+
+    /// Remove the credential provider, modules that use credentials fail to load until a new
+    /// provider is set
+    pub fn remove_credential_provider(&self) {
+        self.0.set_credential_provider(None)
+    }
+
+    /// Allow `${env:NAME}` references to the environment variable `name` in the headers used to
+    /// download modules, no environment variables can be used until they're allowed
+    pub fn allow_env_var(&self, name: impl AsRef<str>) -> Result<(), Error> {
+        let name =
+            std::ffi::CString::new(name.as_ref()).map_err(|e| Error::Message(e.to_string()))?;
+        if unsafe { bindings::extism_context_allow_env_var(self.as_ptr(), name.as_ptr()) } {
+            return Ok(());
+        }
+
+        Err(self.last_error())
     }
 
-    pub(crate) fn lock(&self) -> std::sync::MutexGuard<extism_runtime::Context> {
-        match self.0.lock() {
+    /// Stop allowing an environment variable to be used in headers, returns `false` if the
+    /// variable wasn't allowed
+    pub fn remove_allowed_env_var(&self, name: impl AsRef<str>) -> bool {
+        let name = match std::ffi::CString::new(name.as_ref()) {
             Ok(x) => x,
-            Err(x) => x.into_inner(),
+            Err(_) => return false,
+        };
+        unsafe { bindings::extism_context_remove_allowed_env_var(self.as_ptr(), name.as_ptr()) }
+    }
+
+    /// Check a JSON or TOML manifest for problems without creating a plugin, an empty list means
+    /// the manifest is valid. See `Manifest::validate`
+    pub fn validate_manifest(
+        &self,
+        data: impl AsRef<[u8]>,
+    ) -> Result<Vec<manifest::Diagnostic>, Error> {
+        let data = data.as_ref();
+        let ptr = unsafe {
+            bindings::extism_manifest_validate(self.as_ptr(), data.as_ptr(), data.len() as u64)
+        };
+        if ptr.is_null() {
+            return Err(self.last_error());
+        }
+
+        let s = unsafe { std::ffi::CStr::from_ptr(ptr) };
+        let diagnostics = serde_json::from_slice(s.to_bytes());
+        unsafe { bindings::extism_string_free(ptr) };
+        Ok(diagnostics?)
+    }
+
+    /// Get the last context error as an `Error`
+    pub(crate) fn last_error(&self) -> Error {
+        let err = unsafe { bindings::extism_error(self.as_ptr(), -1) };
+        let buf = unsafe { std::ffi::CStr::from_ptr(err) };
+        Error::Message(buf.to_string_lossy().to_string())
+    }
+
+    /// Trust modules signed by `public_key`, which must be a 32 byte ed25519 public key. Modules
+    /// refer to the key using the `key_id` field of their metadata
+    pub fn trust_key(&self, key_id: impl AsRef<str>, public_key: &[u8]) -> Result<(), Error> {
+        let key_id =
+            std::ffi::CString::new(key_id.as_ref()).map_err(|e| Error::Message(e.to_string()))?;
+        let ctx = self.as_ptr();
+        let ok = unsafe {
+            bindings::extism_context_trust_key(
+                ctx,
+                key_id.as_ptr(),
+                public_key.as_ptr(),
+                public_key.len() as u64,
+            )
+        };
+        if ok {
+            return Ok(());
         }
+
+        Err(self.last_error())
+    }
+
+    /// Stop trusting a key, returns `false` if the key wasn't trusted
+    pub fn remove_trusted_key(&self, key_id: impl AsRef<str>) -> bool {
+        let key_id = match std::ffi::CString::new(key_id.as_ref()) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        unsafe { bindings::extism_context_remove_trusted_key(self.as_ptr(), key_id.as_ptr()) }
+    }
+
+    /// Only allow plugins to be created from modules signed by a trusted key
+    pub fn require_signatures(&self, required: bool) {
+        unsafe { bindings::extism_context_require_signatures(self.as_ptr(), required) }
+    }
+
+    /// Get the code for the last context error, for example the reason a plugin couldn't be
+    /// created
+    pub fn error_code(&self) -> ErrorCode {
+        unsafe { bindings::extism_error_code(self.as_ptr(), -1) }
+    }
+
+    /// Get a pointer to the context that can be passed to the C API, the context is internally
+    /// synchronized so the pointer can be used from multiple threads
+    pub(crate) fn as_ptr(&self) -> *mut extism_runtime::Context {
+        std::sync::Arc::as_ptr(&self.0) as *mut _
     }
 }
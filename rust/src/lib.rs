@@ -1,13 +1,19 @@
 pub use extism_manifest::{self as manifest, Manifest};
-pub use extism_runtime::{sdk as bindings, Function, ValType};
+pub use extism_runtime::{
+    sdk as bindings, CacheEntry, CancelHandle, CurrentPlugin, ErrorCode, Export, ExternKind,
+    ExtismMemoryStats, Function, GuestMemoryStats, Import, ImportProvider, MemoryBlock,
+    MemoryStats, ModuleCacheStats, PluginInfo, Val, ValType, VarStoreStats, PLUGIN_LOG_TARGET,
+};
 
 mod context;
 mod plugin;
 mod plugin_builder;
+mod pool;
 
 pub use context::Context;
 pub use plugin::Plugin;
 pub use plugin_builder::PluginBuilder;
+pub use pool::PluginPool;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -25,17 +31,29 @@ pub enum Error {
 pub fn extism_version() -> String {
     let err = unsafe { bindings::extism_version() };
     let buf = unsafe { std::ffi::CStr::from_ptr(err) };
-    return buf.to_str().unwrap().to_string();
+    buf.to_str().unwrap().to_string()
 }
 
 /// Set the log file and level, this is a global setting
+///
+/// This isn't needed if the application already uses a `log` logger (or a `tracing` subscriber
+/// with `tracing-log`), because messages from the runtime and plugins are sent to that logger.
+/// Plugin messages use the `PLUGIN_LOG_TARGET` target and include a `plugin` key with the plugin
+/// ID. This returns `false` if the application has already installed its own logger
 pub fn set_log_file(filename: impl AsRef<std::path::Path>, log_level: Option<log::Level>) -> bool {
-    let log_level = log_level.map(|x| x.as_str());
+    let filename = match std::ffi::CString::new(filename.as_ref().to_string_lossy().as_bytes()) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    let log_level = log_level.map(|x| std::ffi::CString::new(x.as_str()).unwrap());
     unsafe {
-        return bindings::extism_log_file(
-            filename.as_ref().as_os_str().to_string_lossy().as_ptr() as *const _,
-            log_level.map(|x| x.as_ptr()).unwrap_or(std::ptr::null()) as *const _,
-        );
+        bindings::extism_log_file(
+            filename.as_ptr(),
+            log_level
+                .as_ref()
+                .map(|x| x.as_ptr())
+                .unwrap_or(std::ptr::null()),
+        )
     }
 }
 
@@ -46,7 +64,54 @@ mod tests {
 
     const WASM: &[u8] = include_bytes!("../../wasm/code.wasm");
 
+    /// Start an HTTP server on a random port that responds to every request with `body`, returns
+    /// the port and the number of requests received
+    fn serve(body: &'static [u8]) -> (u16, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        serve_with(move |_| (200, body))
+    }
+
+    /// Start an HTTP server on a random port that passes each request to `handler`, which returns
+    /// the status and body of the response. For redirects the body is sent as the `Location`
+    /// header instead. Returns the port and the number of requests received
+    fn serve_with(
+        handler: impl Fn(&str) -> (u16, &'static [u8]) + Send + Sync + 'static,
+    ) -> (u16, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let count = requests.clone();
+        let handler = std::sync::Arc::new(handler);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let count = count.clone();
+                let handler = handler.clone();
+                std::thread::spawn(move || {
+                    let mut buf = [0; 4096];
+                    let n = stream.read(&mut buf).unwrap_or(0);
+                    count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let (status, mut body) = handler(&String::from_utf8_lossy(&buf[..n]));
+                    let mut location = String::new();
+                    if (300..400).contains(&status) {
+                        location = format!("Location: {}\r\n", String::from_utf8_lossy(body));
+                        body = b"";
+                    }
+                    let header = format!(
+                        "HTTP/1.1 {status} Status\r\n{location}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _ = stream.write_all(header.as_bytes());
+                    let _ = stream.write_all(body);
+                });
+            }
+        });
+        (port, requests)
+    }
+
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn it_works() {
         let wasm_start = Instant::now();
         set_log_file("test.log", Some(log::Level::Info));
@@ -138,6 +203,1497 @@ mod tests {
         println!("wasm function call (avg, N = {}): {:?}", num_tests, avg);
     }
 
+    #[test]
+    fn test_module_cache() {
+        let context = Context::new();
+
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        let mut cached = Plugin::new(&context, WASM, false).unwrap();
+        let stats = context.module_cache_stats();
+        assert_eq!((stats.modules, stats.hits, stats.misses), (1, 1, 1));
+
+        let a = plugin.call("count_vowels", "abc").unwrap();
+        let b = cached.call("count_vowels", "abc").unwrap();
+        assert_eq!(a, b);
+
+        // The least recently used modules are removed when the cache is full
+        const EMPTY: &[u8] = b"(module)";
+        context.set_module_cache_size(Some(1));
+        Plugin::new(&context, EMPTY, false).unwrap();
+        let stats = context.module_cache_stats();
+        assert_eq!((stats.modules, stats.max_modules), (1, Some(1)));
+        Plugin::new(&context, EMPTY, false).unwrap();
+        assert_eq!(context.module_cache_stats().hits, 2);
+        Plugin::new(&context, WASM, false).unwrap();
+        assert_eq!(context.module_cache_stats().misses, 3);
+
+        // Plugins using a removed module still work
+        assert_eq!(plugin.call("count_vowels", "abc").unwrap(), a);
+    }
+
+    #[test]
+    fn test_cache_dir() {
+        let dir = std::env::temp_dir().join(format!("extism-test-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let context = Context::new();
+        context.set_cache_dir(Some(&dir)).unwrap();
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        let expected = plugin.call("count_vowels", "aaa").unwrap().to_vec();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // A new context starts with an empty in-memory cache, so the module is loaded from disk
+        let context = Context::new();
+        context.set_cache_dir(Some(&dir)).unwrap();
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        assert_eq!(plugin.call("count_vowels", "aaa").unwrap(), expected);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The key is derived from the settings that affect the compiled code, metering fuel
+        // changes the code so a separate module is stored
+        let context = Context::new_with_fuel();
+        context.set_cache_dir(Some(&dir)).unwrap();
+        Plugin::new(&context, WASM, false).unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // Async support doesn't, so async contexts (which always meter fuel) share the module
+        let context = Context::new_async();
+        context.set_cache_dir(Some(&dir)).unwrap();
+        Plugin::new(&context, WASM, false).unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fuel_limit() {
+        // Fuel limits can only be used in contexts that meter fuel
+        let context = Context::new();
+        let manifest = Manifest::new([manifest::Wasm::data(WASM)]).with_fuel_limit(100);
+        assert!(Plugin::new_with_manifest(&context, &manifest, false).is_err());
+        assert_eq!(context.error_code(), ErrorCode::ManifestInvalid);
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        plugin.call("count_vowels", "this is a test").unwrap();
+        assert_eq!(plugin.fuel_consumed(), 0);
+
+        let context = Context::new_with_fuel();
+        let manifest = Manifest::new([manifest::Wasm::data(WASM)]).with_fuel_limit(100);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        match plugin.call("count_vowels", "this is a test") {
+            Err(Error::Message(msg)) => assert_eq!(msg, "out of fuel"),
+            _ => panic!("expected call to run out of fuel"),
+        }
+        assert!(plugin.fuel_consumed() >= 100);
+
+        let manifest = Manifest::new([manifest::Wasm::data(WASM)]).with_fuel_limit(1_000_000);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        plugin.call("count_vowels", "this is a test").unwrap();
+        let consumed = plugin.fuel_consumed();
+        assert!(consumed > 0 && consumed < 1_000_000);
+
+        // Fuel is refilled before each call
+        let manifest = Manifest::new([manifest::Wasm::data(WASM)]).with_fuel_limit(consumed * 2);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        for _ in 0..5 {
+            plugin.call("count_vowels", "this is a test").unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_call_async() {
+        let context = Context::new_async();
+        let mut a = Plugin::new(&context, WASM, false).unwrap();
+        let mut b = Plugin::new(&context, WASM, false).unwrap();
+        let (x, y) = tokio::join!(
+            a.call_async("count_vowels", "aaa"),
+            b.call_async("count_vowels", "this is a test")
+        );
+        assert_eq!(x.unwrap(), b"{\"count\": 3}");
+        assert_eq!(y.unwrap(), b"{\"count\": 4}");
+
+        // Errors from failed async calls are recorded like errors from synchronous calls
+        assert!(a.call_async("missing", "").await.is_err());
+        assert_eq!(a.error_code(), ErrorCode::FunctionNotFound);
+
+        // Synchronous calls still work in an async context
+        assert_eq!(a.call("count_vowels", "aaa").unwrap(), b"{\"count\": 3}");
+
+        // Futures can be spawned onto the runtime
+        let output = tokio::spawn(async {
+            let context = Context::new_async();
+            let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+            plugin.call_async("count_vowels", "abc").await
+        })
+        .await
+        .unwrap();
+        assert_eq!(output.unwrap(), b"{\"count\": 1}");
+    }
+
+    #[tokio::test]
+    async fn test_call_async_timeout() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        const LOOP: &[u8] = b"(module (func (export \"run\") (loop br 0)))";
+
+        let context = Context::new_async();
+        let manifest = Manifest::new([manifest::Wasm::data(LOOP)])
+            .with_timeout(std::time::Duration::from_millis(100));
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+
+        // The call yields while looping, so it's polled more than once and the other future
+        // makes progress before the call finishes. This test uses a single threaded runtime, so
+        // the other future can only run while the call is suspended
+        let polls = AtomicUsize::new(0);
+        let ticks = AtomicUsize::new(0);
+        let done = AtomicBool::new(false);
+        let mut busy = unsafe { Plugin::from_id(plugin.as_i32(), &context) };
+        let start = Instant::now();
+        let call = async {
+            let mut call = std::pin::pin!(plugin.call_async("run", ""));
+            let res = std::future::poll_fn(|cx| {
+                polls.fetch_add(1, Ordering::SeqCst);
+                std::future::Future::poll(call.as_mut(), cx)
+            })
+            .await;
+            done.store(true, Ordering::SeqCst);
+            res
+        };
+        let ticker = async {
+            // Calls to a plugin that's already running fail instead of blocking the thread
+            tokio::task::yield_now().await;
+            match busy.call_async("run", "").await {
+                Err(Error::Message(msg)) => assert!(msg.contains("in use"), "{msg}"),
+                _ => panic!("expected call to fail while the plugin is in use"),
+            }
+
+            while !done.load(Ordering::SeqCst) {
+                ticks.fetch_add(1, Ordering::SeqCst);
+                tokio::task::yield_now().await;
+            }
+        };
+        let (res, _) = tokio::join!(call, ticker);
+        std::mem::forget(busy);
+        match res {
+            Err(Error::Message(msg)) => assert_eq!(msg, "timeout"),
+            _ => panic!("expected call to time out"),
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(polls.load(Ordering::SeqCst) > 1);
+        assert!(ticks.load(Ordering::SeqCst) > 0);
+
+        // Synchronous calls in an async context block the thread until they finish, even inside
+        // an async runtime, but timeouts are still enforced
+        let start = Instant::now();
+        match plugin.call("run", "") {
+            Err(Error::Message(msg)) => assert_eq!(msg, "timeout"),
+            _ => panic!("expected call to time out"),
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_cancel() {
+        const LOOP: &[u8] = b"(module (func (export \"run\") (loop br 0)))";
+
+        let context = Context::new();
+        let manifest = Manifest::new([manifest::Wasm::data(LOOP)]);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        let handle = plugin.cancel_handle().unwrap();
+
+        let start = Instant::now();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            handle.cancel();
+        });
+
+        match plugin.call("run", "") {
+            Err(Error::Message(msg)) => assert_eq!(msg, "cancelled"),
+            _ => panic!("expected call to be cancelled"),
+        }
+        assert_eq!(plugin.error_code(), ErrorCode::Cancelled);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        // Handles can be used after the plugin is freed
+        let handle = plugin.cancel_handle().unwrap();
+        drop(plugin);
+        handle.cancel();
+
+        // Cancelling before a call starts cancels that call, but not the calls after it
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        plugin.cancel_handle().unwrap().cancel();
+        assert!(plugin.call("count_vowels", "this is a test").is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::Cancelled);
+        plugin.call("count_vowels", "this is a test").unwrap();
+    }
+
+    #[test]
+    fn test_call_with_timeout() {
+        const LOOP: &[u8] = b"(module (func (export \"run\") (loop br 0)))";
+
+        let context = Context::new();
+        let manifest = Manifest::new([manifest::Wasm::data(LOOP)])
+            .with_timeout(std::time::Duration::from_secs(60));
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+
+        let start = Instant::now();
+        match plugin.call_with_timeout("run", "", std::time::Duration::from_millis(100)) {
+            Err(Error::Message(msg)) => assert_eq!(msg, "timeout"),
+            _ => panic!("expected call to time out"),
+        }
+        assert_eq!(plugin.error_code(), ErrorCode::Timeout);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        // Timeouts that can't be represented as a deadline never expire
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        let output = plugin
+            .call_with_timeout(
+                "count_vowels",
+                "this is a test",
+                std::time::Duration::from_millis(u64::MAX),
+            )
+            .unwrap();
+        assert_eq!(output, b"{\"count\": 4}");
+    }
+
+    #[test]
+    fn test_snapshot() {
+        // Increments a global and a counter stored in the guest memory, the output is
+        // `count + memory[0] * 16`
+        const COUNTER: &[u8] = br#"
+            (module
+                (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+                (import "env" "extism_store_u8" (func $store_u8 (param i64 i32)))
+                (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+                (memory (export "memory") 1)
+                (global $count (export "count") (mut i32) (i32.const 0))
+                (func (export "incr") (result i32) (local $p i64)
+                    (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                    (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                    (local.set $p (call $alloc (i64.const 1)))
+                    (call $store_u8 (local.get $p)
+                        (i32.add (global.get $count) (i32.mul (i32.load (i32.const 0)) (i32.const 16))))
+                    (call $output_set (local.get $p) (i64.const 1))
+                    (i32.const 0)))
+        "#;
+
+        let context = Context::new();
+        let manifest = Manifest::new([manifest::Wasm::data(COUNTER)]);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+
+        for _ in 0..3 {
+            plugin.call("incr", "").unwrap();
+        }
+        let snapshot = plugin.snapshot().unwrap();
+        assert_eq!(plugin.call("incr", "").unwrap(), [17 * 4]);
+        assert_eq!(plugin.call("incr", "").unwrap(), [17 * 5]);
+
+        // Roll back, the plugin is reinstantiated in a new store but its cancel handle still
+        // works
+        let handle = plugin.cancel_handle().unwrap();
+        plugin.restore(&snapshot).unwrap();
+        handle.cancel();
+        assert!(plugin.call("incr", "").is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::Cancelled);
+        assert_eq!(plugin.call("incr", "").unwrap(), [17 * 4]);
+
+        // Fork into a new instance
+        let mut fork = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        fork.restore(&snapshot).unwrap();
+        assert_eq!(fork.call("incr", "").unwrap(), [17 * 4]);
+
+        // Snapshots can't be restored into a different module
+        let mut other = Plugin::new(&context, WASM, false).unwrap();
+        assert!(other.restore(&snapshot).is_err());
+        assert!(other.restore(b"invalid").is_err());
+
+        // Snapshots with blocks outside of the captured memory are rejected
+        let memory_len = u64::from_le_bytes(snapshot[84..92].try_into().unwrap()) as usize;
+        let live_blocks = 92 + memory_len + 8;
+        assert!(u64::from_le_bytes(snapshot[live_blocks..live_blocks + 8].try_into().unwrap()) > 0);
+        let mut invalid = snapshot.clone();
+        invalid[live_blocks + 16..live_blocks + 24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(plugin.restore(&invalid).is_err());
+        assert_eq!(plugin.call("incr", "").unwrap(), [17 * 5]);
+
+        // Snapshots that don't match the module are rejected before the plugin is reset, the
+        // last global is followed by its 16 byte value and the number of vars
+        let mut invalid = snapshot.clone();
+        let tag = invalid.len() - 8 - 16 - 1;
+        assert_eq!(invalid[tag], 0);
+        invalid[tag] = 1;
+        assert!(plugin.restore(&invalid).is_err());
+        assert_eq!(plugin.call("incr", "").unwrap(), [17 * 6]);
+
+        // Including modules with the same imports and exports
+        let same_interface = String::from_utf8(COUNTER.to_vec())
+            .unwrap()
+            .replace("(i32.const 1)))", "(i32.const 2)))");
+        let manifest = Manifest::new([manifest::Wasm::data(same_interface.into_bytes())]);
+        let mut other = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        assert!(other.restore(&snapshot).is_err());
+    }
+
+    #[test]
+    fn test_vars() {
+        // Outputs the value of the var `k` and then sets it to `b`
+        const SWAP: &[u8] = br#"(module
+            (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+            (import "env" "extism_length" (func $length (param i64) (result i64)))
+            (import "env" "extism_store_u8" (func $store_u8 (param i64 i32)))
+            (import "env" "extism_var_get" (func $var_get (param i64) (result i64)))
+            (import "env" "extism_var_set" (func $var_set (param i64 i64)))
+            (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "swap") (result i32) (local $key i64) (local $value i64)
+                (local.set $key (call $alloc (i64.const 1)))
+                (call $store_u8 (local.get $key) (i32.const 107))
+                (local.set $value (call $var_get (local.get $key)))
+                (if (i64.ne (local.get $value) (i64.const 0))
+                    (then (call $output_set (local.get $value) (call $length (local.get $value))))
+                    (else (call $output_set (i64.const 0) (i64.const 0))))
+                (local.set $value (call $alloc (i64.const 1)))
+                (call $store_u8 (local.get $value) (i32.const 98))
+                (call $var_set (local.get $key) (local.get $value))
+                (i32.const 0)))"#;
+
+        let context = Context::new();
+        let mut plugin = Plugin::new(&context, SWAP, false).unwrap();
+        assert!(plugin.vars().unwrap().is_empty());
+        assert_eq!(plugin.var("k").unwrap(), None);
+
+        // Seed the var before calling
+        plugin.set_var("k", "a").unwrap();
+        assert_eq!(plugin.call("swap", "").unwrap(), b"a");
+        assert_eq!(plugin.var("k").unwrap(), Some(b"b".to_vec()));
+        assert_eq!(plugin.vars().unwrap(), ["k"]);
+
+        assert!(plugin.remove_var("k").unwrap());
+        assert!(!plugin.remove_var("k").unwrap());
+        assert_eq!(plugin.call("swap", "").unwrap(), b"");
+        assert_eq!(plugin.var("k").unwrap(), Some(b"b".to_vec()));
+
+        plugin.set_var("other", [0, 1, 2]).unwrap();
+        assert_eq!(plugin.vars().unwrap(), ["k", "other"]);
+        plugin.clear_vars().unwrap();
+        assert!(plugin.vars().unwrap().is_empty());
+        assert!(plugin.set_var("invalid\0", "").is_err());
+
+        // Errors aren't reported as a missing var
+        let missing = unsafe { Plugin::from_id(i32::MAX, &context) };
+        assert!(missing.var("k").is_err());
+        assert_eq!(missing.error_code(), ErrorCode::PluginNotFound);
+        std::mem::forget(missing);
+
+        // Replacing a value only counts the new value towards the limit
+        let mut manifest = Manifest::new([manifest::Wasm::data(SWAP)]);
+        manifest.memory.max_var_bytes = Some(2);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        plugin.set_var("k", "ab").unwrap();
+        plugin.set_var("k", "cd").unwrap();
+        assert!(plugin.set_var("other", "e").is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::LimitExceeded);
+        let stats = plugin.memory_stats().unwrap();
+        assert_eq!(
+            stats.vars,
+            VarStoreStats {
+                count: 1,
+                bytes: 2,
+                max_bytes: 2
+            }
+        );
+
+        // The plugin replaces `cd` with `b`, but setting `k` from the plugin fails once the
+        // store is full
+        plugin.remove_var("k").unwrap();
+        plugin.set_var("other", "ef").unwrap();
+        assert!(plugin.call("swap", "").is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::LimitExceeded);
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        // `fragment` allocates four 100 byte blocks and frees the first and third
+        const WASM: &[u8] = br#"(module
+            (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+            (import "env" "extism_free" (func $free (param i64)))
+            (memory (export "memory") 1 2)
+            (func (export "fragment") (result i32) (local $a i64) (local $c i64)
+                (local.set $a (call $alloc (i64.const 100)))
+                (drop (call $alloc (i64.const 100)))
+                (local.set $c (call $alloc (i64.const 100)))
+                (drop (call $alloc (i64.const 100)))
+                (call $free (local.get $a))
+                (call $free (local.get $c))
+                (i32.const 0))
+            (func (export "grow") (result i32)
+                (drop (memory.grow (i32.const 1)))
+                (i32.const 0)))"#;
+
+        let context = Context::new();
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        plugin.call("fragment", "").unwrap();
+
+        let stats = plugin.memory_stats().unwrap();
+        assert_eq!(stats.extism.pages, 4);
+        assert_eq!(stats.extism.max_pages, None);
+        assert_eq!(stats.extism.live_bytes, 200);
+        assert_eq!(stats.extism.live_blocks, 2);
+        assert_eq!(stats.extism.free_bytes, 200);
+        assert_eq!(stats.extism.free_blocks, 2);
+        assert_eq!(stats.extism.fragmentation, 0.5);
+        assert_eq!(stats.extism.high_water_mark, 401);
+        assert_eq!(stats.vars.max_bytes, 1024 * 1024 * 100);
+        assert_eq!(stats.max_http_response_bytes, 1024 * 1024 * 50);
+        assert_eq!(
+            stats.guest,
+            Some(GuestMemoryStats {
+                pages: 1,
+                max_pages: Some(2),
+                high_water_mark: 65536,
+            })
+        );
+
+        // The extism memory is reset before each call, but the high-water mark is kept
+        plugin.call("grow", "").unwrap();
+        let stats = plugin.memory_stats().unwrap();
+        assert_eq!(stats.extism.live_bytes, 0);
+        assert_eq!(stats.extism.free_bytes, 0);
+        assert_eq!(stats.extism.fragmentation, 0.0);
+        assert_eq!(stats.extism.high_water_mark, 401);
+        let guest = stats.guest.unwrap();
+        assert_eq!(guest.pages, 2);
+        assert_eq!(guest.high_water_mark, 131072);
+    }
+
+    #[test]
+    fn test_allowed_host_rules() {
+        // Sends the input to `extism_http_request` and outputs the response body
+        const REQUEST: &[u8] = br#"(module
+            (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+            (import "env" "extism_length" (func $length (param i64) (result i64)))
+            (import "env" "extism_store_u8" (func $store_u8 (param i64 i32)))
+            (import "env" "extism_input_length" (func $input_length (result i64)))
+            (import "env" "extism_input_load_u8" (func $input_load_u8 (param i64) (result i32)))
+            (import "env" "extism_http_request" (func $http_request (param i64 i64) (result i64)))
+            (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "request") (result i32)
+                (local $len i64) (local $req i64) (local $i i64) (local $res i64)
+                (local.set $len (call $input_length))
+                (local.set $req (call $alloc (local.get $len)))
+                (block $done
+                    (loop $copy
+                        (br_if $done (i64.ge_u (local.get $i) (local.get $len)))
+                        (call $store_u8
+                            (i64.add (local.get $req) (local.get $i))
+                            (call $input_load_u8 (local.get $i)))
+                        (local.set $i (i64.add (local.get $i) (i64.const 1)))
+                        (br $copy)))
+                (local.set $res (call $http_request (local.get $req) (i64.const 0)))
+                (call $output_set (local.get $res) (call $length (local.get $res)))
+                (i32.const 0)))"#;
+
+        let (port, _) = serve(b"ok");
+
+        let rule = manifest::HostRule::new("127.0.0.1")
+            .with_scheme("http")
+            .with_port(port)
+            .with_path_prefix("/api")
+            .with_method("GET");
+        let manifest = Manifest::new([manifest::Wasm::data(REQUEST)]).with_allowed_host(rule);
+        let context = Context::new();
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+
+        let mut request = |url: String, method: &str| {
+            let req = manifest::HttpRequest::new(url).with_method(method);
+            let res = plugin.call("request", serde_json::to_vec(&req).unwrap());
+            res.map(|x| x.to_vec()).map_err(|_| plugin.error_code())
+        };
+
+        assert_eq!(
+            request(format!("http://127.0.0.1:{port}/api/x"), "GET").unwrap(),
+            b"ok"
+        );
+        assert_eq!(
+            request(format!("http://127.0.0.1:{port}/api"), "get").unwrap(),
+            b"ok"
+        );
+        for (url, method) in [
+            (format!("http://127.0.0.1:{port}/apix"), "GET"),
+            (format!("http://127.0.0.1:{port}/"), "GET"),
+            (format!("http://127.0.0.1:{port}/api/x"), "POST"),
+            (format!("https://127.0.0.1:{port}/api/x"), "GET"),
+            (format!("http://127.0.0.1:{}/api/x", port + 1), "GET"),
+            (format!("http://localhost:{port}/api/x"), "GET"),
+        ] {
+            assert_eq!(request(url, method), Err(ErrorCode::HttpDenied));
+        }
+
+        // Responses larger than the limit are rejected instead of being truncated
+        let mut manifest = manifest;
+        manifest.memory.max_http_response_bytes = Some(1);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        let req = manifest::HttpRequest::new(format!("http://127.0.0.1:{port}/api"));
+        assert!(plugin
+            .call("request", serde_json::to_vec(&req).unwrap())
+            .is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::LimitExceeded);
+
+        // Redirects are checked against the rules
+        let (other_port, other_requests) = serve(b"other");
+        let other: &'static str =
+            Box::leak(format!("http://127.0.0.1:{other_port}/api/x").into_boxed_str());
+        let (port, _) = serve_with(move |req| match req.split_whitespace().nth(1) {
+            Some("/api/other-port") => (302, other.as_bytes()),
+            Some("/api/other-path") => (302, b"/admin"),
+            Some("/api/same") => (307, b"/api/x"),
+            _ => (200, b"ok"),
+        });
+        let rule = manifest::HostRule::new("127.0.0.1")
+            .with_port(port)
+            .with_path_prefix("/api");
+        let manifest = Manifest::new([manifest::Wasm::data(REQUEST)]).with_allowed_host(rule);
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+        let mut request = |path: &str| {
+            let req = manifest::HttpRequest::new(format!("http://127.0.0.1:{port}{path}"));
+            let res = plugin.call("request", serde_json::to_vec(&req).unwrap());
+            res.map_err(|_| plugin.error_code())
+        };
+        assert_eq!(request("/api/same").unwrap(), b"ok");
+        assert_eq!(request("/api/other-port"), Err(ErrorCode::HttpDenied));
+        assert_eq!(request("/api/other-path"), Err(ErrorCode::HttpDenied));
+        assert_eq!(other_requests.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // Bare host names and rules can be mixed
+        let manifest: Manifest = serde_json::from_str(
+            r#"{"allowed_hosts": ["*.example.com", {"host": "example.org", "port": 443}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.allowed_hosts.unwrap(),
+            [
+                manifest::AllowedHost::Host("*.example.com".to_string()),
+                manifest::AllowedHost::Rule(manifest::HostRule::new("example.org").with_port(443)),
+            ]
+        );
+
+        // Misspelled fields are rejected instead of being ignored
+        for rule in [
+            r#"{"host": "example.org", "schema": "https"}"#,
+            r#"{"host": "example.org", "method": ["GET"]}"#,
+        ] {
+            let manifest = format!(r#"{{"allowed_hosts": [{rule}]}}"#);
+            assert!(serde_json::from_str::<Manifest>(&manifest).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_manifest() {
+        use manifest::DiagnosticKind::*;
+
+        let manifest = r#"{
+            "wasm": [
+                {"path": "missing.wasm", "hash": "abc"},
+                {"url": "not a url", "name": "missing", "timeout_ms": 0, "retries": 11,
+                 "retry_backoff_ms": 60000,
+                 "headers": {"A/B": "Bearer ${credential:token", "C": "$${env:X} ${env:X}"}},
+                {"data": "AGFzbQEAAAA="}
+            ],
+            "memory": {"max_pages": 0},
+            "allowed_paths": {"/does/not/exist": "/x"}
+        }"#;
+        let context = Context::new();
+        let diagnostics: Vec<_> = context
+            .validate_manifest(manifest)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.kind, x.path))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                (InvalidHash, "/wasm/0/hash".to_string()),
+                (MissingFile, "/wasm/0/path".to_string()),
+                (InvalidUrl, "/wasm/1/url".to_string()),
+                (InvalidValue, "/wasm/1/timeout_ms".to_string()),
+                (InvalidValue, "/wasm/1/retries".to_string()),
+                (InvalidValue, "/wasm/1/retry_backoff_ms".to_string()),
+                (InvalidValue, "/wasm/1/headers/A~1B".to_string()),
+                (DuplicateName, "/wasm/1/name".to_string()),
+                (InvalidValue, "/memory/max_pages".to_string()),
+                (MissingPath, "/allowed_paths/~1does~1not~1exist".to_string()),
+            ]
+        );
+
+        // The JSON error is reported instead of the TOML error
+        let diagnostics = context.validate_manifest(r#"{"wasm": 1}"#).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, Parse);
+        assert!(diagnostics[0].message.contains("line 1"));
+
+        assert!(context.validate_manifest(WASM).unwrap().is_empty());
+        assert!(Manifest::new([manifest::Wasm::data(WASM)])
+            .validate()
+            .is_empty());
+        assert_eq!(
+            Manifest::default().validate(),
+            [manifest::Diagnostic::new(
+                NoWasm,
+                "/wasm",
+                "No wasm files specified"
+            )]
+        );
+
+        // Modules with the same name are rejected when the manifest is loaded
+        let named = |name: &str| manifest::Wasm::Data {
+            data: WASM.to_vec(),
+            meta: manifest::WasmMetadata {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+        };
+        let manifest = Manifest::new([named("a"), named("a")]);
+        assert!(Plugin::new_with_manifest(&context, &manifest, false).is_err());
+        assert_eq!(context.error_code(), ErrorCode::ManifestInvalid);
+    }
+
+    #[test]
+    fn test_signatures() {
+        use ed25519_dalek::Signer;
+
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let signed = |wasm: manifest::Wasm, data: &[u8], key_id: &str| {
+            let mut wasm = wasm;
+            let meta = wasm.meta_mut();
+            meta.signature = Some(base64::encode(key.sign(data).to_bytes()));
+            meta.key_id = Some(key_id.to_string());
+            Manifest::new([wasm])
+        };
+        let valid = signed(manifest::Wasm::data(WASM), WASM, "release");
+        let tampered = signed(manifest::Wasm::data(WASM), b"other code", "release");
+        let unknown_key = signed(manifest::Wasm::data(WASM), WASM, "other");
+        let unsigned = Manifest::new([manifest::Wasm::data(WASM)]);
+
+        // Signatures aren't required by default and unknown keys are ignored
+        let context = Context::new();
+        assert!(Plugin::new_with_manifest(&context, &unsigned, false).is_ok());
+        assert!(Plugin::new_with_manifest(&context, &tampered, false).is_ok());
+
+        // Signatures from trusted keys are always verified
+        context
+            .trust_key("release", key.verifying_key().as_bytes())
+            .unwrap();
+        let mut plugin = Plugin::new_with_manifest(&context, &valid, false).unwrap();
+        assert!(plugin.call("count_vowels", "abc").is_ok());
+        assert!(Plugin::new_with_manifest(&context, &tampered, false).is_err());
+        assert_eq!(context.error_code(), ErrorCode::SignatureInvalid);
+
+        context.require_signatures(true);
+        for manifest in [&unsigned, &unknown_key] {
+            assert!(Plugin::new_with_manifest(&context, manifest, false).is_err());
+            assert_eq!(context.error_code(), ErrorCode::SignatureInvalid);
+        }
+        assert!(Plugin::new(&context, WASM, false).is_err());
+        assert!(Plugin::new_with_manifest(&context, &valid, false).is_ok());
+
+        let path = std::env::temp_dir().join(format!("extism-signed-{}.wasm", std::process::id()));
+        std::fs::write(&path, WASM).unwrap();
+        let file = signed(manifest::Wasm::file(&path), WASM, "release");
+        assert!(Plugin::new_with_manifest(&context, &file, false).is_ok());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(context.remove_trusted_key("release"));
+        assert!(!context.remove_trusted_key("release"));
+        assert!(Plugin::new_with_manifest(&context, &valid, false).is_err());
+        assert!(context.trust_key("short", &[0; 3]).is_err());
+    }
+
+    #[test]
+    fn test_hash_algorithms() {
+        use sha2::Digest;
+
+        let hex = |digest: &[u8]| -> String { digest.iter().map(|x| format!("{x:02x}")).collect() };
+        let sha256 = hex(&sha2::Sha256::digest(WASM));
+        let sha512 = hex(&sha2::Sha512::digest(WASM));
+        let blake3 = hex(blake3::hash(WASM).as_bytes());
+
+        let with_hash = |hash: String| {
+            let mut wasm = manifest::Wasm::data(WASM);
+            wasm.meta_mut().hash = Some(hash);
+            Manifest::new([wasm])
+        };
+
+        let context = Context::new();
+        for hash in [
+            sha256.clone(),
+            format!("sha256:{}", sha256.to_uppercase()),
+            format!("sha512:{sha512}"),
+            format!("blake3:{blake3}"),
+            format!("BLAKE3:{blake3}"),
+        ] {
+            let manifest = with_hash(hash);
+            assert!(manifest.validate().is_empty());
+            let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+            assert!(plugin.call("count_vowels", "abc").is_ok());
+        }
+
+        // The digest is checked using the algorithm from the prefix
+        let manifest = with_hash(format!("sha512:{}", hex(&sha2::Sha512::digest(b"other"))));
+        assert!(Plugin::new_with_manifest(&context, &manifest, false).is_err());
+        assert_eq!(context.error_code(), ErrorCode::HashMismatch);
+
+        for hash in [format!("md5:{sha256}"), format!("sha512:{sha256}")] {
+            let manifest = with_hash(hash);
+            assert_eq!(
+                manifest.validate()[0].kind,
+                manifest::DiagnosticKind::InvalidHash
+            );
+            assert!(Plugin::new_with_manifest(&context, &manifest, false).is_err());
+            assert_eq!(context.error_code(), ErrorCode::ManifestInvalid);
+        }
+    }
+
+    #[test]
+    fn test_download_cache() {
+        use sha2::Digest;
+        use std::sync::atomic::Ordering;
+
+        let (port, requests) = serve(WASM);
+        let dir = std::env::temp_dir().join(format!("extism-downloads-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let context = Context::new();
+        context.set_download_cache(Some(&dir), None).unwrap();
+
+        let hash: String = sha2::Sha256::digest(WASM)
+            .iter()
+            .map(|x| format!("{x:02x}"))
+            .collect();
+        let url = |path: &str| {
+            manifest::Wasm::url(manifest::HttpRequest::new(format!(
+                "http://127.0.0.1:{port}/{path}"
+            )))
+        };
+        let mut hashed = url("hashed.wasm");
+        hashed.meta_mut().hash = Some(hash.clone());
+        let hashed = Manifest::new([hashed]);
+        let unhashed = Manifest::new([url("unhashed.wasm")]);
+
+        // Modules with a hash are only downloaded once
+        for _ in 0..2 {
+            Plugin::new_with_manifest(&context, &hashed, false).unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Modules without a hash are only cached when enabled by the host
+        for _ in 0..2 {
+            Plugin::new_with_manifest(&context, &unhashed, false).unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        context.set_download_cache_unhashed(true);
+        for _ in 0..2 {
+            Plugin::new_with_manifest(&context, &unhashed, false).unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // Requests with different headers don't share an entry, and requests with secrets are
+        // never cached without a hash
+        let header = |k: &str, v: &str| {
+            Manifest::new([manifest::Wasm::url(
+                manifest::HttpRequest::new(format!("http://127.0.0.1:{port}/unhashed.wasm"))
+                    .with_header(k, v),
+            )])
+        };
+        for _ in 0..2 {
+            Plugin::new_with_manifest(&context, &header("Accept", "application/wasm"), false)
+                .unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+        for _ in 0..2 {
+            Plugin::new_with_manifest(&context, &header("Authorization", "Bearer x"), false)
+                .unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 7);
+
+        let entries = context.download_cache_entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|x| x.size == WASM.len() as u64));
+        assert!(entries.iter().any(|x| x.key == format!("sha256:{hash}")));
+        assert!(entries.iter().any(|x| x.key.starts_with("url:")));
+
+        // Temporary files are renamed once they've been written
+        for file in std::fs::read_dir(&dir).unwrap() {
+            let name = file.unwrap().file_name();
+            assert!(name.to_string_lossy().ends_with(".wasm"));
+        }
+
+        // Set the modification times explicitly, since the file system may not be precise enough
+        // to order modules that were used within the same second
+        let set_last_used = |path: &std::path::Path, secs: u64| {
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(time).unwrap();
+        };
+        for (i, entry) in context.download_cache_entries().unwrap().iter().enumerate() {
+            set_last_used(&entry.path, 1_000_000 + i as u64);
+        }
+
+        // Loading a module marks it as recently used, so the other modules are evicted first
+        Plugin::new_with_manifest(&context, &hashed, false).unwrap();
+        context
+            .set_download_cache(Some(&dir), Some(WASM.len() as u64))
+            .unwrap();
+        assert_eq!(context.prune_download_cache().unwrap(), 2);
+        let entries = context.download_cache_entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, format!("sha256:{hash}"));
+        set_last_used(&entries[0].path, 1_000_000);
+
+        // Adding a module evicts the least recently used module when the cache is full
+        Plugin::new_with_manifest(&context, &unhashed, false).unwrap();
+        let entries = context.download_cache_entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].key.starts_with("url:"));
+
+        assert_eq!(context.clear_download_cache().unwrap(), 1);
+        assert!(context.download_cache_entries().unwrap().is_empty());
+
+        // Disabling the cache downloads the module every time
+        context.set_download_cache(None, None).unwrap();
+        let count = requests.load(Ordering::SeqCst);
+        for _ in 0..2 {
+            Plugin::new_with_manifest(&context, &hashed, false).unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), count + 2);
+        assert!(context.download_cache_entries().unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_retries_and_credentials() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let env = format!("EXTISM_TEST_TOKEN_{}", std::process::id());
+        std::env::set_var(&env, "env-secret");
+
+        // The first two requests for `/wasm` fail with a 503
+        let attempts = AtomicUsize::new(0);
+        let (port, requests) = serve_with(move |req| {
+            let req = req.to_ascii_lowercase();
+            if req.starts_with("get /missing") {
+                return (404, b"");
+            }
+            if req.starts_with("get /unavailable") {
+                return (503, b"");
+            }
+            if req.starts_with("get /slow") {
+                std::thread::sleep(std::time::Duration::from_secs(2));
+                return (200, WASM);
+            }
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                return (503, b"");
+            }
+            if req.contains("authorization: bearer cred-secret")
+                && req.contains("x-token: env-secret")
+            {
+                (200, WASM)
+            } else {
+                (401, b"")
+            }
+        });
+
+        let context = Context::new();
+        context.set_download_cache(None, None).unwrap();
+        let backoff = std::time::Duration::from_millis(10);
+        let manifest = |path: &str, retries: u32| {
+            Manifest::new([manifest::Wasm::url(
+                manifest::HttpRequest::new(format!("http://127.0.0.1:{port}{path}"))
+                    .with_header("Authorization", "Bearer ${credential:registry}")
+                    .with_header("X-Token", format!("${{env:{env}}}"))
+                    .with_timeout(std::time::Duration::from_millis(500))
+                    .with_retries(retries, backoff),
+            )])
+        };
+
+        // Credentials are required before the module is downloaded
+        let err = Plugin::new_with_manifest(&context, &manifest("/wasm", 2), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("credential registry"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        // The provider can use the context while a plugin is being created
+        let ctx = std::sync::Arc::downgrade(&context.0);
+        context.set_credential_provider(move |name, url| {
+            assert!(url.starts_with(&format!("http://127.0.0.1:{port}/")));
+            let ctx = ctx.upgrade()?;
+            let id = ctx.new_plugin(WASM, false);
+            assert!(id >= 0);
+            ctx.remove(id);
+            (name == "registry").then(|| "cred-secret".to_string())
+        });
+
+        // Environment variables can only be used once they're allowed by the host
+        let err = Plugin::new_with_manifest(&context, &manifest("/wasm", 2), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("isn't allowed"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        context.allow_env_var(&env).unwrap();
+        Plugin::new_with_manifest(&context, &manifest("/wasm", 2), false).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // Errors that aren't temporary aren't retried
+        let err = Plugin::new_with_manifest(&context, &manifest("/missing", 2), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("404"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        let err = Plugin::new_with_manifest(&context, &manifest("/unavailable", 1), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("503"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 6);
+
+        let start = std::time::Instant::now();
+        assert!(Plugin::new_with_manifest(&context, &manifest("/slow", 0), false).is_err());
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+        // Invalid URLs aren't retried and the number of retries is limited
+        let invalid = Manifest::new([manifest::Wasm::url(
+            manifest::HttpRequest::new(format!("http://127.0.0.1:{port}:1/wasm"))
+                .with_retries(3, std::time::Duration::from_secs(1)),
+        )]);
+        let start = std::time::Instant::now();
+        assert!(Plugin::new_with_manifest(&context, &invalid, false).is_err());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        let err = Plugin::new_with_manifest(&context, &manifest("/wasm", 11), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("the maximum is 10"), "{err}");
+
+        context.remove_credential_provider();
+        assert!(context.remove_allowed_env_var(&env));
+        std::env::remove_var(&env);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timer_idle_cpu() {
+        // Get the CPU time used by the timer thread, in clock ticks
+        fn timer_cpu_time() -> u64 {
+            for task in std::fs::read_dir("/proc/self/task").unwrap() {
+                let path = task.unwrap().path();
+                let comm = std::fs::read_to_string(path.join("comm")).unwrap_or_default();
+                if comm.trim() != "extism-timer" {
+                    continue;
+                }
+
+                let stat = std::fs::read_to_string(path.join("stat")).unwrap();
+                let fields: Vec<&str> = stat
+                    .rsplit(')')
+                    .next()
+                    .unwrap()
+                    .split_whitespace()
+                    .collect();
+                let utime: u64 = fields[11].parse().unwrap();
+                let stime: u64 = fields[12].parse().unwrap();
+                return utime + stime;
+            }
+            panic!("timer thread not found");
+        }
+
+        const SLEEP: &[u8] = b"(module
+            (import \"env\" \"host_sleep\" (func $sleep))
+            (func (export \"run\") call $sleep)
+        )";
+
+        let f = Function::new("host_sleep", [], [], |_caller, _params, _results| {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            Ok(())
+        });
+
+        let context = Context::new();
+        let manifest = Manifest::new([manifest::Wasm::data(SLEEP)]);
+        let mut plugin =
+            Plugin::new_with_manifest_and_functions(&context, &manifest, [f], false).unwrap();
+
+        // The timer has an active deadline for the whole call, but should stay asleep
+        let before = timer_cpu_time();
+        plugin.call("run", "").unwrap();
+        let used = timer_cpu_time() - before;
+        assert!(used <= 5, "timer thread used {used} ticks while idle");
+    }
+
+    #[test]
+    fn test_current_plugin() {
+        // Outputs the block returned by `host_greet`
+        const GREET: &[u8] = b"(module
+            (import \"env\" \"extism_length\" (func $length (param i64) (result i64)))
+            (import \"env\" \"extism_output_set\" (func $output_set (param i64 i64)))
+            (import \"env\" \"host_greet\" (func $greet (result i64)))
+            (func (export \"run\") (result i32) (local $p i64)
+                (local.set $p (call $greet))
+                (call $output_set (local.get $p) (call $length (local.get $p)))
+                (i32.const 0))
+        )";
+
+        let f = Function::new(
+            "host_greet",
+            [],
+            [ValType::I64],
+            |mut caller, _params, results| {
+                let mut plugin = CurrentPlugin::new(&mut caller);
+                let name = std::str::from_utf8(plugin.input())?.to_string();
+
+                let tmp = plugin.memory_alloc_bytes(&name)?;
+                assert_eq!(plugin.memory_str(tmp.offset)?, name);
+                plugin.memory_free(tmp.offset)?;
+                assert_eq!(plugin.memory_length(tmp.offset), None);
+
+                let block = plugin.memory_alloc_bytes(format!("Hello, {name}"))?;
+                plugin.memory_bytes_mut(block)?[0] = b'h';
+                assert!(plugin.memory_bytes((block.offset, usize::MAX)).is_err());
+                results[0] = Val::I64(block.offset as i64);
+                Ok(())
+            },
+        );
+
+        let context = Context::new();
+        let manifest = Manifest::new([manifest::Wasm::data(GREET)]);
+        let mut plugin =
+            Plugin::new_with_manifest_and_functions(&context, &manifest, [f], false).unwrap();
+        assert_eq!(plugin.call("run", "world").unwrap(), b"hello, world");
+    }
+
+    #[test]
+    fn test_error_code() {
+        const FAIL: &[u8] = b"(module
+            (import \"env\" \"host_fail\" (func $fail))
+            (func (export \"run\") (result i32)
+                (call $fail)
+                (i32.const 0))
+            (func (export \"trap\") (result i32)
+                unreachable)
+        )";
+
+        let context = Context::new();
+        assert!(Plugin::new(&context, b"{", false).is_err());
+        assert_eq!(context.error_code(), ErrorCode::ManifestInvalid);
+
+        let manifest = Manifest::new([manifest::Wasm::Data {
+            data: WASM.to_vec(),
+            meta: manifest::WasmMetadata {
+                hash: Some("0".repeat(64)),
+                ..Default::default()
+            },
+        }]);
+        assert!(Plugin::new_with_manifest(&context, &manifest, false).is_err());
+        assert_eq!(context.error_code(), ErrorCode::HashMismatch);
+
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+        assert!(plugin.call("missing", "").is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::FunctionNotFound);
+        plugin.call("count_vowels", "abc").unwrap();
+        assert_eq!(plugin.error_code(), ErrorCode::None);
+
+        let f = Function::new("host_fail", [], [], |_caller, _params, _results| {
+            Err(extism_runtime::Error::msg("failed"))
+        });
+        let manifest = Manifest::new([manifest::Wasm::data(FAIL)]);
+        let mut plugin =
+            Plugin::new_with_manifest_and_functions(&context, &manifest, [f], false).unwrap();
+        assert!(plugin.call("run", "").is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::HostFunctionError);
+        assert!(plugin.call("trap", "").is_err());
+        assert_eq!(plugin.error_code(), ErrorCode::Trap);
+
+        unsafe { bindings::extism_plugin_output_length(context.as_ptr(), 1000) };
+        assert_eq!(context.error_code(), ErrorCode::PluginNotFound);
+    }
+
+    #[test]
+    fn test_info() {
+        const LIB: &[u8] = b"(module
+            (func (export \"add\") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))))";
+        const MAIN: &[u8] = b"(module
+            (import \"lib\" \"add\" (func (param i32 i32) (result i32)))
+            (import \"env\" \"extism_alloc\" (func (param i64) (result i64)))
+            (import \"env\" \"host_log\" (func (param i64)))
+            (memory (export \"memory\") 1)
+            (func (export \"run\") (result i32) (i32.const 0)))";
+
+        let module = |name: &str, data: &[u8]| manifest::Wasm::Data {
+            data: data.to_vec(),
+            meta: manifest::WasmMetadata {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+        };
+        let f = Function::new("host_log", [ValType::I64], [], |_, _, _| Ok(()));
+
+        let context = Context::new();
+        let manifest = Manifest::new([module("lib", LIB), module("main", MAIN)]);
+        let plugin =
+            Plugin::new_with_manifest_and_functions(&context, &manifest, [f], false).unwrap();
+        let info = plugin.info().unwrap();
+
+        assert_eq!(
+            info.exports,
+            [
+                Export {
+                    name: "memory".to_string(),
+                    kind: ExternKind::Memory,
+                    params: vec![],
+                    results: vec![],
+                },
+                Export {
+                    name: "run".to_string(),
+                    kind: ExternKind::Function,
+                    params: vec![],
+                    results: vec![ValType::I32],
+                },
+            ]
+        );
+
+        let providers: Vec<_> = info
+            .imports
+            .iter()
+            .map(|i| (i.required_by.as_str(), i.name.as_str(), i.provider))
+            .collect();
+        assert_eq!(
+            providers,
+            [
+                ("main", "add", ImportProvider::Module),
+                ("main", "extism_alloc", ImportProvider::Builtin),
+                ("main", "host_log", ImportProvider::HostFunction),
+            ]
+        );
+        assert_eq!(info.imports[0].params, [ValType::I32, ValType::I32]);
+        assert_eq!(info.imports[2].params, [ValType::I64]);
+    }
+
+    #[test]
+    fn test_update_host_functions() {
+        // Outputs the value returned by `host_value`
+        const WASM_VALUE: &[u8] = b"(module
+            (import \"env\" \"extism_alloc\" (func $alloc (param i64) (result i64)))
+            (import \"env\" \"extism_store_u8\" (func $store_u8 (param i64 i32)))
+            (import \"env\" \"extism_output_set\" (func $output_set (param i64 i64)))
+            (import \"env\" \"host_value\" (func $value (result i32)))
+            (func (export \"run\") (result i32) (local $p i64)
+                (local.set $p (call $alloc (i64.const 1)))
+                (call $store_u8 (local.get $p) (call $value))
+                (call $output_set (local.get $p) (i64.const 1))
+                (i32.const 0)))";
+
+        let value = |x: i32| {
+            Function::new("host_value", [], [ValType::I32], move |_, _, results| {
+                results[0] = Val::I32(x);
+                Ok(())
+            })
+        };
+
+        let context = Context::new();
+        let mut plugin =
+            Plugin::new_with_functions(&context, WASM_VALUE, [value(1)], false).unwrap();
+        assert_eq!(plugin.call("run", "").unwrap(), [1]);
+
+        // The existing host functions are kept
+        plugin.update(WASM_VALUE, false).unwrap();
+        assert_eq!(plugin.call("run", "").unwrap(), [1]);
+
+        plugin
+            .update_with_functions(WASM_VALUE, [value(2)], false)
+            .unwrap();
+        assert_eq!(plugin.call("run", "").unwrap(), [2]);
+
+        // Updating without the required host function fails and leaves the plugin unchanged
+        assert!(plugin.update_with_functions(WASM_VALUE, [], false).is_err());
+        assert_eq!(plugin.call("run", "").unwrap(), [2]);
+    }
+
+    #[test]
+    fn test_c_host_function() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // Calls `host_fill` to fill a block with the user data byte, the output is the block
+        const WASM_FILL: &[u8] = br#"(module
+                (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+                (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+                (import "env" "host_fill" (func $fill (param i64 i64) (result i64)))
+                (func (export "run") (result i32) (local $p i64)
+                    (local.set $p (call $alloc (i64.const 3)))
+                    (call $output_set (local.get $p) (call $fill (local.get $p) (i64.const 3)))
+                    (i32.const 0)))
+        "#;
+
+        static FILL_BYTE: u8 = b'x';
+        static FREED: AtomicBool = AtomicBool::new(false);
+
+        extern "C" fn host_fill(
+            plugin: *mut extism_runtime::Internal,
+            inputs: *const bindings::ExtismVal,
+            n_inputs: u64,
+            outputs: *mut bindings::ExtismVal,
+            n_outputs: u64,
+            data: *mut std::ffi::c_void,
+        ) {
+            assert_eq!(n_inputs, 2);
+            assert_eq!(n_outputs, 1);
+            unsafe {
+                let inputs = std::slice::from_raw_parts(inputs, 2);
+                let (offset, length) = (inputs[0].v.i64 as usize, inputs[1].v.i64 as usize);
+                let mem = bindings::extism_current_plugin_memory(plugin);
+                std::ptr::write_bytes(mem.add(offset), *(data as *const u8), length);
+                (*outputs).v.i64 = length as i64;
+            }
+        }
+
+        extern "C" fn free_user_data(_: *mut std::ffi::c_void) {
+            FREED.store(true, Ordering::SeqCst);
+        }
+
+        let context = Context::new();
+        unsafe {
+            let f = bindings::extism_function_new(
+                c"host_fill".as_ptr(),
+                [ValType::I64, ValType::I64].as_ptr(),
+                2,
+                [ValType::I64].as_ptr(),
+                1,
+                host_fill,
+                &FILL_BYTE as *const u8 as *mut _,
+                Some(free_user_data),
+            );
+            let id = bindings::extism_plugin_new_with_functions(
+                context.as_ptr(),
+                WASM_FILL.as_ptr(),
+                WASM_FILL.len() as u64,
+                [f as *const _].as_ptr(),
+                1,
+                false,
+            );
+            bindings::extism_function_free(f);
+            assert!(id >= 0);
+
+            let mut plugin = Plugin::from_id(id, &context);
+            assert_eq!(plugin.call("run", "").unwrap(), b"xxx");
+            assert!(!FREED.load(Ordering::SeqCst));
+        }
+
+        assert!(FREED.load(Ordering::SeqCst));
+
+        // Host functions can fail the call
+        const WASM_FAIL: &[u8] = br#"(module
+                (import "env" "host_fail" (func $fail))
+                (func (export "run") (result i32) (call $fail) (i32.const 0)))
+        "#;
+
+        extern "C" fn host_fail(
+            plugin: *mut extism_runtime::Internal,
+            _inputs: *const bindings::ExtismVal,
+            _n_inputs: u64,
+            _outputs: *mut bindings::ExtismVal,
+            _n_outputs: u64,
+            _data: *mut std::ffi::c_void,
+        ) {
+            unsafe { bindings::extism_current_plugin_set_error(plugin, c"not allowed".as_ptr()) }
+        }
+
+        unsafe {
+            let f = bindings::extism_function_new(
+                c"host_fail".as_ptr(),
+                std::ptr::null(),
+                0,
+                std::ptr::null(),
+                0,
+                host_fail,
+                std::ptr::null_mut(),
+                None,
+            );
+            let id = bindings::extism_plugin_new_with_functions(
+                context.as_ptr(),
+                WASM_FAIL.as_ptr(),
+                WASM_FAIL.len() as u64,
+                [f as *const _].as_ptr(),
+                1,
+                false,
+            );
+            bindings::extism_function_free(f);
+            assert!(id >= 0);
+
+            let mut plugin = Plugin::from_id(id, &context);
+            match plugin.call("run", "") {
+                Err(Error::Message(msg)) => assert!(msg.contains("not allowed"), "{msg}"),
+                _ => panic!("expected host function to fail"),
+            }
+            assert_eq!(plugin.error_code(), ErrorCode::HostFunctionError);
+        }
+    }
+
+    #[test]
+    fn test_pool() {
+        let context = Context::new();
+        let pool = PluginPool::new(&context, WASM, false, 1, 4).unwrap();
+        assert_eq!(pool.size(), 1);
+        assert_eq!(pool.idle(), 1);
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        let output = pool.call("count_vowels", "this is a test").unwrap();
+                        assert_eq!(output, b"{\"count\": 4}");
+                    }
+                });
+            }
+        });
+
+        assert!(pool.size() <= 4);
+        assert_eq!(pool.idle(), pool.size());
+
+        let pool = PluginPool::new(&context, WASM, false, 2, 4).unwrap();
+        assert!(pool.call("missing", "").is_err());
+        assert_eq!(pool.idle(), 2);
+
+        assert!(PluginPool::new(&context, WASM, false, 2, 1).is_err());
+
+        // Timeouts that can't be represented as a deadline wait forever
+        let pool = extism_runtime::PluginPool::new(&context.0, WASM, [], false, 1, 1).unwrap();
+        let plugin = pool
+            .acquire(Some(std::time::Duration::from_millis(u64::MAX)))
+            .unwrap();
+        pool.release(plugin);
+    }
+
+    #[test]
+    fn test_pool_isolation() {
+        // Returns a non-zero value if a previous call changed the guest's static memory or globals
+        const STATE: &[u8] = br#"(module
+            (memory (export "memory") 1)
+            (global $calls (mut i32) (i32.const 0))
+            (func (export "run") (result i32)
+                (local $n i32)
+                (local.set $n (i32.add (i32.load (i32.const 0)) (global.get $calls)))
+                (i32.store (i32.const 0) (i32.const 1))
+                (global.set $calls (i32.const 1))
+                (local.get $n)))"#;
+
+        let context = Context::new();
+        let pool = PluginPool::new(&context, STATE, false, 1, 1).unwrap();
+        for _ in 0..3 {
+            pool.call("run", "").unwrap();
+        }
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pool_async() {
+        let context = Context::new_async();
+        let pool = PluginPool::new(&context, WASM, false, 1, 1).unwrap();
+        let (x, y) = tokio::join!(
+            pool.call_async("count_vowels", "aaa"),
+            pool.call_async("count_vowels", "this is a test")
+        );
+        assert_eq!(x.unwrap(), b"{\"count\": 3}");
+        assert_eq!(y.unwrap(), b"{\"count\": 4}");
+
+        // Waiting for an instance yields instead of blocking the thread, this test uses a single
+        // threaded runtime so the instance can only be released while the other task waits
+        let pool = extism_runtime::PluginPool::new(&context.0, WASM, [], false, 1, 1).unwrap();
+        let plugin = pool.get(None).unwrap();
+        assert!(pool.try_acquire().unwrap().is_none());
+        let (waiting, _) = tokio::join!(pool.get_async(), async {
+            tokio::task::yield_now().await;
+            drop(plugin);
+        });
+        assert!(waiting.is_ok());
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn test_shared_context() {
+        const LOOP: &[u8] = b"(module (func (export \"run\") (loop br 0)))";
+
+        let context = Context::new();
+        let mut shared = Plugin::new(&context, WASM, false).unwrap();
+        let ctx = context.as_ptr() as usize;
+        let shared_id = shared.as_i32();
+
+        std::thread::scope(|s| {
+            // Each thread creates, calls and frees its own plugins
+            for i in 0..8 {
+                let context = &context;
+                s.spawn(move || {
+                    for j in 0..5 {
+                        let mut plugin = Plugin::new(context, WASM, false).unwrap();
+                        let input = "a".repeat(i * 5 + j);
+                        let output = plugin.call("count_vowels", &input).unwrap();
+                        let expected = format!("{{\"count\": {}}}", i * 5 + j);
+                        assert_eq!(output, expected.as_bytes());
+                    }
+                });
+            }
+
+            // Calls to the same plugin from multiple threads are serialized
+            for _ in 0..4 {
+                s.spawn(move || {
+                    let name = std::ffi::CString::new("count_vowels").unwrap();
+                    for _ in 0..20 {
+                        let rc = unsafe {
+                            bindings::extism_plugin_call(
+                                ctx as *mut _,
+                                shared_id,
+                                name.as_ptr(),
+                                b"aaa".as_ptr(),
+                                3,
+                            )
+                        };
+                        assert_eq!(rc, 0);
+                    }
+                });
+            }
+
+            // A long running call doesn't block other plugins in the same context
+            s.spawn(|| {
+                let manifest = Manifest::new([manifest::Wasm::data(LOOP)])
+                    .with_timeout(std::time::Duration::from_secs(2));
+                let mut plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+                let start = Instant::now();
+                assert!(plugin.call("run", "").is_err());
+                assert!(start.elapsed() >= std::time::Duration::from_secs(2));
+            });
+            s.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                let start = Instant::now();
+                let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+                plugin.call("count_vowels", "aaa").unwrap();
+                assert!(start.elapsed() < std::time::Duration::from_secs(1));
+            });
+        });
+
+        // Context errors are stored per thread
+        assert!(Plugin::new(&context, b"{", false).is_err());
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(context.error_code(), ErrorCode::None);
+                // Clears the context error for this thread only
+                shared.has_function("count_vowels");
+            });
+        });
+        assert_eq!(context.error_code(), ErrorCode::ManifestInvalid);
+        assert_eq!(
+            shared.call("count_vowels", "aaa").unwrap(),
+            b"{\"count\": 3}"
+        );
+    }
+
     #[test]
     fn test_threads() {
         use std::io::Write;
@@ -145,7 +1701,7 @@ mod tests {
             let context = Context::new();
             let mut plugin = Plugin::new(&context, WASM, false).unwrap();
             let output = plugin.call("count_vowels", "this is a test").unwrap();
-            std::io::stdout().write_all(output).unwrap();
+            std::io::stdout().write_all(&output).unwrap();
         });
 
         std::thread::spawn(|| {
@@ -154,12 +1710,12 @@ mod tests {
                 .build(&context)
                 .unwrap();
             let output = plugin.call("count_vowels", "this is a test aaa").unwrap();
-            std::io::stdout().write_all(output).unwrap();
+            std::io::stdout().write_all(&output).unwrap();
         });
 
         let context = Context::new();
         let mut plugin = Plugin::new(&context, WASM, false).unwrap();
         let output = plugin.call("count_vowels", "abc123").unwrap();
-        std::io::stdout().write_all(output).unwrap();
+        std::io::stdout().write_all(&output).unwrap();
     }
 }
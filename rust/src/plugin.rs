@@ -41,11 +41,11 @@ impl<'a> Plugin<'a> {
     }
 
     /// Create a new plugin from a WASM module
-    pub fn new(ctx: &'a Context, data: impl AsRef<[u8]>, wasi: bool) -> Result<Plugin, Error> {
-        let plugin = ctx.lock().new_plugin(data, wasi);
+    pub fn new(ctx: &'a Context, data: impl AsRef<[u8]>, wasi: bool) -> Result<Plugin<'a>, Error> {
+        let plugin = ctx.0.new_plugin(data, wasi);
 
         if plugin < 0 {
-            let err = unsafe { bindings::extism_error(&mut *ctx.lock(), -1) };
+            let err = unsafe { bindings::extism_error(ctx.as_ptr(), -1) };
             let buf = unsafe { std::ffi::CStr::from_ptr(err) };
             let buf = buf.to_str().unwrap().to_string();
             return Err(Error::UnableToLoadPlugin(buf));
@@ -63,11 +63,11 @@ impl<'a> Plugin<'a> {
         data: impl AsRef<[u8]>,
         imports: impl IntoIterator<Item = extism_runtime::Function>,
         wasi: bool,
-    ) -> Result<Plugin, Error> {
-        let plugin = ctx.lock().new_plugin_with_functions(data, imports, wasi);
+    ) -> Result<Plugin<'a>, Error> {
+        let plugin = ctx.0.new_plugin_with_functions(data, imports, wasi);
 
         if plugin < 0 {
-            let err = unsafe { bindings::extism_error(&mut *ctx.lock(), -1) };
+            let err = unsafe { bindings::extism_error(ctx.as_ptr(), -1) };
             let buf = unsafe { std::ffi::CStr::from_ptr(err) };
             let buf = buf.to_str().unwrap().to_string();
             return Err(Error::UnableToLoadPlugin(buf));
@@ -79,11 +79,12 @@ impl<'a> Plugin<'a> {
         })
     }
 
-    /// Update a plugin with the given WASM module
+    /// Update a plugin with the given WASM module, the host functions the plugin was created
+    /// with are kept
     pub fn update(&mut self, data: impl AsRef<[u8]>, wasi: bool) -> Result<(), Error> {
         let b = unsafe {
             bindings::extism_plugin_update(
-                &mut *self.context.lock(),
+                self.context.as_ptr(),
                 self.id,
                 data.as_ref().as_ptr(),
                 data.as_ref().len() as u64,
@@ -94,7 +95,33 @@ impl<'a> Plugin<'a> {
             return Ok(());
         }
 
-        let err = unsafe { bindings::extism_error(&mut *self.context.lock(), -1) };
+        let err = unsafe { bindings::extism_error(self.context.as_ptr(), -1) };
+        if !err.is_null() {
+            let s = unsafe { std::ffi::CStr::from_ptr(err) };
+            return Err(Error::Message(s.to_str().unwrap().to_string()));
+        }
+
+        Err(Error::Message("extism_plugin_update failed".to_string()))
+    }
+
+    /// Update a plugin with the given WASM module, replacing the host functions it was created
+    /// with
+    pub fn update_with_functions(
+        &mut self,
+        data: impl AsRef<[u8]>,
+        imports: impl IntoIterator<Item = Function>,
+        wasi: bool,
+    ) -> Result<(), Error> {
+        let imports = imports.into_iter().collect();
+        if self
+            .context
+            .0
+            .update_plugin(self.id, data, Some(imports), wasi)
+        {
+            return Ok(());
+        }
+
+        let err = unsafe { bindings::extism_error(self.context.as_ptr(), -1) };
         if !err.is_null() {
             let s = unsafe { std::ffi::CStr::from_ptr(err) };
             return Err(Error::Message(s.to_str().unwrap().to_string()));
@@ -114,7 +141,7 @@ impl<'a> Plugin<'a> {
         let encoded = serde_json::to_vec(config)?;
         unsafe {
             bindings::extism_plugin_config(
-                &mut *self.context.lock(),
+                self.context.as_ptr(),
                 self.id,
                 encoded.as_ptr() as *const _,
                 encoded.len() as u64,
@@ -134,47 +161,273 @@ impl<'a> Plugin<'a> {
         let name = std::ffi::CString::new(name.as_ref()).expect("Invalid function name");
         unsafe {
             bindings::extism_plugin_function_exists(
-                &mut *self.context.lock(),
+                self.context.as_ptr(),
                 self.id,
                 name.as_ptr() as *const _,
             )
         }
     }
 
-    /// Call a function with the given input
-    pub fn call(&mut self, name: impl AsRef<str>, input: impl AsRef<[u8]>) -> Result<&[u8], Error> {
-        let name = std::ffi::CString::new(name.as_ref()).expect("Invalid function name");
-        let rc = unsafe {
-            bindings::extism_plugin_call(
-                &mut *self.context.lock(),
+    /// List the exports of the plugin's main module and the imports required by all of its
+    /// modules
+    pub fn info(&self) -> Result<PluginInfo, Error> {
+        let ptr = unsafe { bindings::extism_plugin_info(self.context.as_ptr(), self.id) };
+        if ptr.is_null() {
+            return Err(self.error("extism_plugin_info failed"));
+        }
+
+        let s = unsafe { std::ffi::CStr::from_ptr(ptr) };
+        let info = serde_json::from_slice(s.to_bytes());
+        unsafe { bindings::extism_string_free(ptr) };
+        Ok(info?)
+    }
+
+    /// Get the memory usage of the plugin, the extism memory reflects the blocks allocated by
+    /// the last call
+    pub fn memory_stats(&self) -> Result<MemoryStats, Error> {
+        let ptr = unsafe { bindings::extism_plugin_memory_stats(self.context.as_ptr(), self.id) };
+        if ptr.is_null() {
+            return Err(self.error("extism_plugin_memory_stats failed"));
+        }
+
+        let s = unsafe { std::ffi::CStr::from_ptr(ptr) };
+        let stats = serde_json::from_slice(s.to_bytes());
+        unsafe { bindings::extism_string_free(ptr) };
+        Ok(stats?)
+    }
+
+    /// Get a handle that can be used to cancel running calls from another thread
+    pub fn cancel_handle(&self) -> Result<CancelHandle, Error> {
+        let handle =
+            unsafe { bindings::extism_plugin_cancel_handle(self.context.as_ptr(), self.id) };
+        if handle.is_null() {
+            return Err(Error::Message(format!(
+                "Unable to get cancel handle for plugin {}",
+                self.id
+            )));
+        }
+
+        let cancel_handle = unsafe { (*handle).clone() };
+        unsafe { bindings::extism_cancel_handle_free(handle) };
+        Ok(cancel_handle)
+    }
+
+    /// Get the amount of fuel consumed by the last call, this is always `0` if the context wasn't
+    /// created using `Context::new_with_fuel` or `Context::new_async`
+    pub fn fuel_consumed(&self) -> u64 {
+        unsafe { bindings::extism_plugin_fuel_consumed(self.context.as_ptr(), self.id) }
+    }
+
+    /// Capture the plugin's state so it can be restored later using `Plugin::restore`
+    pub fn snapshot(&mut self) -> Result<Vec<u8>, Error> {
+        let mut length = 0;
+        let ptr = unsafe {
+            bindings::extism_plugin_snapshot(self.context.as_ptr(), self.id, &mut length)
+        };
+
+        if ptr.is_null() {
+            return Err(self.error("extism_plugin_snapshot failed"));
+        }
+
+        let data = unsafe { std::slice::from_raw_parts(ptr, length as usize).to_vec() };
+        unsafe { bindings::extism_snapshot_free(ptr, length) };
+        Ok(data)
+    }
+
+    /// Restore a snapshot created using `Plugin::snapshot`, the plugin must have been created
+    /// from the same module
+    pub fn restore(&mut self, snapshot: impl AsRef<[u8]>) -> Result<(), Error> {
+        let snapshot = snapshot.as_ref();
+        let ok = unsafe {
+            bindings::extism_plugin_restore(
+                self.context.as_ptr(),
                 self.id,
-                name.as_ptr() as *const _,
-                input.as_ref().as_ptr() as *const _,
-                input.as_ref().len() as u64,
+                snapshot.as_ptr(),
+                snapshot.len() as u64,
             )
         };
 
-        if rc != 0 {
-            let err = unsafe { bindings::extism_error(&mut *self.context.lock(), self.id) };
-            if !err.is_null() {
-                let s = unsafe { std::ffi::CStr::from_ptr(err) };
-                return Err(Error::Message(s.to_str().unwrap().to_string()));
-            }
+        if !ok {
+            return Err(self.error("extism_plugin_restore failed"));
+        }
+
+        Ok(())
+    }
 
-            return Err(Error::Message("extism_call failed".to_string()));
+    /// Get the names of all vars set for this plugin
+    pub fn vars(&self) -> Result<Vec<String>, Error> {
+        let ptr = unsafe { bindings::extism_plugin_vars(self.context.as_ptr(), self.id) };
+        if ptr.is_null() {
+            return Err(self.error("extism_plugin_vars failed"));
         }
 
-        let out_len =
-            unsafe { bindings::extism_plugin_output_length(&mut *self.context.lock(), self.id) };
-        unsafe {
-            let ptr = bindings::extism_plugin_output_data(&mut *self.context.lock(), self.id);
-            Ok(std::slice::from_raw_parts(ptr, out_len as usize))
+        let s = unsafe { std::ffi::CStr::from_ptr(ptr) };
+        let keys = serde_json::from_slice(s.to_bytes());
+        unsafe { bindings::extism_string_free(ptr) };
+        Ok(keys?)
+    }
+
+    /// Get the value of a var, `None` is returned if the var isn't set
+    pub fn var(&self, key: impl AsRef<str>) -> Result<Option<Vec<u8>>, Error> {
+        // `extism_plugin_var_get` returns NULL both when the var isn't set and on error, and the
+        // value is only valid while the plugin is locked, so the plugin is used directly
+        var_key(&key)?;
+        let plugin = match extism_runtime::PluginRef::new(&self.context.0, self.id, true) {
+            Some(p) => p,
+            None => return Err(self.error("Unable to load plugin")),
+        };
+
+        Ok(plugin.as_ref().vars.get(key.as_ref()).cloned())
+    }
+
+    /// Set the value of a var, the var will be visible to the plugin during the next call
+    pub fn set_var(&mut self, key: impl AsRef<str>, value: impl AsRef<[u8]>) -> Result<(), Error> {
+        let key = var_key(key)?;
+        let value = value.as_ref();
+        let ok = unsafe {
+            bindings::extism_plugin_var_set(
+                self.context.as_ptr(),
+                self.id,
+                key.as_ptr(),
+                value.as_ptr(),
+                value.len() as u64,
+            )
+        };
+
+        if !ok {
+            return Err(self.error("extism_plugin_var_set failed"));
+        }
+
+        Ok(())
+    }
+
+    /// Remove a var, returns true if the var was set
+    pub fn remove_var(&mut self, key: impl AsRef<str>) -> Result<bool, Error> {
+        let key = var_key(key)?;
+        Ok(unsafe {
+            bindings::extism_plugin_var_delete(self.context.as_ptr(), self.id, key.as_ptr())
+        })
+    }
+
+    /// Remove all vars
+    pub fn clear_vars(&mut self) -> Result<(), Error> {
+        let ok = unsafe { bindings::extism_plugin_vars_clear(self.context.as_ptr(), self.id) };
+        if !ok {
+            return Err(self.error("extism_plugin_vars_clear failed"));
+        }
+
+        Ok(())
+    }
+
+    /// Get the code for the last error set for this plugin
+    pub fn error_code(&self) -> ErrorCode {
+        unsafe { bindings::extism_error_code(self.context.as_ptr(), self.id) }
+    }
+
+    /// Get the last error set for this plugin, falling back to `default` if there is none
+    fn error(&self, default: &str) -> Error {
+        let err = unsafe { bindings::extism_error(self.context.as_ptr(), self.id) };
+        if !err.is_null() {
+            let s = unsafe { std::ffi::CStr::from_ptr(err) };
+            return Error::Message(s.to_str().unwrap().to_string());
+        }
+
+        Error::Message(default.to_string())
+    }
+
+    /// Call a function with the given input
+    ///
+    /// This blocks the current thread until the call finishes, plugins from a context created
+    /// using `Context::new_async` should use `Plugin::call_async` from async code
+    pub fn call(
+        &mut self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+    ) -> Result<Vec<u8>, Error> {
+        self.call_locked(name.as_ref(), input.as_ref(), None)
+    }
+
+    /// Call a function with the given input, `timeout` overrides the timeout set in the
+    /// manifest for this call only
+    pub fn call_with_timeout(
+        &mut self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+        timeout: std::time::Duration,
+    ) -> Result<Vec<u8>, Error> {
+        let timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        self.call_locked(name.as_ref(), input.as_ref(), Some(timeout_ms))
+    }
+
+    /// Call a function and copy its output, the plugin is locked until the output has been
+    /// copied so it can't be replaced by a call from another thread
+    fn call_locked(
+        &mut self,
+        name: &str,
+        input: &[u8],
+        timeout_ms: Option<u64>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut plugin = match extism_runtime::PluginRef::new(&self.context.0, self.id, true) {
+            Some(p) => p,
+            None => return Err(self.error("Unable to load plugin")),
+        };
+        let plugin = plugin.as_mut();
+
+        let timeout_ms = timeout_ms.or(plugin.manifest.as_ref().timeout_ms);
+        let rc = plugin.call(name, input, timeout_ms).unwrap_or(-1);
+        if rc != 0 {
+            let err = plugin.last_error.borrow();
+            return match err.as_ref() {
+                Some(e) => Err(Error::Message(e.to_string_lossy().to_string())),
+                None => Err(Error::Message("extism_call failed".to_string())),
+            };
+        }
+
+        Ok(plugin.output().to_vec())
+    }
+
+    /// Call a function asynchronously with the given input, the plugin must have been created
+    /// using a context returned by `Context::new_async`
+    ///
+    /// Only this plugin is locked while the call is running, so other plugins in the same
+    /// context can be used while the call is running. The lock is held until the call finishes,
+    /// so this fails instead of waiting if the plugin is already being used by another thread or
+    /// task
+    pub async fn call_async(
+        &mut self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut plugin = match extism_runtime::PluginRef::try_new(&self.context.0, self.id, true) {
+            Some(p) => p,
+            None => return Err(self.context.last_error()),
+        };
+        let plugin = plugin.as_mut();
+
+        let rc = match plugin.call_async(name, input).await {
+            Ok(rc) => rc,
+            Err(e) => return Err(Error::Message(format!("{e:#}"))),
+        };
+
+        if rc != 0 {
+            let err = plugin.last_error.borrow();
+            return match err.as_ref() {
+                Some(e) => Err(Error::Message(e.to_string_lossy().to_string())),
+                None => Err(Error::Message("extism_call failed".to_string())),
+            };
         }
+
+        Ok(plugin.output().to_vec())
     }
 }
 
 impl<'a> Drop for Plugin<'a> {
     fn drop(&mut self) {
-        unsafe { bindings::extism_plugin_free(&mut *self.context.lock(), self.id) }
+        unsafe { bindings::extism_plugin_free(self.context.as_ptr(), self.id) }
     }
 }
+
+fn var_key(key: impl AsRef<str>) -> Result<std::ffi::CString, Error> {
+    std::ffi::CString::new(key.as_ref())
+        .map_err(|_| Error::Message(format!("Invalid var name: {:?}", key.as_ref())))
+}
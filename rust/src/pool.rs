@@ -0,0 +1,122 @@
+use crate::*;
+
+/// A pool of instances of the same plugin, unlike `Plugin` a pool can be called from multiple
+/// threads at once
+///
+/// Each call uses an idle instance from the pool, new instances are created as needed up to the
+/// maximum size of the pool. Instances that have been used are replaced with a new instance before
+/// the next call, so no state is shared between calls.
+pub struct PluginPool(extism_runtime::PluginPool);
+
+impl PluginPool {
+    /// Create a new pool from a WASM module, `size` instances are created up front and the pool
+    /// will grow to at most `max_size` instances
+    pub fn new(
+        ctx: &Context,
+        data: impl AsRef<[u8]>,
+        wasi: bool,
+        size: usize,
+        max_size: usize,
+    ) -> Result<PluginPool, Error> {
+        Self::new_with_functions(ctx, data, [], wasi, size, max_size)
+    }
+
+    /// Create a new pool from a WASM module with imported functions
+    pub fn new_with_functions(
+        ctx: &Context,
+        data: impl AsRef<[u8]>,
+        imports: impl IntoIterator<Item = extism_runtime::Function>,
+        wasi: bool,
+        size: usize,
+        max_size: usize,
+    ) -> Result<PluginPool, Error> {
+        match extism_runtime::PluginPool::new(&ctx.0, data, imports, wasi, size, max_size) {
+            Ok(pool) => Ok(PluginPool(pool)),
+            Err(e) => Err(Error::UnableToLoadPlugin(format!("{e:#}"))),
+        }
+    }
+
+    /// Create a new pool from the given manifest
+    pub fn new_with_manifest(
+        ctx: &Context,
+        manifest: &Manifest,
+        wasi: bool,
+        size: usize,
+        max_size: usize,
+    ) -> Result<PluginPool, Error> {
+        let data = serde_json::to_vec(manifest)?;
+        Self::new(ctx, data, wasi, size, max_size)
+    }
+
+    /// Call a function with the given input, waiting for an idle instance if all instances are
+    /// in use and the pool can't grow
+    pub fn call(&self, name: impl AsRef<str>, input: impl AsRef<[u8]>) -> Result<Vec<u8>, Error> {
+        let mut plugin = self.0.get(None)?;
+        let timeout_ms = plugin.manifest.as_ref().timeout_ms;
+        let res = plugin.call(name, input, timeout_ms);
+        output(&plugin, res)
+    }
+
+    /// Call a function with the given input, `timeout` overrides the timeout set in the
+    /// manifest for this call only
+    pub fn call_with_timeout(
+        &self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+        timeout: std::time::Duration,
+    ) -> Result<Vec<u8>, Error> {
+        let mut plugin = self.0.get(None)?;
+        let timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        let res = plugin.call(name, input, Some(timeout_ms));
+        output(&plugin, res)
+    }
+
+    /// Call a function asynchronously with the given input, the pool must have been created
+    /// using a context returned by `Context::new_async`. If all instances are in use and the
+    /// pool can't grow the current task yields until an instance is returned.
+    pub async fn call_async(
+        &self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut plugin = self.0.get_async().await?;
+        let res = plugin.call_async(name, input).await;
+        output(&plugin, res)
+    }
+
+    /// Total number of instances, including instances that are in use
+    pub fn size(&self) -> usize {
+        self.0.size()
+    }
+
+    /// Number of instances that aren't in use
+    pub fn idle(&self) -> usize {
+        self.0.idle()
+    }
+
+    /// Maximum number of instances
+    pub fn max_size(&self) -> usize {
+        self.0.max_size()
+    }
+}
+
+/// Get the output of a call made using a pooled instance
+fn output(
+    plugin: &extism_runtime::Plugin,
+    res: Result<i32, extism_runtime::Error>,
+) -> Result<Vec<u8>, Error> {
+    let rc = match res {
+        Ok(rc) => rc,
+        Err(e) => return Err(Error::Message(format!("{e:#}"))),
+    };
+
+    if rc != 0 {
+        let err = plugin.last_error.borrow();
+        return match err.as_ref() {
+            Some(e) => Err(Error::Message(e.to_string_lossy().to_string())),
+            None => Err(Error::Message("extism_call failed".to_string())),
+        };
+    }
+
+    Ok(plugin.output().to_vec())
+}
@@ -0,0 +1,54 @@
+// The logger is global, so tests that change it are kept in their own test binary instead of
+// the tests in `src/lib.rs`
+use extism::*;
+
+#[test]
+fn test_log_custom() {
+    type Message = (extism_runtime::LogLevel, String, String, i32);
+    static MESSAGES: std::sync::Mutex<Vec<Message>> = std::sync::Mutex::new(Vec::new());
+
+    extern "C" fn callback(
+        level: extism_runtime::LogLevel,
+        target: *const std::os::raw::c_char,
+        message: *const std::os::raw::c_char,
+        plugin: i32,
+        _user_data: *mut std::ffi::c_void,
+    ) {
+        let target = unsafe { std::ffi::CStr::from_ptr(target) };
+        let message = unsafe { std::ffi::CStr::from_ptr(message) };
+        MESSAGES.lock().unwrap().push((
+            level,
+            target.to_string_lossy().into_owned(),
+            message.to_string_lossy().into_owned(),
+            plugin,
+        ));
+    }
+
+    // Logs `hi` at the info level
+    const LOG: &[u8] = br#"(module
+        (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+        (import "env" "extism_store_u8" (func $store_u8 (param i64 i32)))
+        (import "env" "extism_log_info" (func $log_info (param i64)))
+        (memory (export "memory") 1)
+        (func (export "log") (result i32) (local $msg i64)
+            (local.set $msg (call $alloc (i64.const 2)))
+            (call $store_u8 (local.get $msg) (i32.const 104))
+            (call $store_u8 (i64.add (local.get $msg) (i64.const 1)) (i32.const 105))
+            (call $log_info (local.get $msg))
+            (i32.const 0)))"#;
+
+    let level = std::ffi::CString::new("info").unwrap();
+    assert!(unsafe { bindings::extism_log_custom(callback, level.as_ptr(), std::ptr::null_mut()) });
+
+    let context = Context::new();
+    let mut plugin = Plugin::new(&context, LOG, false).unwrap();
+    plugin.call("log", "").unwrap();
+
+    let messages = MESSAGES.lock().unwrap();
+    assert!(messages.contains(&(
+        extism_runtime::LogLevel::Info,
+        PLUGIN_LOG_TARGET.to_string(),
+        "hi".to_string(),
+        plugin.as_i32(),
+    )));
+}